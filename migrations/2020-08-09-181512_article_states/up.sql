@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE article_states (
+  article UUID REFERENCES articles(id) ON DELETE CASCADE NOT NULL,
+  username TEXT REFERENCES users(username) ON DELETE CASCADE NOT NULL,
+  read BOOLEAN NOT NULL,
+  starred BOOLEAN NOT NULL,
+  read_at TIMESTAMP,
+  PRIMARY KEY (article, username)
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources
+  DROP COLUMN etag,
+  DROP COLUMN last_modified;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE sources
+  ADD COLUMN etag TEXT,
+  ADD COLUMN last_modified TEXT;
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources
+  DROP COLUMN min_fetch_interval,
+  DROP COLUMN max_fetch_interval,
+  DROP COLUMN adaptive_fetch,
+  DROP COLUMN feed_ttl,
+  DROP COLUMN next_fetch;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+ALTER TABLE sources
+  ADD COLUMN min_fetch_interval INTEGER NOT NULL DEFAULT 15,
+  ADD COLUMN max_fetch_interval INTEGER NOT NULL DEFAULT 1440,
+  ADD COLUMN adaptive_fetch BOOLEAN NOT NULL DEFAULT FALSE,
+  ADD COLUMN feed_ttl INTEGER,
+  ADD COLUMN next_fetch TIMESTAMP NOT NULL DEFAULT NOW();
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE source_events;
+
+ALTER TABLE sources DROP COLUMN disabled;
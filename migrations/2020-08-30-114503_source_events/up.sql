@@ -0,0 +1,11 @@
+-- Your SQL goes here
+ALTER TABLE sources ADD COLUMN disabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE source_events (
+  id UUID PRIMARY KEY,
+  source UUID NOT NULL REFERENCES sources(id) ON DELETE CASCADE,
+  at TIMESTAMP NOT NULL,
+  kind TEXT NOT NULL,
+  detail TEXT NOT NULL
+);
+CREATE INDEX source_events_source_at ON source_events (source, at);
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources
+  DROP COLUMN consecutive_failures,
+  ADD COLUMN fetch_errors TEXT[] NOT NULL DEFAULT '{}';
+
+DROP TABLE fetch_attempts;
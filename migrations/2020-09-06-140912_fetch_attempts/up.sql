@@ -0,0 +1,36 @@
+-- Your SQL goes here
+CREATE TABLE fetch_attempts (
+  id UUID PRIMARY KEY,
+  source UUID NOT NULL REFERENCES sources(id) ON DELETE CASCADE,
+  started TIMESTAMP NOT NULL,
+  duration_ms INTEGER NOT NULL,
+  http_status INTEGER,
+  error_category TEXT,
+  error TEXT,
+  bytes BIGINT,
+  new_articles INTEGER NOT NULL
+);
+CREATE INDEX fetch_attempts_source_started ON fetch_attempts (source, started);
+
+-- Keep the errors sources have, as failed attempts. They were stored as
+-- `<RFC 822 date>: <error>`.
+INSERT INTO fetch_attempts
+  (id, source, started, duration_ms, error_category, error, new_articles)
+SELECT
+  md5(random()::TEXT || clock_timestamp()::TEXT)::UUID,
+  sources.id,
+  CASE WHEN dated THEN
+    to_timestamp(left(fetch_error, 25), 'Dy, DD Mon YYYY HH24:MI:SS')::TIMESTAMP
+  ELSE sources.last_fetch_started END,
+  0,
+  'other',
+  CASE WHEN dated THEN substring(fetch_error FROM 32) ELSE fetch_error END,
+  0
+FROM sources,
+  unnest(sources.fetch_errors) AS fetch_error,
+  LATERAL (SELECT fetch_error ~
+    '^\w{3}, \d{2} \w{3} \d{4} \d{2}:\d{2}:\d{2} GMT: ' AS dated) AS format;
+
+ALTER TABLE sources
+  DROP COLUMN fetch_errors,
+  ADD COLUMN consecutive_failures INTEGER NOT NULL DEFAULT 0;
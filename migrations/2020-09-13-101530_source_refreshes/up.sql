@@ -0,0 +1,5 @@
+-- Your SQL goes here
+CREATE TABLE source_refreshes (
+  source UUID PRIMARY KEY REFERENCES sources(id) ON DELETE CASCADE,
+  requested_at TIMESTAMP NOT NULL
+);
@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER articles_search_language ON articles;
+DROP FUNCTION articles_set_search_language;
+DROP INDEX articles_search;
+ALTER TABLE articles DROP COLUMN search;
+ALTER TABLE articles DROP COLUMN search_language;
+ALTER TABLE users DROP COLUMN search_language;
@@ -0,0 +1,35 @@
+-- Your SQL goes here
+-- Needs Postgres 12+, for generated columns
+ALTER TABLE users
+  ADD COLUMN search_language TEXT NOT NULL DEFAULT 'english';
+
+-- Copied from the source creator's `search_language` on insert
+ALTER TABLE articles
+  ADD COLUMN search_language REGCONFIG NOT NULL DEFAULT 'english';
+
+ALTER TABLE articles ADD COLUMN search TSVECTOR GENERATED ALWAYS AS (
+  setweight(to_tsvector(search_language, COALESCE(title, '')), 'A') ||
+  setweight(to_tsvector(search_language, COALESCE(summary, '')), 'B') ||
+  setweight(
+    to_tsvector(search_language, COALESCE(content ->> 'value', '')), 'C'
+  )
+) STORED;
+
+CREATE INDEX articles_search ON articles USING GIN (search);
+
+CREATE FUNCTION articles_set_search_language() RETURNS TRIGGER AS $$
+BEGIN
+  NEW.search_language := COALESCE(
+    (SELECT u.search_language::REGCONFIG
+     FROM sources s
+     JOIN users u ON u.username = s.creator
+     WHERE s.id = NEW.source),
+    'english'
+  );
+  RETURN NEW;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER articles_search_language
+  BEFORE INSERT ON articles
+  FOR EACH ROW EXECUTE FUNCTION articles_set_search_language();
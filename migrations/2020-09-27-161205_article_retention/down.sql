@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources DROP COLUMN retention_max_age;
+ALTER TABLE sources DROP COLUMN retention_max_count;
+ALTER TABLE sources DROP COLUMN retention_keep_starred;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- NULL uses the worker's default, and 0 means no limit
+ALTER TABLE sources ADD COLUMN retention_max_age INTEGER;
+ALTER TABLE sources ADD COLUMN retention_max_count INTEGER;
+ALTER TABLE sources ADD COLUMN retention_keep_starred BOOLEAN;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE articles DROP COLUMN raw_summary;
+ALTER TABLE articles DROP COLUMN raw_content;
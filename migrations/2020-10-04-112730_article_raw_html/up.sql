@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Feed-provided summary & content, before sanitizing. NULL for articles
+-- stored before sanitizing was added.
+ALTER TABLE articles ADD COLUMN raw_summary TEXT;
+ALTER TABLE articles ADD COLUMN raw_content JSON;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- The original shapes can't be recovered, and the normalized ones are still
+-- valid JSON, so articles are left as they are.
@@ -0,0 +1,65 @@
+-- Your SQL goes here
+-- Normalize articles' content, links & authors into the same shape for
+-- every kind of source. Atom rows are the ones with Atom's fields, or
+-- without any content, & are typed the same as `ContentType::from_atom`.
+
+UPDATE articles SET content = json_build_object(
+  'content_type', CASE
+    WHEN content IS NULL OR json_typeof(content) = 'null' THEN 'text'
+    WHEN content::jsonb ? 'src' THEN CASE
+      WHEN lower(trim(content ->> 'content_type')) = 'xhtml' THEN 'xhtml'
+      WHEN lower(trim(content ->> 'content_type')) LIKE '%html%' THEN 'html'
+      ELSE 'text'
+    END
+    WHEN content ->> 'content_type' = 'text' THEN 'text'
+    ELSE 'html'
+  END,
+  'value', content ->> 'value',
+  'src', content ->> 'src',
+  'lang', content ->> 'lang',
+  'base', content ->> 'base'
+);
+
+UPDATE articles SET links = COALESCE((
+  SELECT json_agg(CASE json_typeof(link)
+    WHEN 'string' THEN json_build_object(
+      'url', link #>> '{}',
+      'relationship', NULL,
+      'title', NULL,
+      'mime_type', NULL
+    )
+    ELSE json_build_object(
+      'url', link ->> 'href',
+      'relationship', link ->> 'rel',
+      'title', link ->> 'title',
+      'mime_type', link ->> 'mime_type'
+    )
+  END)
+  FROM json_array_elements(
+    CASE json_typeof(links) WHEN 'array' THEN links ELSE '[]' END
+  ) AS link
+), '[]');
+
+-- RSS authors are usually `email (Name)`
+UPDATE articles SET authors = COALESCE((
+  SELECT json_agg(CASE json_typeof(author)
+    WHEN 'string' THEN json_build_object(
+      'name', NULLIF(COALESCE(
+        substring(author #>> '{}' FROM '^\S+@\S+\s+\((.*)\)$'),
+        author #>> '{}'
+      ), ''),
+      'email', substring(author #>> '{}' FROM '^(\S+@\S+)\s+\(.*\)$'),
+      'url', NULL,
+      'avatar', NULL
+    )
+    ELSE json_build_object(
+      'name', author ->> 'name',
+      'email', author ->> 'email',
+      'url', COALESCE(author ->> 'url', author ->> 'uri'),
+      'avatar', author ->> 'avatar'
+    )
+  END)
+  FROM json_array_elements(
+    CASE json_typeof(authors) WHEN 'array' THEN authors ELSE '[]' END
+  ) AS author
+), '[]');
@@ -0,0 +1,20 @@
+-- This file should undo anything in `up.sql`
+UPDATE articles SET extensions = json_build_object(
+  'attachments', COALESCE((
+    SELECT json_agg(json_build_object(
+      'url', enclosure ->> 'url',
+      'mime_type', enclosure ->> 'mime_type',
+      'title', NULL,
+      'size_in_bytes', (enclosure ->> 'length')::BIGINT,
+      'duration_in_seconds', (enclosure ->> 'duration')::DOUBLE PRECISION
+    ))
+    FROM json_array_elements(enclosures) AS enclosure
+  ), '[]')
+)
+WHERE source IN (
+  SELECT id FROM sources WHERE source_data::jsonb ? 'JSONFeed'
+);
+
+ALTER TABLE articles DROP COLUMN enclosures;
+ALTER TABLE articles DROP COLUMN thumbnail;
+ALTER TABLE articles DROP COLUMN duration;
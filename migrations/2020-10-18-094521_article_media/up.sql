@@ -0,0 +1,45 @@
+-- Your SQL goes here
+-- Media attached to articles. Media RSS & iTunes data in stored articles'
+-- extensions is picked up as feeds are refetched.
+ALTER TABLE articles ADD COLUMN enclosures JSON NOT NULL DEFAULT '[]';
+ALTER TABLE articles ADD COLUMN thumbnail TEXT;
+ALTER TABLE articles ADD COLUMN duration INTEGER;
+
+-- JSON Feed attachments were kept in extensions
+UPDATE articles SET
+  enclosures = COALESCE((
+    SELECT json_agg(json_build_object(
+      'url', attachment ->> 'url',
+      'mime_type', attachment ->> 'mime_type',
+      'length', (attachment ->> 'size_in_bytes')::BIGINT,
+      'duration', round((attachment ->> 'duration_in_seconds')::NUMERIC)::INTEGER
+    ))
+    FROM json_array_elements(extensions -> 'attachments') AS attachment
+  ), '[]'),
+  extensions = '{}'
+WHERE json_typeof(extensions -> 'attachments') = 'array';
+
+UPDATE articles SET
+  duration = (
+    SELECT (enclosure ->> 'duration')::INTEGER
+    FROM json_array_elements(enclosures) AS enclosure
+    WHERE enclosure ->> 'duration' IS NOT NULL
+    LIMIT 1
+  )
+WHERE json_array_length(enclosures) > 0;
+
+-- Atom enclosures are links
+UPDATE articles SET enclosures = (
+  SELECT json_agg(json_build_object(
+    'url', link ->> 'url',
+    'mime_type', link ->> 'mime_type',
+    'length', NULL,
+    'duration', NULL
+  ))
+  FROM json_array_elements(links) AS link
+  WHERE link ->> 'relationship' = 'enclosure'
+)
+WHERE EXISTS (
+  SELECT 1 FROM json_array_elements(links) AS link
+  WHERE link ->> 'relationship' = 'enclosure'
+);
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE sources DROP COLUMN fetch_full_content;
+ALTER TABLE sources DROP COLUMN content_selector;
+ALTER TABLE articles DROP COLUMN full_content;
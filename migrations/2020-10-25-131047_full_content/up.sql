@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE sources ADD COLUMN fetch_full_content BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE sources ADD COLUMN content_selector TEXT;
+ALTER TABLE articles ADD COLUMN full_content TEXT;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Numeric article ids, for APIs that can't use UUIDs. Existing articles are
+-- numbered as the column is added.
+ALTER TABLE articles ADD COLUMN short_id BIGSERIAL NOT NULL UNIQUE;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE tags DROP COLUMN short_id;
+ALTER TABLE sources DROP COLUMN short_id;
+ALTER TABLE users DROP COLUMN fever_api_key;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- md5("<username>:<password>"), which is how Fever clients sign in. NULL
+-- until the user turns the Fever API on.
+ALTER TABLE users ADD COLUMN fever_api_key TEXT UNIQUE;
+-- Fever identifies feeds & groups by number too
+ALTER TABLE sources ADD COLUMN short_id BIGSERIAL NOT NULL UNIQUE;
+ALTER TABLE tags ADD COLUMN short_id BIGSERIAL NOT NULL UNIQUE;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE article_states DROP COLUMN updated;
+ALTER TABLE articles DROP COLUMN added;
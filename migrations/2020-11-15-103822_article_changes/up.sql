@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- For syncing only what changed since a client last synced. `added` isn't
+-- in the schema, since it's always set by Postgres.
+ALTER TABLE articles ADD COLUMN added TIMESTAMP NOT NULL DEFAULT now();
+ALTER TABLE article_states ADD COLUMN updated TIMESTAMP NOT NULL DEFAULT now();
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE output_feeds (
+  id UUID PRIMARY KEY,
+  owner TEXT REFERENCES users(username) ON DELETE CASCADE NOT NULL,
+  title TEXT NOT NULL,
+  source UUID REFERENCES sources(id) ON DELETE CASCADE,
+  tag UUID REFERENCES tags(id) ON DELETE CASCADE,
+  starred BOOLEAN NOT NULL DEFAULT FALSE,
+  public BOOLEAN NOT NULL DEFAULT FALSE,
+  token UUID UNIQUE NOT NULL,
+  CHECK (source IS NULL OR tag IS NULL),
+  CHECK (source IS NOT NULL OR tag IS NOT NULL OR starred)
+);
+
+CREATE INDEX output_feeds_owner ON output_feeds (owner);
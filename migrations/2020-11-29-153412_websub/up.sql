@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE websub_subscriptions (
+  source UUID PRIMARY KEY REFERENCES sources(id) ON DELETE CASCADE,
+  hub TEXT NOT NULL,
+  topic TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  requested_at TIMESTAMP,
+  lease_expires TIMESTAMP
+);
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE full_content_queue (
+  article UUID PRIMARY KEY REFERENCES articles(id) ON DELETE CASCADE,
+  queued_at TIMESTAMP NOT NULL
+);
+CREATE INDEX full_content_queue_queued_at ON full_content_queue (queued_at);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Articles deleted by retention, so they aren't stored again while they're
+-- still in the feed
+CREATE TABLE expired_articles (
+  source UUID NOT NULL REFERENCES sources(id) ON DELETE CASCADE,
+  id_from_source TEXT NOT NULL,
+  PRIMARY KEY (source, id_from_source)
+);
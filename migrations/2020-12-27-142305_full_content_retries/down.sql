@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE full_content_queue DROP COLUMN attempts;
+ALTER TABLE full_content_queue DROP COLUMN next_attempt;
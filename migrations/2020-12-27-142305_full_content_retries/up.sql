@@ -0,0 +1,8 @@
+-- Your SQL goes here
+-- Articles stay queued until their full content's stored. `next_attempt` is
+-- pushed back while one's being fetched, & after each failed attempt.
+ALTER TABLE full_content_queue ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE full_content_queue
+  ADD COLUMN next_attempt TIMESTAMP NOT NULL DEFAULT NOW();
+CREATE INDEX full_content_queue_next_attempt
+  ON full_content_queue (next_attempt);
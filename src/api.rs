@@ -1 +1,144 @@
+pub mod fever;
+pub mod greader;
+pub mod nextcloud;
 pub mod v1;
+pub mod websub;
+
+use crate::{
+    db::{
+        sources::{self, Source, SourceData},
+        tags, ShortId,
+    },
+    sources::{detect, Detected},
+};
+use diesel::{pg::PgConnection, QueryResult};
+use rocket::request::{FormItems, FromForm};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Every field of a form or query string, for APIs that repeat fields (ex:
+/// GReader's `i` for each item) or use fields without values (ex: Fever's
+/// `?api&items`).
+#[derive(Debug, Default)]
+pub struct Params(HashMap<String, Vec<String>>);
+
+impl<'f> FromForm<'f> for Params {
+    type Error = ();
+
+    fn from_form(items: &mut FormItems<'f>, _: bool) -> Result<Self, ()> {
+        let mut params = Params::default();
+        for item in items {
+            let (key, value) = item.key_value_decoded();
+            params.0.entry(key).or_insert_with(Vec::new).push(value);
+        }
+        Ok(params)
+    }
+}
+
+impl Params {
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.all(key).first().map(|value| value.as_str())
+    }
+
+    pub fn all(&self, key: &str) -> &[String] {
+        self.0
+            .get(key)
+            .map(|values| values.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn has(&self, key: &str) -> bool {
+        self.0.contains_key(key)
+    }
+
+    pub fn number(&self, key: &str) -> Option<i64> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Both sets of fields, ex: a query string & form.
+    pub fn merge(mut self, other: Params) -> Params {
+        for (key, values) in other.0 {
+            self.0.entry(key).or_insert_with(Vec::new).extend(values);
+        }
+        self
+    }
+}
+
+/// Numeric ids for the user's sources & tags, for APIs that can't use
+/// UUIDs.
+pub struct ShortIds {
+    pub sources: HashMap<Uuid, i64>,
+    pub tags: HashMap<Uuid, i64>,
+}
+
+impl ShortIds {
+    pub fn new(username: &str, conn: &PgConnection) -> QueryResult<ShortIds> {
+        let to_map = |ids: Vec<ShortId>| {
+            ids.into_iter().map(|s| (s.id, s.short_id)).collect()
+        };
+        Ok(ShortIds {
+            sources: to_map(sources::short_ids_from_user(
+                username.to_string(),
+                conn,
+            )?),
+            tags: to_map(tags::short_ids_from_user(
+                username.to_string(),
+                conn,
+            )?),
+        })
+    }
+
+    fn find(ids: &HashMap<Uuid, i64>, short_id: i64) -> Option<Uuid> {
+        ids.iter()
+            .find(|(_, id)| **id == short_id)
+            .map(|(uuid, _)| *uuid)
+    }
+
+    pub fn source(&self, short_id: i64) -> Option<Uuid> {
+        ShortIds::find(&self.sources, short_id)
+    }
+
+    pub fn tag(&self, short_id: i64) -> Option<Uuid> {
+        ShortIds::find(&self.tags, short_id)
+    }
+}
+
+/// Where a source's articles are fetched from.
+pub fn source_url(source: &Source) -> String {
+    serde_json::from_value::<SourceData>(source.source_data.to_owned())
+        .map(|data| data.url().to_string())
+        .unwrap_or_default()
+}
+
+/// A new source for the feed at `url`, for APIs that subscribe by URL. It
+/// isn't inserted yet.
+pub fn detect_source(
+    url: &str,
+    title: Option<&str>,
+    username: &str,
+) -> Result<Source, String> {
+    let id = Uuid::new_v4();
+    let detected = match detect(url, id) {
+        Ok(Detected::Feed(detected)) => detected,
+        Ok(Detected::Candidates(candidates)) => {
+            return Err(format!(
+                "{} links to {} feeds, subscribe to one of them",
+                url,
+                candidates.len()
+            ))
+        }
+        Err(e) => return Err(format!("Could not fetch {}: {}", url, e)),
+    };
+    let title = title
+        .map(|t| t.to_string())
+        .or(detected.feed.title)
+        .map(|t| t.trim().to_string())
+        .unwrap_or_default();
+    Ok(Source::new(
+        Some(id),
+        title,
+        serde_json::to_value(detected.source_data).unwrap(),
+        "".to_string(),
+        username.to_string(),
+    ))
+}
@@ -0,0 +1,523 @@
+//! A Fever compatible API, for clients that only speak it (ex: Unread,
+//! ReadKit). Mounted at `/api/fever`.
+//!
+//! Every request is a POST with `api_key` (see
+//! `db::users::set_fever_api_key`), and the query string says what to send
+//! back (ex: `?api&items&since_id=10`). Groups are tags, feeds are sources,
+//! and everything is identified by its `short_id`.
+
+use crate::{
+    api::{source_url, v1::ApiError, Params, ShortIds},
+    db::{
+        article_states::{self, StateChange},
+        articles::{self, ArticleFilter, ShortIdRange},
+        sources, tagged_sources, tags, users, DbConn,
+    },
+    sanitize::content_html,
+    timestamp::Timestamp,
+};
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::request::Form;
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const API_VERSION: i32 = 3;
+/// Fever always sends items 50 at a time.
+const PAGE_SIZE: i64 = 50;
+/// The group every feed's in.
+const KINDLING: i64 = 0;
+
+#[derive(Debug, Serialize)]
+pub struct Group {
+    id: i64,
+    title: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedsGroup {
+    group_id: i64,
+    /// Comma separated
+    feed_ids: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Feed {
+    id: i64,
+    favicon_id: i64,
+    title: String,
+    url: String,
+    site_url: String,
+    is_spark: i32,
+    last_updated_on_time: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Item {
+    id: i64,
+    feed_id: i64,
+    title: String,
+    author: String,
+    html: String,
+    url: String,
+    is_saved: i32,
+    is_read: i32,
+    created_on_time: i64,
+}
+
+/// Only what was asked for is sent.
+#[derive(Debug, Default, Serialize)]
+pub struct FeverResp {
+    api_version: i32,
+    auth: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_refreshed_on_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<Vec<Group>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feeds: Option<Vec<Feed>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    feeds_groups: Option<Vec<FeedsGroup>>,
+    /// Sources don't have favicons, so this is always empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicons: Option<Vec<()>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    items: Option<Vec<Item>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    total_items: Option<i64>,
+    /// Hot links aren't supported, so this is always empty
+    #[serde(skip_serializing_if = "Option::is_none")]
+    links: Option<Vec<()>>,
+    /// Comma separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unread_item_ids: Option<String>,
+    /// Comma separated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    saved_item_ids: Option<String>,
+}
+
+fn join_ids(ids: impl Iterator<Item = i64>) -> String {
+    ids.map(|id| id.to_string()).collect::<Vec<_>>().join(",")
+}
+
+fn parse_ids(ids: &str) -> Vec<i64> {
+    ids.split(',')
+        .filter_map(|id| id.trim().parse().ok())
+        .collect()
+}
+
+fn feeds_groups(
+    username: &str,
+    ids: &ShortIds,
+    conn: &PgConnection,
+) -> QueryResult<Vec<FeedsGroup>> {
+    let mut groups: HashMap<i64, Vec<i64>> = HashMap::new();
+    for tagged in tagged_sources::all_from_user(username.to_string(), conn)? {
+        if let (Some(group), Some(feed)) =
+            (ids.tags.get(&tagged.tag), ids.sources.get(&tagged.source))
+        {
+            groups.entry(*group).or_default().push(*feed);
+        }
+    }
+    Ok(groups
+        .into_iter()
+        .map(|(group_id, feeds)| FeedsGroup {
+            group_id,
+            feed_ids: join_ids(feeds.into_iter()),
+        })
+        .collect())
+}
+
+/// Up to `PAGE_SIZE` items: those in `with_ids`, or after `since_id`, or
+/// before `max_id`.
+fn items(
+    params: &Params,
+    username: &str,
+    ids: &ShortIds,
+    conn: &PgConnection,
+) -> QueryResult<Vec<Item>> {
+    // Newest first when paging back from `max_id`
+    let newest_first = params.number("since_id").is_none()
+        && params.number("max_id").is_some();
+    let short_ids = match params.get("with_ids") {
+        Some(with_ids) => {
+            let mut with_ids = parse_ids(with_ids);
+            with_ids.truncate(PAGE_SIZE as usize);
+            articles::from_short_ids(&with_ids, conn)?
+        }
+        None => articles::short_ids_from_user(
+            username.to_string(),
+            &ArticleFilter::default(),
+            &ShortIdRange {
+                after: params.number("since_id"),
+                before: params.number("max_id"),
+                newest_first,
+                limit: Some(PAGE_SIZE),
+                ..ShortIdRange::default()
+            },
+            conn,
+        )?,
+    };
+    let order: HashMap<Uuid, i64> =
+        short_ids.into_iter().map(|s| (s.id, s.short_id)).collect();
+    let uuids: Vec<Uuid> = order.keys().cloned().collect();
+    let mut articles = articles::all_with_ids(&uuids, conn)?;
+    // Articles from other users' sources don't have a feed id, so they're
+    // dropped
+    articles.retain(|a| ids.sources.contains_key(&a.source));
+    articles.sort_by_key(|a| {
+        let id = order[&a.id];
+        if newest_first {
+            -id
+        } else {
+            id
+        }
+    });
+
+    let article_ids: Vec<Uuid> = articles.iter().map(|a| a.id).collect();
+    let states: HashMap<Uuid, (bool, bool)> = article_states::all_for_articles(
+        &article_ids,
+        username.to_string(),
+        conn,
+    )?
+    .into_iter()
+    .map(|s| (s.article, (s.read, s.starred)))
+    .collect();
+    Ok(articles
+        .into_iter()
+        .map(|article| {
+            let (read, starred) =
+                states.get(&article.id).cloned().unwrap_or((false, false));
+            let html = content_html(&article)
+                .map(|html| html.into_owned())
+                .unwrap_or_default();
+            Item {
+                id: order[&article.id],
+                feed_id: ids.sources[&article.source],
+                url: article.links.article_url().unwrap_or("").into(),
+                title: article.title.unwrap_or_default(),
+                author: article
+                    .authors
+                    .0
+                    .into_iter()
+                    .find_map(|author| author.name)
+                    .unwrap_or_default(),
+                html,
+                is_saved: starred as i32,
+                is_read: read as i32,
+                created_on_time: article
+                    .published
+                    .map(|p| p.0.sec)
+                    .unwrap_or(0),
+            }
+        })
+        .collect())
+}
+
+/// Short ids of the user's articles matching `filter`.
+fn item_ids(
+    username: &str,
+    filter: ArticleFilter,
+    conn: &PgConnection,
+) -> QueryResult<String> {
+    let ids = articles::ids_from_user(username.to_string(), &filter, conn)?;
+    let mut short_ids: Vec<i64> = articles::short_ids(&ids, conn)?
+        .into_iter()
+        .map(|s| s.short_id)
+        .collect();
+    short_ids.sort_unstable();
+    Ok(join_ids(short_ids.into_iter()))
+}
+
+/// `mark=item|feed|group`, `as=read|unread|saved|unsaved`, `id` and, for
+/// feeds & groups, `before` (seconds).
+fn mark(
+    params: &Params,
+    username: &str,
+    ids: &ShortIds,
+    conn: &PgConnection,
+) -> QueryResult<()> {
+    let (kind, id) = match (params.get("mark"), params.number("id")) {
+        (Some(kind), Some(id)) => (kind, id),
+        _ => return Ok(()),
+    };
+    let before = params
+        .number("before")
+        .map(|before| Timestamp(time::Timespec::new(before, 0)))
+        .unwrap_or_else(Timestamp::now);
+    match (kind, params.get("as")) {
+        ("item", Some(state)) => {
+            let change = match state {
+                "read" => StateChange::new(Some(true), None),
+                "unread" => StateChange::new(Some(false), None),
+                "saved" => StateChange::new(None, Some(true)),
+                "unsaved" => StateChange::new(None, Some(false)),
+                _ => return Ok(()),
+            };
+            let articles: Vec<Uuid> = articles::from_short_ids(&[id], conn)?
+                .into_iter()
+                .map(|s| s.id)
+                .collect();
+            article_states::set(
+                &articles,
+                username.to_string(),
+                &change,
+                conn,
+            )?;
+        }
+        ("feed", Some("read")) => {
+            if let Some(source) = ids.source(id) {
+                article_states::mark_read_until(
+                    username.to_string(),
+                    Some(source),
+                    None,
+                    before,
+                    conn,
+                )?;
+            }
+        }
+        ("group", Some("read")) => {
+            let tag = ids.tag(id);
+            if tag.is_some() || id == KINDLING {
+                article_states::mark_read_until(
+                    username.to_string(),
+                    None,
+                    tag,
+                    before,
+                    conn,
+                )?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[post("/?<query..>", data = "<form>")]
+pub fn fever(
+    conn: DbConn,
+    query: Form<Params>,
+    form: Form<Params>,
+) -> Result<Json<FeverResp>, ApiError> {
+    let params = query.into_inner().merge(form.into_inner());
+    let mut resp = FeverResp {
+        api_version: API_VERSION,
+        ..FeverResp::default()
+    };
+    let user = match params.get("api_key") {
+        Some(api_key) => {
+            users::get_by_fever_api_key(api_key, &conn).optional()?
+        }
+        None => None,
+    };
+    let username = match user {
+        Some(user) => user.username,
+        None => return Ok(Json(resp)),
+    };
+    resp.auth = 1;
+
+    let ids = ShortIds::new(&username, &conn)?;
+    mark(&params, &username, &ids, &conn)?;
+
+    let user_sources = sources::all_from_user(username.clone(), &conn)?;
+    resp.last_refreshed_on_time = user_sources
+        .iter()
+        .map(|source| source.last_successful_fetch.0.sec)
+        .max()
+        .or(Some(0));
+    if params.has("groups") {
+        let mut groups: Vec<Group> =
+            tags::all_from_user(username.clone(), &conn)?
+                .into_iter()
+                .filter_map(|tag| {
+                    Some(Group {
+                        id: *ids.tags.get(&tag.id)?,
+                        title: tag.name,
+                    })
+                })
+                .collect();
+        groups.sort_by_key(|group| group.id);
+        resp.groups = Some(groups);
+    }
+    if params.has("feeds") {
+        let mut feeds: Vec<Feed> = user_sources
+            .into_iter()
+            .filter_map(|source| {
+                let url = source_url(&source);
+                Some(Feed {
+                    id: *ids.sources.get(&source.id)?,
+                    favicon_id: 0,
+                    title: source.title,
+                    site_url: url.clone(),
+                    url,
+                    is_spark: 0,
+                    last_updated_on_time: source.last_successful_fetch.0.sec,
+                })
+            })
+            .collect();
+        feeds.sort_by_key(|feed| feed.id);
+        resp.feeds = Some(feeds);
+    }
+    if params.has("groups") || params.has("feeds") {
+        resp.feeds_groups = Some(feeds_groups(&username, &ids, &conn)?);
+    }
+    if params.has("favicons") {
+        resp.favicons = Some(vec![]);
+    }
+    if params.has("items") {
+        resp.items = Some(items(&params, &username, &ids, &conn)?);
+        resp.total_items =
+            Some(articles::count_from_user(username.clone(), &conn)?);
+    }
+    if params.has("links") {
+        resp.links = Some(vec![]);
+    }
+    if params.has("unread_item_ids") {
+        let filter = ArticleFilter {
+            read: Some(false),
+            ..ArticleFilter::default()
+        };
+        resp.unread_item_ids = Some(item_ids(&username, filter, &conn)?);
+    }
+    if params.has("saved_item_ids") {
+        let filter = ArticleFilter {
+            starred: Some(true),
+            ..ArticleFilter::default()
+        };
+        resp.saved_item_ids = Some(item_ids(&username, filter, &conn)?);
+    }
+    Ok(Json(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use rocket::{
+        http::{ContentType, Status},
+        local::Client,
+    };
+    use serde_json::Value;
+
+    fn fever_post(client: &Client, query: &str, body: &str) -> Value {
+        let mut response = client
+            .post(format!("/api/fever/?{}", query))
+            .header(ContentType::Form)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        testing::json(&mut response)
+    }
+
+    #[test]
+    fn ids() {
+        assert_eq!(parse_ids("1, 2,x,30"), vec![1, 2, 30]);
+        assert_eq!(join_ids(vec![4, 5].into_iter()), "4,5");
+        assert_eq!(join_ids(vec![].into_iter()), "");
+    }
+
+    #[test]
+    fn api_fever_sync() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("fever", "hunter22", &conn);
+
+        // Off until the user turns it on
+        let resp = fever_post(&client, "api", "api_key=nope");
+        assert_eq!(resp["auth"], 0);
+        assert!(resp.get("feeds").is_none());
+        users::set_fever_api_key(username.clone(), Some("hunter22"), &conn)
+            .unwrap();
+        let api_key = users::get(username.clone(), &conn)
+            .unwrap()
+            .fever_api_key
+            .unwrap();
+        let body = format!("api_key={}", api_key);
+
+        let resp = fever_post(&client, "api&feeds&groups", &body);
+        assert_eq!(resp["auth"], 1);
+        assert_eq!(resp["api_version"], 3);
+        assert_eq!(resp["feeds"][0]["title"], "Example Blog");
+        assert_eq!(resp["feeds"][0]["url"], "https://example.org/feed.json");
+        assert_eq!(resp["groups"], Value::Array(vec![]));
+        assert!(resp.get("items").is_none());
+        let feed_id = resp["feeds"][0]["id"].clone();
+
+        let resp = fever_post(&client, "api&unread_item_ids", &body);
+        let unread = parse_ids(resp["unread_item_ids"].as_str().unwrap());
+        assert_eq!(unread.len(), 2);
+
+        let resp = fever_post(
+            &client,
+            &format!("api&items&since_id={}", unread[0] - 1),
+            &body,
+        );
+        assert_eq!(resp["total_items"], 2);
+        let items = resp["items"].as_array().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0]["id"], unread[0]);
+        assert_eq!(items[0]["feed_id"], feed_id);
+        assert_eq!(items[0]["is_read"], 0);
+        let second_post = items
+            .iter()
+            .find(|item| item["title"] == "Second post")
+            .unwrap();
+        assert_eq!(second_post["url"], "https://example.org/second-post");
+        assert_eq!(second_post["author"], "Item Author");
+
+        let resp = fever_post(
+            &client,
+            &format!("api&items&max_id={}", unread[1]),
+            &body,
+        );
+        assert_eq!(resp["items"][0]["id"], unread[0]);
+
+        let id = second_post["id"].as_i64().unwrap();
+        fever_post(
+            &client,
+            "api",
+            &format!("{}&mark=item&as=saved&id={}", body, id),
+        );
+        fever_post(
+            &client,
+            "api",
+            &format!("{}&mark=item&as=read&id={}", body, id),
+        );
+        let resp =
+            fever_post(&client, "api&unread_item_ids&saved_item_ids", &body);
+        assert_eq!(resp["saved_item_ids"], id.to_string());
+        let unread = parse_ids(resp["unread_item_ids"].as_str().unwrap());
+        assert_eq!(unread.len(), 1);
+        assert!(!unread.contains(&id));
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn api_fever_plain_text_escaped() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) = testing::seed_user("fever", "hunter22", &conn);
+        testing::insert_markup_text(source, &conn);
+        users::set_fever_api_key(username.clone(), Some("hunter22"), &conn)
+            .unwrap();
+        let api_key = users::get(username.clone(), &conn)
+            .unwrap()
+            .fever_api_key
+            .unwrap();
+
+        let resp =
+            fever_post(&client, "api&items", &format!("api_key={}", api_key));
+        let html: Vec<&str> = resp["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|item| item["html"].as_str())
+            .collect();
+        assert!(html.contains(&testing::MARKUP_TEXT_HTML));
+        assert!(!html.iter().any(|html| html.contains(testing::MARKUP_TEXT)));
+
+        testing::remove_user(username, &conn);
+    }
+}
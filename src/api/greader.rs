@@ -0,0 +1,300 @@
+//! A Google Reader compatible API, for clients that speak it (ex: Reeder,
+//! NetNewsWire, FeedMe). Mounted at `/api/greader`.
+//!
+//! Sources are `feed/<id>` streams, tags are `user/-/label/<name>` streams,
+//! and read & starred are the `user/-/state/com.google/*` streams. GReader
+//! item ids are 64 bit integers, so articles' `short_id`s are used. Streams
+//! are always newest first.
+
+pub mod accounts;
+pub mod streams;
+pub mod subscriptions;
+pub mod tags;
+
+use crate::{api::v1::ApiError, timestamp::Timestamp};
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use std::fmt;
+use uuid::Uuid;
+
+pub const READING_LIST: &str = "user/-/state/com.google/reading-list";
+pub const READ: &str = "user/-/state/com.google/read";
+pub const STARRED: &str = "user/-/state/com.google/starred";
+const LABEL_PREFIX: &str = "user/-/label/";
+const FEED_PREFIX: &str = "feed/";
+const ITEM_PREFIX: &str = "tag:google.com,2005:reader/item/";
+
+pub type GReaderResp<T> = Result<Json<T>, ApiError>;
+
+pub fn user_err<U: Into<String>, T>(x: U) -> Result<T, ApiError> {
+    Err(ApiError::new(Status::BadRequest, x.into()))
+}
+
+/// A stream of articles, ex: a source or tag.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamId {
+    ReadingList,
+    Read,
+    Starred,
+    Label(String),
+    Source(Uuid),
+    /// A feed by URL, which may not be a source yet
+    FeedUrl(String),
+    /// A state we don't keep (ex: `kept-unread`)
+    Other(String),
+}
+
+impl StreamId {
+    pub fn parse(id: &str) -> StreamId {
+        // `user/<user id>/...` is the same as `user/-/...`
+        let id = match id
+            .strip_prefix("user/")
+            .and_then(|rest| rest.find('/').map(|slash| &rest[slash..]))
+        {
+            Some(rest) => format!("user/-{}", rest),
+            None => id.to_string(),
+        };
+        match id.as_str() {
+            READING_LIST => StreamId::ReadingList,
+            READ => StreamId::Read,
+            STARRED => StreamId::Starred,
+            _ => {
+                if let Some(name) = id.strip_prefix(LABEL_PREFIX) {
+                    StreamId::Label(name.to_string())
+                } else if let Some(feed) = id.strip_prefix(FEED_PREFIX) {
+                    match Uuid::parse_str(feed) {
+                        Ok(source) => StreamId::Source(source),
+                        Err(_) => StreamId::FeedUrl(feed.to_string()),
+                    }
+                } else {
+                    StreamId::Other(id)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamId::ReadingList => write!(f, "{}", READING_LIST),
+            StreamId::Read => write!(f, "{}", READ),
+            StreamId::Starred => write!(f, "{}", STARRED),
+            StreamId::Label(name) => write!(f, "{}{}", LABEL_PREFIX, name),
+            StreamId::Source(id) => write!(f, "{}{}", FEED_PREFIX, id),
+            StreamId::FeedUrl(url) => write!(f, "{}{}", FEED_PREFIX, url),
+            StreamId::Other(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Parse an item id, in either the long form
+/// (`tag:google.com,2005:reader/item/<hex>`) or the short form (decimal).
+pub fn parse_item_id(id: &str) -> Option<i64> {
+    match id.strip_prefix(ITEM_PREFIX) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|id| id as i64),
+        None => id.parse().ok(),
+    }
+}
+
+pub fn long_item_id(short_id: i64) -> String {
+    format!("{}{:016x}", ITEM_PREFIX, short_id)
+}
+
+pub fn seconds(timestamp: Option<Timestamp>) -> i64 {
+    timestamp.map(|t| t.0.sec).unwrap_or(0)
+}
+
+pub fn from_seconds(seconds: i64) -> Timestamp {
+    Timestamp(time::Timespec::new(seconds, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use regex::Regex;
+    use rocket::http::{ContentType, Header, Method};
+    use serde::Deserialize;
+    use std::{collections::HashMap, fs};
+
+    /// A request from a recorded client session. `{name}`s are filled in
+    /// from the seeded data (`username`, `password` & `source`) and earlier
+    /// captures.
+    #[derive(Debug, Deserialize)]
+    struct Recorded {
+        method: String,
+        path: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        #[serde(default)]
+        body: String,
+        status: u16,
+        contains: Vec<String>,
+        /// Regexes whose first group is saved for later requests
+        #[serde(default)]
+        capture: HashMap<String, String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Session {
+        client: String,
+        requests: Vec<Recorded>,
+    }
+
+    fn fill(template: &str, vars: &HashMap<String, String>) -> String {
+        vars.iter()
+            .fold(template.to_string(), |filled, (name, value)| {
+                filled.replace(&format!("{{{}}}", name), value)
+            })
+    }
+
+    /// Replay a session against a new user, with one source.
+    fn replay(path: &str) {
+        let session: Session =
+            serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        let client = testing::client();
+        let conn = testing::conn();
+
+        let password = "hunter22";
+        let (username, source) = testing::seed_user("greader", password, &conn);
+
+        let mut vars = HashMap::new();
+        vars.insert("username".to_string(), username.clone());
+        vars.insert("password".to_string(), password.to_string());
+        vars.insert("source".to_string(), source.to_string());
+        for (i, recorded) in session.requests.iter().enumerate() {
+            let context = format!("{} request {}", session.client, i);
+            let method = match recorded.method.as_str() {
+                "GET" => Method::Get,
+                "POST" => Method::Post,
+                other => panic!("{}: unknown method {}", context, other),
+            };
+            let mut request = client.req(method, fill(&recorded.path, &vars));
+            for (name, value) in &recorded.headers {
+                request
+                    .add_header(Header::new(name.clone(), fill(value, &vars)));
+            }
+            request.set_body(fill(&recorded.body, &vars));
+            let mut response = request.dispatch();
+            let body = response.body_string().unwrap_or_default();
+
+            assert_eq!(response.status().code, recorded.status, "{}", context);
+            for expected in &recorded.contains {
+                let expected = fill(expected, &vars);
+                assert!(
+                    body.contains(&expected),
+                    "{}: {} not in {}",
+                    context,
+                    expected,
+                    body
+                );
+            }
+            for (name, pattern) in &recorded.capture {
+                let captured = Regex::new(pattern)
+                    .unwrap()
+                    .captures(&body)
+                    .unwrap_or_else(|| {
+                        panic!("{}: {} not in {}", context, pattern, body)
+                    })[1]
+                    .to_string();
+                vars.insert(name.clone(), captured);
+            }
+        }
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn replay_reeder() {
+        replay("test_data/greader/reeder.json");
+    }
+
+    #[test]
+    fn replay_netnewswire() {
+        replay("test_data/greader/netnewswire.json");
+    }
+
+    #[test]
+    fn plain_text_escaped() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("greader", "hunter22", &conn);
+        testing::insert_markup_text(source, &conn);
+
+        let mut response = client
+            .post("/api/greader/accounts/ClientLogin")
+            .header(ContentType::Form)
+            .body(format!("Email={}&Passwd=hunter22", username))
+            .dispatch();
+        let body = response.body_string().unwrap();
+        let auth = body
+            .lines()
+            .find_map(|line| line.strip_prefix("Auth="))
+            .unwrap()
+            .to_string();
+        let mut response = client
+            .get(format!(
+                "/api/greader/reader/api/0/stream/contents/feed%2F{}",
+                source
+            ))
+            .header(Header::new(
+                "Authorization",
+                format!("GoogleLogin auth={}", auth),
+            ))
+            .dispatch();
+        let contents = testing::json(&mut response);
+        let html: Vec<&str> = contents["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|item| item["summary"]["content"].as_str())
+            .collect();
+        assert!(html.contains(&testing::MARKUP_TEXT_HTML));
+        assert!(!html.iter().any(|html| html.contains(testing::MARKUP_TEXT)));
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn stream_ids() {
+        let source = Uuid::new_v4();
+        assert_eq!(StreamId::parse(READING_LIST), StreamId::ReadingList);
+        assert_eq!(
+            StreamId::parse("user/1005921515/state/com.google/starred"),
+            StreamId::Starred
+        );
+        assert_eq!(
+            StreamId::parse("user/-/label/Tech News"),
+            StreamId::Label("Tech News".into())
+        );
+        assert_eq!(
+            StreamId::parse(&format!("feed/{}", source)),
+            StreamId::Source(source)
+        );
+        assert_eq!(
+            StreamId::parse("feed/https://example.org/feed.xml"),
+            StreamId::FeedUrl("https://example.org/feed.xml".into())
+        );
+        assert_eq!(
+            StreamId::parse("user/-/state/com.google/kept-unread"),
+            StreamId::Other("user/-/state/com.google/kept-unread".into())
+        );
+        assert_eq!(
+            StreamId::Source(source).to_string(),
+            format!("feed/{}", source)
+        );
+    }
+
+    #[test]
+    fn item_ids() {
+        assert_eq!(
+            long_item_id(31),
+            "tag:google.com,2005:reader/item/000000000000001f"
+        );
+        assert_eq!(parse_item_id(&long_item_id(31)), Some(31));
+        assert_eq!(parse_item_id("31"), Some(31));
+        assert_eq!(parse_item_id("tag:google.com,2005:reader/item/xyz"), None);
+    }
+}
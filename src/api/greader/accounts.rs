@@ -0,0 +1,79 @@
+use crate::{
+    api::{
+        greader::GReaderResp,
+        v1::{ApiError, ValidToken},
+    },
+    db::{tokens, tokens::Token, users, DbConn},
+};
+use bcrypt::verify;
+use rocket::{http::Status, request::Form};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// GReader clients stay signed in until the token expires or is revoked,
+/// then sign in again.
+const TOKEN_DAYS: i64 = 365;
+
+#[derive(Debug, FromForm)]
+pub struct ClientLogin {
+    #[form(field = "Email")]
+    email: String,
+    #[form(field = "Passwd")]
+    passwd: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    user_id: String,
+    user_name: String,
+    user_profile_id: String,
+    user_email: String,
+}
+
+/// Sign in, getting a token to send as `Authorization: GoogleLogin
+/// auth=<token>`.
+#[post("/accounts/ClientLogin", data = "<login>")]
+pub fn client_login(
+    conn: DbConn,
+    login: Form<ClientLogin>,
+) -> Result<String, ApiError> {
+    let bad_auth = || {
+        ApiError::new(Status::Unauthorized, "Error=BadAuthentication".into())
+    };
+    let user =
+        users::get(login.email.clone(), &conn).map_err(|_| bad_auth())?;
+    if !verify(&login.passwd, &user.password)? {
+        return Err(bad_auth());
+    }
+
+    let token = tokens::insert(
+        Token {
+            id: Uuid::new_v4(),
+            username: user.username,
+            expires: (time::now() + time::Duration::days(TOKEN_DAYS))
+                .to_timespec(),
+        },
+        &conn,
+    )?;
+    Ok(format!("SID={0}\nLSID={0}\nAuth={0}\n", token.id))
+}
+
+/// A token for requests that change things (GReader's `T` field). The auth
+/// token already proves who's asking, so it's only for clients that expect
+/// one.
+#[get("/reader/api/0/token")]
+pub fn token(token: ValidToken) -> String {
+    format!("{}\n", token.id.to_simple())
+}
+
+#[get("/reader/api/0/user-info")]
+pub fn user_info(token: ValidToken) -> GReaderResp<UserInfo> {
+    Ok(Json(UserInfo {
+        user_id: token.username.clone(),
+        user_name: token.username.clone(),
+        user_profile_id: token.username.clone(),
+        user_email: token.username,
+    }))
+}
@@ -0,0 +1,562 @@
+use crate::{
+    api::{
+        greader::{
+            from_seconds, long_item_id, parse_item_id, seconds,
+            subscriptions::user_source, tags::find_label, user_err,
+            GReaderResp, StreamId,
+        },
+        source_url,
+        v1::{items::with_states, ApiError, ValidToken},
+        Params,
+    },
+    db::{
+        article_states::{self, StateChange},
+        articles::{self, ArticleFilter, Cursor},
+        sources, tagged_sources, tags, DbConn,
+    },
+    sanitize::content_html,
+    timestamp::Timestamp,
+};
+use diesel::pg::PgConnection;
+use rocket::{
+    http::{uri::Segments, RawStr},
+    request::Form,
+};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 20;
+const MAX_PAGE_SIZE: i64 = 1000;
+const MAX_IDS_PAGE_SIZE: i64 = 10000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Href {
+    href: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Origin {
+    stream_id: String,
+    title: String,
+    html_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Summary {
+    content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Enclosure {
+    href: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<String>,
+}
+
+/// An article, as GReader clients expect it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    id: String,
+    crawl_time_msec: String,
+    timestamp_usec: String,
+    published: i64,
+    updated: i64,
+    title: String,
+    canonical: Vec<Href>,
+    alternate: Vec<Href>,
+    categories: Vec<String>,
+    origin: Origin,
+    summary: Summary,
+    author: String,
+    enclosure: Vec<Enclosure>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamContents {
+    id: String,
+    updated: i64,
+    items: Vec<Item>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemRef {
+    id: String,
+    direct_stream_ids: Vec<String>,
+    timestamp_usec: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemRefs {
+    item_refs: Vec<ItemRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    continuation: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamUnread {
+    id: String,
+    count: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnreadCounts {
+    max: i64,
+    unreadcounts: Vec<StreamUnread>,
+}
+
+fn usec(timestamp: Option<Timestamp>) -> String {
+    (seconds(timestamp) * 1_000_000).to_string()
+}
+
+/// Articles matching a stream, & the `xt` (exclude), `it` (include), `ot`
+/// (newer than) and `nt` (older than) parameters. Like `crawlTimeMsec`,
+/// `ot` & `nt` are when articles were stored.
+fn stream_filter(
+    stream: &StreamId,
+    params: &Params,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<ArticleFilter, ApiError> {
+    let mut filter = ArticleFilter::default();
+    match stream {
+        StreamId::ReadingList => {}
+        StreamId::Read => filter.read = Some(true),
+        StreamId::Starred => filter.starred = Some(true),
+        StreamId::Label(name) => match find_label(name, username, conn)? {
+            Some(tag) => filter.tag = Some(tag.id),
+            None => return user_err(format!("No label {}", name)),
+        },
+        StreamId::Source(_) | StreamId::FeedUrl(_) => {
+            match user_source(stream, username, conn)? {
+                Some(source) => filter.source = Some(source.id),
+                None => {
+                    return user_err(format!("Not subscribed to {}", stream))
+                }
+            }
+        }
+        StreamId::Other(_) => {
+            return user_err(format!("Unknown stream {}", stream))
+        }
+    }
+
+    for (key, value) in &[("xt", false), ("it", true)] {
+        for state in params.all(key) {
+            match StreamId::parse(state) {
+                StreamId::Read => filter.read = Some(*value),
+                StreamId::Starred => filter.starred = Some(*value),
+                _ => {}
+            }
+        }
+    }
+    filter.added_after = params.number("ot").map(from_seconds);
+    filter.added_before = params.number("nt").map(from_seconds);
+    Ok(filter)
+}
+
+fn page_size(params: &Params, max: i64) -> i64 {
+    params
+        .number("n")
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .max(1)
+        .min(max)
+}
+
+fn continuation(params: &Params) -> Result<Option<Cursor>, ApiError> {
+    match params.get("c").map(|c| c.parse::<Cursor>()).transpose() {
+        Ok(cursor) => Ok(cursor),
+        Err(e) => user_err(e),
+    }
+}
+
+/// Convert articles to items, in the same order.
+fn items(
+    articles: Vec<articles::Article>,
+    username: String,
+    conn: &PgConnection,
+) -> Result<Vec<Item>, ApiError> {
+    let ids: Vec<Uuid> = articles.iter().map(|a| a.id).collect();
+    let short_ids: HashMap<Uuid, i64> = articles::short_ids(&ids, conn)?
+        .into_iter()
+        .map(|s| (s.id, s.short_id))
+        .collect();
+    let added: HashMap<Uuid, Timestamp> = articles::added(&ids, conn)?
+        .into_iter()
+        .map(|a| (a.id, a.added))
+        .collect();
+
+    let tag_names: HashMap<Uuid, String> =
+        tags::all_from_user(username.clone(), conn)?
+            .into_iter()
+            .map(|tag| (tag.id, tag.name))
+            .collect();
+    let mut labels: HashMap<Uuid, Vec<String>> = HashMap::new();
+    for tagged in tagged_sources::all_from_user(username.clone(), conn)? {
+        if let Some(name) = tag_names.get(&tagged.tag) {
+            labels
+                .entry(tagged.source)
+                .or_default()
+                .push(StreamId::Label(name.clone()).to_string());
+        }
+    }
+    let origins: HashMap<Uuid, Origin> =
+        sources::all_from_user(username.clone(), conn)?
+            .into_iter()
+            .map(|source| {
+                let origin = Origin {
+                    stream_id: StreamId::Source(source.id).to_string(),
+                    html_url: source_url(&source),
+                    title: source.title,
+                };
+                (source.id, origin)
+            })
+            .collect();
+
+    Ok(with_states(articles, username, conn)?
+        .into_iter()
+        .filter_map(|item| {
+            let article = item.article;
+            let origin = origins.get(&article.source)?.clone();
+            let mut categories = vec![StreamId::ReadingList.to_string()];
+            categories.extend(
+                labels.get(&article.source).cloned().unwrap_or_default(),
+            );
+            if item.read {
+                categories.push(StreamId::Read.to_string());
+            }
+            if item.starred {
+                categories.push(StreamId::Starred.to_string());
+            }
+            let links: Vec<Href> = article
+                .links
+                .article_url()
+                .map(|url| Href { href: url.into() })
+                .into_iter()
+                .collect();
+            let content = content_html(&article)
+                .map(|html| html.into_owned())
+                .unwrap_or_default();
+            let added = added.get(&article.id).copied();
+            // Undated articles are dated by when they were stored
+            let published = article.published.or(added);
+            Some(Item {
+                id: long_item_id(*short_ids.get(&article.id)?),
+                crawl_time_msec: (seconds(added) * 1000).to_string(),
+                timestamp_usec: usec(published),
+                published: seconds(published),
+                updated: seconds(published),
+                title: article.title.unwrap_or_default(),
+                canonical: links.clone(),
+                alternate: links,
+                categories,
+                origin,
+                summary: Summary { content },
+                author: article
+                    .authors
+                    .0
+                    .into_iter()
+                    .find_map(|author| author.name)
+                    .unwrap_or_default(),
+                enclosure: article
+                    .enclosures
+                    .0
+                    .into_iter()
+                    .map(|enclosure| Enclosure {
+                        href: enclosure.url,
+                        mime_type: enclosure.mime_type,
+                        length: enclosure.length.map(|l| l.to_string()),
+                    })
+                    .collect(),
+            })
+        })
+        .collect())
+}
+
+fn contents(
+    stream: &str,
+    params: &Params,
+    username: String,
+    conn: &PgConnection,
+) -> GReaderResp<StreamContents> {
+    let stream = StreamId::parse(stream);
+    let filter = stream_filter(&stream, params, &username, conn)?;
+    let limit = page_size(params, MAX_PAGE_SIZE);
+    let articles = articles::all_from_user(
+        username.clone(),
+        &filter,
+        continuation(params)?,
+        limit,
+        conn,
+    )?;
+    let continuation = if articles.len() as i64 == limit {
+        articles.last().map(|a| Cursor::after(a).to_string())
+    } else {
+        None
+    };
+    Ok(Json(StreamContents {
+        id: stream.to_string(),
+        updated: Timestamp::now().0.sec,
+        items: items(articles, username, conn)?,
+        continuation,
+    }))
+}
+
+/// A stream's articles, newest first. The stream id's in the path.
+#[get("/reader/api/0/stream/contents/<stream..>?<params..>")]
+pub fn stream_contents(
+    conn: DbConn,
+    token: ValidToken,
+    stream: Segments,
+    params: Form<Params>,
+) -> GReaderResp<StreamContents> {
+    let stream = RawStr::from_str(stream.0).percent_decode_lossy();
+    contents(&stream, &params, token.username, &conn)
+}
+
+/// `stream_contents`, with the stream id in `s`. Without one, it's the
+/// reading list.
+#[get("/reader/api/0/stream/contents?<params..>")]
+pub fn stream_contents_param(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> GReaderResp<StreamContents> {
+    let stream = params
+        .get("s")
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| StreamId::ReadingList.to_string());
+    contents(&stream, &params, token.username, &conn)
+}
+
+/// The ids of a stream's articles, newest first. Clients fetch these first,
+/// then the articles they don't have with `stream/items/contents`.
+#[get("/reader/api/0/stream/items/ids?<params..>")]
+pub fn stream_item_ids(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> GReaderResp<ItemRefs> {
+    let stream = match params.get("s") {
+        Some(stream) => StreamId::parse(stream),
+        None => return user_err("s is required"),
+    };
+    let filter = stream_filter(&stream, &params, &token.username, &conn)?;
+    let limit = page_size(&params, MAX_IDS_PAGE_SIZE);
+    let cursors = articles::cursors_from_user(
+        token.username,
+        &filter,
+        continuation(&params)?,
+        limit,
+        &conn,
+    )?;
+    let continuation = if cursors.len() as i64 == limit {
+        cursors.last().map(|c| c.to_string())
+    } else {
+        None
+    };
+
+    let ids: Vec<Uuid> = cursors.iter().map(|c| c.id).collect();
+    let short_ids: HashMap<Uuid, i64> = articles::short_ids(&ids, &conn)?
+        .into_iter()
+        .map(|s| (s.id, s.short_id))
+        .collect();
+    let added: HashMap<Uuid, Timestamp> = articles::added(&ids, &conn)?
+        .into_iter()
+        .map(|a| (a.id, a.added))
+        .collect();
+    let item_refs = cursors
+        .into_iter()
+        .filter_map(|cursor| {
+            let published =
+                cursor.published.or_else(|| added.get(&cursor.id).copied());
+            Some(ItemRef {
+                id: short_ids.get(&cursor.id)?.to_string(),
+                direct_stream_ids: vec![],
+                timestamp_usec: usec(published),
+            })
+        })
+        .collect();
+    Ok(Json(ItemRefs {
+        item_refs,
+        continuation,
+    }))
+}
+
+/// Articles by id (`i`, repeated), in the order they're asked for.
+fn items_contents(
+    params: &Params,
+    username: String,
+    conn: &PgConnection,
+) -> GReaderResp<StreamContents> {
+    let short_ids: Vec<i64> = params
+        .all("i")
+        .iter()
+        .filter_map(|i| parse_item_id(i))
+        .collect();
+    let order: HashMap<Uuid, usize> =
+        articles::from_short_ids(&short_ids, conn)?
+            .into_iter()
+            .filter_map(|s| {
+                let position = short_ids.iter().position(|i| *i == s.short_id);
+                position.map(|position| (s.id, position))
+            })
+            .collect();
+    let ids: Vec<Uuid> = order.keys().cloned().collect();
+    let mut articles = articles::all_with_ids(&ids, conn)?;
+    articles.sort_by_key(|a| order.get(&a.id));
+
+    // `items` drops articles from other users' sources
+    Ok(Json(StreamContents {
+        id: StreamId::ReadingList.to_string(),
+        updated: Timestamp::now().0.sec,
+        items: items(articles, username, conn)?,
+        continuation: None,
+    }))
+}
+
+#[get("/reader/api/0/stream/items/contents?<params..>")]
+pub fn stream_items_contents(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> GReaderResp<StreamContents> {
+    items_contents(&params, token.username, &conn)
+}
+
+/// `stream_items_contents`, for clients that post the ids since there can
+/// be too many for a URL.
+#[post("/reader/api/0/stream/items/contents", data = "<params>")]
+pub fn stream_items_contents_post(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> GReaderResp<StreamContents> {
+    items_contents(&params, token.username, &conn)
+}
+
+/// Add (`a`) & remove (`r`) the read & starred states on articles (`i`).
+/// Labels are per source, so other tags are ignored.
+#[post("/reader/api/0/edit-tag", data = "<params>")]
+pub fn edit_tag(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> Result<&'static str, ApiError> {
+    let mut change = (None, None);
+    for (key, value) in &[("a", true), ("r", false)] {
+        for tag in params.all(key) {
+            match StreamId::parse(tag) {
+                StreamId::Read => change.0 = Some(*value),
+                StreamId::Starred => change.1 = Some(*value),
+                _ => {}
+            }
+        }
+    }
+    if change == (None, None) {
+        return Ok("OK");
+    }
+
+    let short_ids: Vec<i64> = params
+        .all("i")
+        .iter()
+        .filter_map(|i| parse_item_id(i))
+        .collect();
+    let ids: Vec<Uuid> = articles::from_short_ids(&short_ids, &conn)?
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    article_states::set(
+        &ids,
+        token.username,
+        &StateChange::new(change.0, change.1),
+        &conn,
+    )?;
+    Ok("OK")
+}
+
+/// Mark a stream's articles (`s`) as read, up to `ts` (microseconds).
+#[post("/reader/api/0/mark-all-as-read", data = "<params>")]
+pub fn mark_all_as_read(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> Result<&'static str, ApiError> {
+    let stream = match params.get("s") {
+        Some(stream) => StreamId::parse(stream),
+        None => return user_err("s is required"),
+    };
+    let until = params
+        .number("ts")
+        .map(|ts| from_seconds(ts / 1_000_000))
+        .unwrap_or_else(Timestamp::now);
+    let (source, tag) = match stream {
+        StreamId::ReadingList => (None, None),
+        StreamId::Label(ref name) => {
+            match find_label(name, &token.username, &conn)? {
+                Some(tag) => (None, Some(tag.id)),
+                None => return user_err(format!("No label {}", name)),
+            }
+        }
+        StreamId::Source(_) | StreamId::FeedUrl(_) => {
+            match user_source(&stream, &token.username, &conn)? {
+                Some(source) => (Some(source.id), None),
+                None => {
+                    return user_err(format!("Not subscribed to {}", stream))
+                }
+            }
+        }
+        _ => return user_err(format!("Can't mark {} as read", stream)),
+    };
+    article_states::mark_read_until(token.username, source, tag, until, &conn)?;
+    Ok("OK")
+}
+
+/// Unread counts for the reading list, each source & each label.
+#[get("/reader/api/0/unread-count")]
+pub fn unread_count(
+    conn: DbConn,
+    token: ValidToken,
+) -> GReaderResp<UnreadCounts> {
+    let by_source =
+        article_states::unread_by_source(token.username.clone(), &conn)?;
+    let total = by_source.iter().map(|c| c.unread).sum();
+    let tag_names: HashMap<Uuid, String> =
+        tags::all_from_user(token.username.clone(), &conn)?
+            .into_iter()
+            .map(|tag| (tag.id, tag.name))
+            .collect();
+    let by_tag = article_states::unread_by_tag(token.username, &conn)?
+        .into_iter()
+        .filter_map(|c| {
+            Some(StreamUnread {
+                id: StreamId::Label(tag_names.get(&c.id)?.clone()).to_string(),
+                count: c.unread,
+            })
+        });
+
+    let unreadcounts = Some(StreamUnread {
+        id: StreamId::ReadingList.to_string(),
+        count: total,
+    })
+    .into_iter()
+    .chain(by_source.into_iter().map(|c| StreamUnread {
+        id: StreamId::Source(c.id).to_string(),
+        count: c.unread,
+    }))
+    .chain(by_tag)
+    .collect();
+    Ok(Json(UnreadCounts {
+        max: total,
+        unreadcounts,
+    }))
+}
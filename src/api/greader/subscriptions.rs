@@ -0,0 +1,226 @@
+use crate::{
+    api::{
+        detect_source,
+        greader::{
+            tags::{find_label, label_tag},
+            user_err, GReaderResp, StreamId,
+        },
+        source_url,
+        v1::{ApiError, ValidToken},
+        Params,
+    },
+    db::{
+        sources::{self, Source},
+        tagged_sources::{self, TaggedSource},
+        tags, DbConn,
+    },
+};
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::request::Form;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Category {
+    id: String,
+    label: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    id: String,
+    title: String,
+    categories: Vec<Category>,
+    url: String,
+    html_url: String,
+    icon_url: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SubscriptionList {
+    subscriptions: Vec<Subscription>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuickAdd {
+    num_results: i32,
+    query: String,
+    stream_id: String,
+    stream_name: String,
+}
+
+/// The user's source for a `feed/` stream.
+pub fn user_source(
+    stream: &StreamId,
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<Option<Source>> {
+    match stream {
+        StreamId::Source(id) => Ok(sources::get(*id, conn)
+            .optional()?
+            .filter(|source| source.creator == username)),
+        StreamId::FeedUrl(url) => {
+            Ok(sources::all_from_user(username.to_string(), conn)?
+                .into_iter()
+                .find(|source| source_url(source) == *url))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Create a source for the feed at `url`, unless the user already has one.
+fn subscribe(
+    url: &str,
+    title: Option<&str>,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<Source, ApiError> {
+    let stream = StreamId::FeedUrl(url.to_string());
+    if let Some(source) = user_source(&stream, username, conn)? {
+        return Ok(source);
+    }
+    match detect_source(url, title, username) {
+        Ok(source) => Ok(sources::insert(source, conn)?),
+        Err(e) => user_err(e),
+    }
+}
+
+/// Add & remove labels (`a` & `r`) from a source, & rename it (`t`).
+fn edit(
+    mut source: Source,
+    params: &Params,
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<()> {
+    if let Some(title) = params.get("t") {
+        source.title = title.to_string();
+        sources::update(&source, conn)?;
+    }
+    for added in params.all("a") {
+        if let StreamId::Label(name) = StreamId::parse(added) {
+            let tag = label_tag(&name, username, conn)?;
+            if tagged_sources::get_by_tag_and_source(tag.id, source.id, conn)
+                .optional()?
+                .is_none()
+            {
+                tagged_sources::insert(
+                    TaggedSource {
+                        id: Uuid::new_v4(),
+                        tag: tag.id,
+                        source: source.id,
+                    },
+                    conn,
+                )?;
+            }
+        }
+    }
+    for removed in params.all("r") {
+        if let StreamId::Label(name) = StreamId::parse(removed) {
+            if let Some(tag) = find_label(&name, username, conn)? {
+                if let Some(tagged) = tagged_sources::get_by_tag_and_source(
+                    tag.id, source.id, conn,
+                )
+                .optional()?
+                {
+                    tagged_sources::delete(tagged.id, conn)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[get("/reader/api/0/subscription/list")]
+pub fn subscription_list(
+    conn: DbConn,
+    token: ValidToken,
+) -> GReaderResp<SubscriptionList> {
+    let tag_names: HashMap<Uuid, String> =
+        tags::all_from_user(token.username.clone(), &conn)?
+            .into_iter()
+            .map(|tag| (tag.id, tag.name))
+            .collect();
+    let mut categories: HashMap<Uuid, Vec<Category>> = HashMap::new();
+    for tagged in tagged_sources::all_from_user(token.username.clone(), &conn)?
+    {
+        if let Some(name) = tag_names.get(&tagged.tag) {
+            categories.entry(tagged.source).or_default().push(Category {
+                id: StreamId::Label(name.clone()).to_string(),
+                label: name.clone(),
+            });
+        }
+    }
+
+    let subscriptions = sources::all_from_user(token.username, &conn)?
+        .into_iter()
+        .map(|source| {
+            let url = source_url(&source);
+            Subscription {
+                id: StreamId::Source(source.id).to_string(),
+                categories: categories.remove(&source.id).unwrap_or_default(),
+                title: source.title,
+                html_url: url.clone(),
+                url,
+                icon_url: "".into(),
+            }
+        })
+        .collect();
+    Ok(Json(SubscriptionList { subscriptions }))
+}
+
+/// Subscribe to (`ac=subscribe`), unsubscribe from (`ac=unsubscribe`), or
+/// edit (`ac=edit`) each of the `s` streams.
+#[post("/reader/api/0/subscription/edit", data = "<params>")]
+pub fn subscription_edit(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> Result<&'static str, ApiError> {
+    let action = params.get("ac").unwrap_or("edit");
+    for stream in params.all("s") {
+        let stream = StreamId::parse(stream);
+        let source = user_source(&stream, &token.username, &conn)?;
+        match (action, source, &stream) {
+            ("subscribe", None, StreamId::FeedUrl(url)) => {
+                let source =
+                    subscribe(url, params.get("t"), &token.username, &conn)?;
+                edit(source, &params, &token.username, &conn)?;
+            }
+            ("subscribe", Some(source), _) | ("edit", Some(source), _) => {
+                edit(source, &params, &token.username, &conn)?
+            }
+            ("unsubscribe", Some(source), _) => {
+                sources::delete(source.id, &conn)?;
+            }
+            (_, None, _) => {
+                return user_err(format!("Not subscribed to {}", stream))
+            }
+            _ => return user_err(format!("Unknown action {}", action)),
+        }
+    }
+    Ok("OK")
+}
+
+#[post("/reader/api/0/subscription/quickadd", data = "<params>")]
+pub fn subscription_quickadd(
+    conn: DbConn,
+    token: ValidToken,
+    params: Form<Params>,
+) -> GReaderResp<QuickAdd> {
+    let query = match params.get("quickadd") {
+        Some(query) => query,
+        None => return user_err("quickadd is required"),
+    };
+    let url = query.strip_prefix("feed/").unwrap_or(query);
+    let source = subscribe(url, None, &token.username, &conn)?;
+    Ok(Json(QuickAdd {
+        num_results: 1,
+        query: query.to_string(),
+        stream_id: StreamId::Source(source.id).to_string(),
+        stream_name: source.title,
+    }))
+}
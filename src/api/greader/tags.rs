@@ -0,0 +1,75 @@
+use crate::{
+    api::{
+        greader::{GReaderResp, StreamId},
+        v1::ValidToken,
+    },
+    db::{
+        tags::{self, Tag},
+        DbConn,
+    },
+};
+use diesel::{pg::PgConnection, prelude::*};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagItem {
+    id: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    kind: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagList {
+    tags: Vec<TagItem>,
+}
+
+/// The user's tag named `name`.
+pub fn find_label(
+    name: &str,
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<Option<Tag>> {
+    Ok(tags::all_from_user(username.to_string(), conn)?
+        .into_iter()
+        .find(|tag| tag.name == name))
+}
+
+/// The user's tag named `name`, created if it doesn't exist yet.
+pub fn label_tag(
+    name: &str,
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<Tag> {
+    match find_label(name, username, conn)? {
+        Some(tag) => Ok(tag),
+        None => tags::insert(
+            Tag {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+                owner: username.to_string(),
+            },
+            conn,
+        ),
+    }
+}
+
+#[get("/reader/api/0/tag/list")]
+pub fn tag_list(conn: DbConn, token: ValidToken) -> GReaderResp<TagList> {
+    let labels =
+        tags::all_from_user(token.username, &conn)?
+            .into_iter()
+            .map(|tag| TagItem {
+                id: StreamId::Label(tag.name).to_string(),
+                kind: Some("folder".into()),
+            });
+    let tags = Some(TagItem {
+        id: StreamId::Starred.to_string(),
+        kind: None,
+    })
+    .into_iter()
+    .chain(labels)
+    .collect();
+    Ok(Json(TagList { tags }))
+}
@@ -0,0 +1,427 @@
+//! A Nextcloud News (API v1.3) compatible API, for its clients (ex:
+//! Nextcloud News for Android, FeedHQ, Fiery Feeds). Mounted at
+//! `/index.php/apps/news/api/v1-3`, where clients expect it.
+//!
+//! Folders are tags, feeds are sources, and everything is identified by its
+//! `short_id`. Nextcloud feeds are in at most one folder, so sources in
+//! several tags show up in the first one. Clients sign in with HTTP Basic
+//! auth on every request, so verified credentials are cached for a few
+//! minutes (see `state::AuthCache`).
+
+pub mod feeds;
+pub mod folders;
+pub mod items;
+
+use crate::{
+    api::v1::ApiError,
+    db::{users, DbConn, Pool},
+    state::AuthCache,
+};
+use bcrypt::verify;
+use rocket::{
+    http::Status,
+    request::{FromRequest, Outcome, Request},
+    State,
+};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+/// The News app version this API matches. Clients check it for features.
+const NEWS_VERSION: &str = "15.1.1";
+
+pub type NextcloudResp<T> = Result<Json<T>, ApiError>;
+
+pub fn err<U: Into<String>, T>(status: Status, x: U) -> Result<T, ApiError> {
+    Err(ApiError::new(status, x.into()))
+}
+
+/// A user signed in with HTTP Basic auth.
+pub struct BasicAuth {
+    pub username: String,
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for BasicAuth {
+    type Error = ();
+
+    fn from_request(
+        request: &'a Request<'r>,
+    ) -> Outcome<BasicAuth, Self::Error> {
+        let credentials = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Basic "))
+            .and_then(|encoded| base64::decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let mut parts = credentials.splitn(2, ':');
+        let (username, password) = match (parts.next(), parts.next()) {
+            (Some(username), Some(password)) => (username, password),
+            _ => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+
+        let pool = request.guard::<State<Pool>>()?;
+        let conn = match pool.get() {
+            Ok(conn) => DbConn(conn),
+            Err(_) => {
+                return Outcome::Failure((Status::ServiceUnavailable, ()))
+            }
+        };
+        let user = match users::get(username.to_string(), &conn) {
+            Ok(user) => user,
+            Err(_) => return Outcome::Failure((Status::Unauthorized, ())),
+        };
+        let cache = request.guard::<State<AuthCache>>()?;
+        if !cache.verified(password, &user.password) {
+            if !verify(password, &user.password).unwrap_or(false) {
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+            cache.insert(password, &user.password);
+        }
+        Outcome::Success(BasicAuth {
+            username: user.username,
+        })
+    }
+}
+
+/// `newestItemId`, for marking everything up to it as read. It's the
+/// newest item the client has seen, so newer ones stay unread.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewestItem {
+    pub newest_item_id: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Version {
+    version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Warnings {
+    improperly_configured_cron: bool,
+    incorrect_db_charset: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerStatus {
+    version: String,
+    warnings: Warnings,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+    user_id: String,
+    display_name: String,
+    last_login_timestamp: i64,
+    avatar: Option<String>,
+}
+
+#[get("/version")]
+pub fn version() -> Json<Version> {
+    Json(Version {
+        version: NEWS_VERSION.into(),
+    })
+}
+
+#[get("/status")]
+pub fn status(_user: BasicAuth) -> Json<ServerStatus> {
+    Json(ServerStatus {
+        version: NEWS_VERSION.into(),
+        warnings: Warnings {
+            improperly_configured_cron: false,
+            incorrect_db_charset: false,
+        },
+    })
+}
+
+#[get("/user")]
+pub fn user(user: BasicAuth) -> Json<UserInfo> {
+    Json(UserInfo {
+        user_id: user.username.clone(),
+        display_name: user.username,
+        last_login_timestamp: 0,
+        avatar: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::{tagged_sources, tagged_sources::TaggedSource, tags},
+        testing,
+    };
+    use rocket::{
+        http::{ContentType, Header, Method, Status},
+        local::Client,
+    };
+    use serde_json::{json, Value};
+    use uuid::Uuid;
+
+    const BASE: &str = "/index.php/apps/news/api/v1-3";
+
+    fn basic_auth(username: &str, password: &str) -> Header<'static> {
+        Header::new(
+            "Authorization",
+            format!(
+                "Basic {}",
+                base64::encode(format!("{}:{}", username, password))
+            ),
+        )
+    }
+
+    fn call(
+        client: &Client,
+        method: Method,
+        path: &str,
+        auth: &Header<'static>,
+        body: Option<Value>,
+    ) -> (Status, Value) {
+        let mut request = client
+            .req(method, format!("{}{}", BASE, path))
+            .header(auth.clone());
+        if let Some(body) = body {
+            request = request.header(ContentType::JSON).body(body.to_string());
+        }
+        let mut response = request.dispatch();
+        let body = response.body_string().unwrap_or_default();
+        (
+            response.status(),
+            serde_json::from_str(&body).unwrap_or(Value::Null),
+        )
+    }
+
+    #[test]
+    fn api_nextcloud_sync() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("nextcloud", "hunter22", &conn);
+        let auth = basic_auth(&username, "hunter22");
+        let bad_auth = basic_auth(&username, "nope");
+
+        let (status, _) = call(&client, Method::Get, "/feeds", &bad_auth, None);
+        assert_eq!(status, Status::Unauthorized);
+
+        // Folders
+        let (status, folders) = call(
+            &client,
+            Method::Post,
+            "/folders",
+            &auth,
+            Some(json!({"name": "Podcasts"})),
+        );
+        assert_eq!(status, Status::Ok);
+        assert_eq!(folders["folders"][0]["name"], "Podcasts");
+        let folder_id = folders["folders"][0]["id"].as_i64().unwrap();
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            "/folders",
+            &auth,
+            Some(json!({"name": "Podcasts"})),
+        );
+        assert_eq!(status, Status::Conflict);
+
+        // Feeds
+        let (status, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(status, Status::Ok);
+        assert_eq!(feeds["feeds"][0]["title"], "Example Blog");
+        assert_eq!(feeds["feeds"][0]["folderId"], Value::Null);
+        assert_eq!(feeds["feeds"][0]["unreadCount"], 2);
+        assert_eq!(feeds["starredCount"], 0);
+        let feed_id = feeds["feeds"][0]["id"].as_i64().unwrap();
+        let newest = feeds["newestItemId"].as_i64().unwrap();
+
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            &format!("/feeds/{}/move", feed_id),
+            &auth,
+            Some(json!({ "folderId": folder_id })),
+        );
+        assert_eq!(status, Status::Ok);
+        let (_, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(feeds["feeds"][0]["folderId"], folder_id);
+
+        // Items
+        let (status, items) = call(
+            &client,
+            Method::Get,
+            &format!("/items?type=1&id={}&batchSize=1", folder_id),
+            &auth,
+            None,
+        );
+        assert_eq!(status, Status::Ok);
+        let items = items["items"].as_array().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0]["id"], newest);
+        assert_eq!(items[0]["feedId"], feed_id);
+        assert_eq!(items[0]["unread"], true);
+        let (_, older) = call(
+            &client,
+            Method::Get,
+            &format!("/items?type=3&offset={}", newest),
+            &auth,
+            None,
+        );
+        assert_eq!(older["items"].as_array().unwrap().len(), 1);
+        let since = items[0]["lastModified"].as_i64().unwrap();
+
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            "/items/star/multiple",
+            &auth,
+            Some(json!({ "itemIds": [newest] })),
+        );
+        assert_eq!(status, Status::Ok);
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            &format!("/items/{}/read", newest),
+            &auth,
+            None,
+        );
+        assert_eq!(status, Status::Ok);
+        let (_, updated) = call(
+            &client,
+            Method::Get,
+            &format!("/items/updated?type=3&lastModified={}", since - 1),
+            &auth,
+            None,
+        );
+        let updated = updated["items"].as_array().unwrap();
+        let item = updated.iter().find(|i| i["id"] == newest).unwrap();
+        assert_eq!(item["unread"], false);
+        assert_eq!(item["starred"], true);
+
+        let (_, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(feeds["feeds"][0]["unreadCount"], 1);
+        assert_eq!(feeds["starredCount"], 1);
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            &format!("/feeds/{}/read", feed_id),
+            &auth,
+            Some(json!({ "newestItemId": newest })),
+        );
+        assert_eq!(status, Status::Ok);
+        let (_, unread) = call(
+            &client,
+            Method::Get,
+            "/items?type=3&getRead=false",
+            &auth,
+            None,
+        );
+        assert_eq!(unread["items"], json!([]));
+
+        let (status, _) = call(
+            &client,
+            Method::Delete,
+            &format!("/folders/{}", folder_id),
+            &auth,
+            None,
+        );
+        assert_eq!(status, Status::Ok);
+        let (status, _) = call(
+            &client,
+            Method::Delete,
+            &format!("/feeds/{}", feed_id),
+            &auth,
+            None,
+        );
+        assert_eq!(status, Status::Ok);
+        let (_, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(feeds["feeds"], json!([]));
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn api_nextcloud_move_keeps_tags() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("nextcloud", "hunter22", &conn);
+        let auth = basic_auth(&username, "hunter22");
+        let mut folder_ids = Vec::new();
+        for name in &["A", "B", "C"] {
+            let (_, folders) = call(
+                &client,
+                Method::Post,
+                "/folders",
+                &auth,
+                Some(json!({ "name": name })),
+            );
+            folder_ids.push(folders["folders"][0]["id"].as_i64().unwrap());
+        }
+        let user_tags = tags::all_from_user(username.clone(), &conn).unwrap();
+        for tag in user_tags.iter().filter(|tag| tag.name != "A") {
+            tagged_sources::insert(
+                TaggedSource {
+                    id: Uuid::new_v4(),
+                    tag: tag.id,
+                    source,
+                },
+                &conn,
+            )
+            .unwrap();
+        }
+        let (_, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(feeds["feeds"][0]["folderId"], folder_ids[1]);
+        let feed_id = feeds["feeds"][0]["id"].as_i64().unwrap();
+
+        // Only B, the tag shown as its folder, is replaced
+        let (status, _) = call(
+            &client,
+            Method::Post,
+            &format!("/feeds/{}/move", feed_id),
+            &auth,
+            Some(json!({ "folderId": folder_ids[0] })),
+        );
+        assert_eq!(status, Status::Ok);
+        let (_, feeds) = call(&client, Method::Get, "/feeds", &auth, None);
+        assert_eq!(feeds["feeds"][0]["folderId"], folder_ids[0]);
+        let mut tag_names: Vec<String> = user_tags
+            .into_iter()
+            .filter(|tag| {
+                tagged_sources::get_by_tag_and_source(tag.id, source, &conn)
+                    .is_ok()
+            })
+            .map(|tag| tag.name)
+            .collect();
+        tag_names.sort();
+        assert_eq!(tag_names, ["A", "C"]);
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn api_nextcloud_plain_text_escaped() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("nextcloud", "hunter22", &conn);
+        testing::insert_markup_text(source, &conn);
+        let auth = basic_auth(&username, "hunter22");
+
+        let (_, items) =
+            call(&client, Method::Get, "/items?type=3", &auth, None);
+        let bodies: Vec<&str> = items["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|item| item["body"].as_str())
+            .collect();
+        assert!(bodies.contains(&testing::MARKUP_TEXT_HTML));
+        assert!(!bodies
+            .iter()
+            .any(|body| body.contains(testing::MARKUP_TEXT)));
+
+        testing::remove_user(username, &conn);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    api::{
+        detect_source,
+        nextcloud::{err, BasicAuth, NewestItem, NextcloudResp},
+        source_url,
+        v1::ApiError,
+        ShortIds,
+    },
+    db::{
+        article_states,
+        articles::{self, ArticleFilter, ShortIdRange},
+        sources::{self, Source},
+        tagged_sources::{self, TaggedSource},
+        DbConn,
+    },
+};
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feed {
+    id: i64,
+    url: String,
+    title: String,
+    favicon_link: Option<String>,
+    added: i64,
+    folder_id: Option<i64>,
+    unread_count: i64,
+    ordering: i32,
+    link: String,
+    pinned: bool,
+    update_error_count: i32,
+    last_update_error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Feeds {
+    feeds: Vec<Feed>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starred_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    newest_item_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NewFeed {
+    url: String,
+    folder_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MoveFeed {
+    folder_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameFeed {
+    feed_title: String,
+}
+
+/// The user's newest article, if they have any.
+pub fn newest_item_id(
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<Option<i64>> {
+    Ok(articles::short_ids_from_user(
+        username.to_string(),
+        &ArticleFilter::default(),
+        &ShortIdRange {
+            newest_first: true,
+            limit: Some(1),
+            ..ShortIdRange::default()
+        },
+        conn,
+    )?
+    .first()
+    .map(|s| s.short_id))
+}
+
+/// The folder each source is shown in: its lowest numbered tag.
+fn folder_ids(
+    username: &str,
+    ids: &ShortIds,
+    conn: &PgConnection,
+) -> QueryResult<HashMap<Uuid, i64>> {
+    let mut folders: HashMap<Uuid, i64> = HashMap::new();
+    for tagged in tagged_sources::all_from_user(username.to_string(), conn)? {
+        if let Some(folder) = ids.tags.get(&tagged.tag) {
+            let entry = folders.entry(tagged.source).or_insert(*folder);
+            *entry = (*entry).min(*folder);
+        }
+    }
+    Ok(folders)
+}
+
+fn feed(
+    source: Source,
+    ids: &ShortIds,
+    folders: &HashMap<Uuid, i64>,
+    unread: &HashMap<Uuid, i64>,
+) -> Option<Feed> {
+    let url = source_url(&source);
+    Some(Feed {
+        id: *ids.sources.get(&source.id)?,
+        link: url.clone(),
+        url,
+        title: source.title,
+        favicon_link: None,
+        added: 0,
+        folder_id: folders.get(&source.id).cloned(),
+        unread_count: unread.get(&source.id).cloned().unwrap_or(0),
+        ordering: 0,
+        pinned: false,
+        update_error_count: source.consecutive_failures,
+        last_update_error: None,
+    })
+}
+
+/// The user's source for a feed id.
+fn user_source(
+    id: i64,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<Source, ApiError> {
+    match ShortIds::new(username, conn)?.source(id) {
+        Some(source) => Ok(sources::get(source, conn)?),
+        None => err(Status::NotFound, "Feed does not exist"),
+    }
+}
+
+/// Put a source in a folder, or in none (`null`, or `0` from older
+/// clients), taking it out of the folder it's shown in. Its other tags are
+/// kept.
+fn move_source(
+    source: Uuid,
+    folder: Option<i64>,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<(), ApiError> {
+    let ids = ShortIds::new(username, conn)?;
+    let tag = match folder.filter(|folder| *folder != 0) {
+        Some(folder) => match ids.tag(folder) {
+            Some(tag) => Some(tag),
+            None => return err(Status::NotFound, "Folder does not exist"),
+        },
+        None => None,
+    };
+    let shown = folder_ids(username, &ids, conn)?
+        .get(&source)
+        .and_then(|folder| ids.tag(*folder));
+    if shown != tag {
+        for tagged in tagged_sources::all_from_user(username.to_string(), conn)?
+        {
+            if tagged.source == source && Some(tagged.tag) == shown {
+                tagged_sources::delete(tagged.id, conn)?;
+            }
+        }
+    }
+    if let Some(tag) = tag {
+        if tagged_sources::get_by_tag_and_source(tag, source, conn)
+            .optional()?
+            .is_none()
+        {
+            tagged_sources::insert(
+                TaggedSource {
+                    id: Uuid::new_v4(),
+                    tag,
+                    source,
+                },
+                conn,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[get("/feeds")]
+pub fn feeds(conn: DbConn, user: BasicAuth) -> NextcloudResp<Feeds> {
+    let ids = ShortIds::new(&user.username, &conn)?;
+    let folders = folder_ids(&user.username, &ids, &conn)?;
+    let unread: HashMap<Uuid, i64> =
+        article_states::unread_by_source(user.username.clone(), &conn)?
+            .into_iter()
+            .map(|count| (count.id, count.unread))
+            .collect();
+    let mut feeds: Vec<Feed> =
+        sources::all_from_user(user.username.clone(), &conn)?
+            .into_iter()
+            .filter_map(|source| feed(source, &ids, &folders, &unread))
+            .collect();
+    feeds.sort_by_key(|feed| feed.id);
+    let starred_count =
+        article_states::starred_count(user.username.clone(), &conn)?;
+    Ok(Json(Feeds {
+        feeds,
+        starred_count: Some(starred_count),
+        newest_item_id: newest_item_id(&user.username, &conn)?,
+    }))
+}
+
+#[post("/feeds", data = "<new_feed>")]
+pub fn create_feed(
+    conn: DbConn,
+    user: BasicAuth,
+    new_feed: Json<NewFeed>,
+) -> NextcloudResp<Feeds> {
+    let exists = sources::all_from_user(user.username.clone(), &conn)?
+        .iter()
+        .any(|source| source_url(source) == new_feed.url);
+    if exists {
+        return err(Status::Conflict, "Feed already exists");
+    }
+    let source = match detect_source(&new_feed.url, None, &user.username) {
+        Ok(source) => sources::insert(source, &conn)?,
+        Err(e) => return err(Status::UnprocessableEntity, e),
+    };
+    move_source(source.id, new_feed.folder_id, &user.username, &conn)?;
+
+    let ids = ShortIds::new(&user.username, &conn)?;
+    let folders = folder_ids(&user.username, &ids, &conn)?;
+    Ok(Json(Feeds {
+        feeds: feed(source, &ids, &folders, &HashMap::new())
+            .into_iter()
+            .collect(),
+        starred_count: None,
+        newest_item_id: newest_item_id(&user.username, &conn)?,
+    }))
+}
+
+#[delete("/feeds/<id>")]
+pub fn delete_feed(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+) -> Result<(), ApiError> {
+    let source = user_source(id, &user.username, &conn)?;
+    sources::delete(source.id, &conn)?;
+    Ok(())
+}
+
+#[post("/feeds/<id>/move", data = "<folder>")]
+pub fn move_feed(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    folder: Json<MoveFeed>,
+) -> Result<(), ApiError> {
+    let source = user_source(id, &user.username, &conn)?;
+    move_source(source.id, folder.folder_id, &user.username, &conn)
+}
+
+#[post("/feeds/<id>/rename", data = "<title>")]
+pub fn rename_feed(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    title: Json<RenameFeed>,
+) -> Result<(), ApiError> {
+    let mut source = user_source(id, &user.username, &conn)?;
+    source.title = title.into_inner().feed_title;
+    sources::update(&source, &conn)?;
+    Ok(())
+}
+
+#[post("/feeds/<id>/read", data = "<newest>")]
+pub fn mark_feed_read(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    newest: Json<NewestItem>,
+) -> Result<(), ApiError> {
+    let source = user_source(id, &user.username, &conn)?;
+    article_states::mark_read_through(
+        user.username,
+        Some(source.id),
+        None,
+        newest.newest_item_id,
+        &conn,
+    )?;
+    Ok(())
+}
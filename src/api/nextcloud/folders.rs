@@ -0,0 +1,145 @@
+use crate::{
+    api::{
+        greader::tags::find_label,
+        nextcloud::{err, BasicAuth, NewestItem, NextcloudResp},
+        v1::ApiError,
+        ShortIds,
+    },
+    db::{
+        article_states,
+        tags::{self, Tag},
+        DbConn,
+    },
+};
+use diesel::pg::PgConnection;
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Folder {
+    id: i64,
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Folders {
+    folders: Vec<Folder>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FolderName {
+    name: String,
+}
+
+/// The user's tag for a folder id.
+fn user_tag(
+    id: i64,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<Uuid, ApiError> {
+    match ShortIds::new(username, conn)?.tag(id) {
+        Some(tag) => Ok(tag),
+        None => err(Status::NotFound, "Folder does not exist"),
+    }
+}
+
+/// A folder name that's not empty & not already used.
+fn valid_name(
+    name: &str,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return err(Status::UnprocessableEntity, "Folder name is empty");
+    }
+    if find_label(name, username, conn)?.is_some() {
+        return err(Status::Conflict, "Folder already exists");
+    }
+    Ok(name.to_string())
+}
+
+#[get("/folders")]
+pub fn folders(conn: DbConn, user: BasicAuth) -> NextcloudResp<Folders> {
+    let ids = ShortIds::new(&user.username, &conn)?;
+    let mut folders: Vec<Folder> = tags::all_from_user(user.username, &conn)?
+        .into_iter()
+        .filter_map(|tag| {
+            Some(Folder {
+                id: *ids.tags.get(&tag.id)?,
+                name: tag.name,
+            })
+        })
+        .collect();
+    folders.sort_by_key(|folder| folder.id);
+    Ok(Json(Folders { folders }))
+}
+
+#[post("/folders", data = "<folder>")]
+pub fn create_folder(
+    conn: DbConn,
+    user: BasicAuth,
+    folder: Json<FolderName>,
+) -> NextcloudResp<Folders> {
+    let name = valid_name(&folder.name, &user.username, &conn)?;
+    let tag = tags::insert(
+        Tag {
+            id: Uuid::new_v4(),
+            name,
+            owner: user.username.clone(),
+        },
+        &conn,
+    )?;
+    let ids = ShortIds::new(&user.username, &conn)?;
+    Ok(Json(Folders {
+        folders: vec![Folder {
+            id: ids.tags[&tag.id],
+            name: tag.name,
+        }],
+    }))
+}
+
+/// Deletes the tag. Its sources are kept.
+#[delete("/folders/<id>")]
+pub fn delete_folder(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+) -> Result<(), ApiError> {
+    let tag = user_tag(id, &user.username, &conn)?;
+    tags::delete(tag, &conn)?;
+    Ok(())
+}
+
+#[put("/folders/<id>", data = "<folder>")]
+pub fn rename_folder(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    folder: Json<FolderName>,
+) -> Result<(), ApiError> {
+    let mut tag = tags::get(user_tag(id, &user.username, &conn)?, &conn)?;
+    tag.name = valid_name(&folder.name, &user.username, &conn)?;
+    tags::update(tag, &conn)?;
+    Ok(())
+}
+
+#[post("/folders/<id>/read", data = "<newest>")]
+pub fn mark_folder_read(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    newest: Json<NewestItem>,
+) -> Result<(), ApiError> {
+    let tag = user_tag(id, &user.username, &conn)?;
+    article_states::mark_read_through(
+        user.username,
+        None,
+        Some(tag),
+        newest.newest_item_id,
+        &conn,
+    )?;
+    Ok(())
+}
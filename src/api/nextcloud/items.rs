@@ -0,0 +1,320 @@
+use crate::{
+    api::{
+        nextcloud::{err, BasicAuth, NewestItem, NextcloudResp},
+        v1::ApiError,
+        Params, ShortIds,
+    },
+    db::{
+        article_states::{self, ArticleState, StateChange},
+        articles::{self, ArticleFilter, ShortIdRange},
+        DbConn,
+    },
+    sanitize::content_html,
+    timestamp::Timestamp,
+};
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::{http::Status, request::Form};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// `type` values for which items to list.
+const FEED: i64 = 0;
+const FOLDER: i64 = 1;
+const STARRED: i64 = 2;
+const ALL: i64 = 3;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Item {
+    id: i64,
+    guid: String,
+    guid_hash: String,
+    url: Option<String>,
+    title: Option<String>,
+    author: Option<String>,
+    pub_date: Option<i64>,
+    updated_date: Option<i64>,
+    body: String,
+    enclosure_mime: Option<String>,
+    enclosure_link: Option<String>,
+    media_thumbnail: Option<String>,
+    media_description: Option<String>,
+    feed_id: i64,
+    unread: bool,
+    starred: bool,
+    rtl: bool,
+    /// When it was added, or its state last changed, in seconds
+    last_modified: i64,
+    fingerprint: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Items {
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ItemIds {
+    item_ids: Vec<i64>,
+}
+
+/// The articles matching `type` & `id`: one feed, one folder, starred
+/// articles, or all of them.
+fn type_filter(
+    params: &Params,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<ArticleFilter, ApiError> {
+    let ids = ShortIds::new(username, conn)?;
+    let id = params.number("id").unwrap_or(0);
+    match params.number("type").unwrap_or(ALL) {
+        FEED => match ids.source(id) {
+            Some(source) => Ok(ArticleFilter {
+                source: Some(source),
+                ..ArticleFilter::default()
+            }),
+            None => err(Status::NotFound, "Feed does not exist"),
+        },
+        FOLDER => match ids.tag(id) {
+            Some(tag) => Ok(ArticleFilter {
+                tag: Some(tag),
+                ..ArticleFilter::default()
+            }),
+            None => err(Status::NotFound, "Folder does not exist"),
+        },
+        STARRED => Ok(ArticleFilter {
+            starred: Some(true),
+            ..ArticleFilter::default()
+        }),
+        ALL => Ok(ArticleFilter::default()),
+        kind => err(Status::BadRequest, format!("Unknown type {}", kind)),
+    }
+}
+
+/// `lastModified`, which newer clients send in microseconds.
+fn modified_since(last_modified: i64) -> Timestamp {
+    let seconds = if last_modified > 10_000_000_000 {
+        last_modified / 1_000_000
+    } else {
+        last_modified
+    };
+    Timestamp(time::Timespec::new(seconds, 0))
+}
+
+/// The items for `short_ids`, in that order.
+fn items(
+    short_ids: Vec<i64>,
+    username: &str,
+    conn: &PgConnection,
+) -> QueryResult<Vec<Item>> {
+    let ids = ShortIds::new(username, conn)?;
+    let uuids: HashMap<i64, Uuid> = articles::from_short_ids(&short_ids, conn)?
+        .into_iter()
+        .map(|s| (s.short_id, s.id))
+        .collect();
+    let found: Vec<Uuid> = uuids.values().cloned().collect();
+    let mut articles: HashMap<Uuid, _> = articles::all_with_ids(&found, conn)?
+        .into_iter()
+        .map(|article| (article.id, article))
+        .collect();
+    let added: HashMap<Uuid, Timestamp> = articles::added(&found, conn)?
+        .into_iter()
+        .map(|a| (a.id, a.added))
+        .collect();
+    let mut states: HashMap<Uuid, ArticleState> =
+        article_states::all_for_articles(&found, username.to_string(), conn)?
+            .into_iter()
+            .map(|s| (s.article, s))
+            .collect();
+
+    Ok(short_ids
+        .into_iter()
+        .filter_map(|short_id| {
+            let id = uuids.get(&short_id)?;
+            let article = articles.remove(id)?;
+            let feed_id = *ids.sources.get(&article.source)?;
+            let state = states.remove(id);
+            let added = added.get(id).map(|t| t.0.sec).unwrap_or(0);
+            let last_modified = match &state {
+                Some(state) => added.max(state.updated.0.sec),
+                None => added,
+            };
+            let enclosure = article.enclosures.0.first();
+            let body = content_html(&article)
+                .map(|html| html.into_owned())
+                .unwrap_or_default();
+            Some(Item {
+                id: short_id,
+                guid: article
+                    .id_from_source
+                    .clone()
+                    .unwrap_or_else(|| article.id.to_string()),
+                guid_hash: article.id.to_simple().to_string(),
+                url: article.links.article_url().map(|url| url.to_string()),
+                author: article
+                    .authors
+                    .0
+                    .iter()
+                    .find_map(|author| author.name.clone()),
+                pub_date: article.published.map(|p| p.0.sec),
+                updated_date: None,
+                enclosure_mime: enclosure.and_then(|e| e.mime_type.clone()),
+                enclosure_link: enclosure.map(|e| e.url.clone()),
+                media_thumbnail: article.thumbnail,
+                media_description: None,
+                body,
+                title: article.title,
+                feed_id,
+                unread: !state.as_ref().map(|s| s.read).unwrap_or(false),
+                starred: state.as_ref().map(|s| s.starred).unwrap_or(false),
+                rtl: false,
+                last_modified,
+                fingerprint: article.id.to_simple().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Apply `change` to the articles with these short ids.
+fn set_states(
+    short_ids: &[i64],
+    change: StateChange,
+    username: String,
+    conn: &PgConnection,
+) -> QueryResult<()> {
+    let articles: Vec<Uuid> = articles::from_short_ids(short_ids, conn)?
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    article_states::set(&articles, username, &change, conn)?;
+    Ok(())
+}
+
+fn state_change(action: &str) -> Result<StateChange, ApiError> {
+    match action {
+        "read" => Ok(StateChange::new(Some(true), None)),
+        "unread" => Ok(StateChange::new(Some(false), None)),
+        "star" => Ok(StateChange::new(None, Some(true))),
+        "unstar" => Ok(StateChange::new(None, Some(false))),
+        _ => err(Status::NotFound, format!("Unknown action {}", action)),
+    }
+}
+
+/// `batchSize` items (`-1` for all) of `type` & `id`, starting after the
+/// `offset` item. Newest first unless `oldestFirst`, & without read items
+/// if `getRead=false`.
+#[get("/items?<params..>")]
+pub fn items_list(
+    conn: DbConn,
+    user: BasicAuth,
+    params: Form<Params>,
+) -> NextcloudResp<Items> {
+    let mut filter = type_filter(&params, &user.username, &conn)?;
+    if params.get("getRead") == Some("false") {
+        filter.read = Some(false);
+    }
+    let oldest_first = params.get("oldestFirst") == Some("true");
+    let offset = params.number("offset").filter(|offset| *offset > 0);
+    let range = ShortIdRange {
+        after: if oldest_first { offset } else { None },
+        before: if oldest_first { None } else { offset },
+        newest_first: !oldest_first,
+        limit: params.number("batchSize").filter(|size| *size >= 0),
+        ..ShortIdRange::default()
+    };
+    let short_ids = articles::short_ids_from_user(
+        user.username.clone(),
+        &filter,
+        &range,
+        &conn,
+    )?
+    .into_iter()
+    .map(|s| s.short_id)
+    .collect();
+    Ok(Json(Items {
+        items: items(short_ids, &user.username, &conn)?,
+    }))
+}
+
+/// Items of `type` & `id` added, or read or starred, since
+/// `lastModified`, for syncing.
+#[get("/items/updated?<params..>")]
+pub fn items_updated(
+    conn: DbConn,
+    user: BasicAuth,
+    params: Form<Params>,
+) -> NextcloudResp<Items> {
+    let filter = type_filter(&params, &user.username, &conn)?;
+    let range = ShortIdRange {
+        modified_since: Some(modified_since(
+            params.number("lastModified").unwrap_or(0),
+        )),
+        ..ShortIdRange::default()
+    };
+    let short_ids = articles::short_ids_from_user(
+        user.username.clone(),
+        &filter,
+        &range,
+        &conn,
+    )?
+    .into_iter()
+    .map(|s| s.short_id)
+    .collect();
+    Ok(Json(Items {
+        items: items(short_ids, &user.username, &conn)?,
+    }))
+}
+
+/// `read`, `unread`, `star` or `unstar` one item.
+#[post("/items/<id>/<action>", rank = 2)]
+pub fn item_action(
+    conn: DbConn,
+    user: BasicAuth,
+    id: i64,
+    action: String,
+) -> Result<(), ApiError> {
+    set_states(&[id], state_change(&action)?, user.username, &conn)?;
+    Ok(())
+}
+
+/// `read`, `unread`, `star` or `unstar` each of `itemIds`.
+#[post("/items/<action>/multiple", data = "<ids>")]
+pub fn items_action(
+    conn: DbConn,
+    user: BasicAuth,
+    action: String,
+    ids: Json<ItemIds>,
+) -> Result<(), ApiError> {
+    set_states(&ids.item_ids, state_change(&action)?, user.username, &conn)?;
+    Ok(())
+}
+
+#[post("/items/read", data = "<newest>")]
+pub fn mark_all_read(
+    conn: DbConn,
+    user: BasicAuth,
+    newest: Json<NewestItem>,
+) -> Result<(), ApiError> {
+    article_states::mark_read_through(
+        user.username,
+        None,
+        None,
+        newest.newest_item_id,
+        &conn,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_modified() {
+        assert_eq!(modified_since(1_605_441_502).0.sec, 1_605_441_502);
+        assert_eq!(modified_since(1_605_441_502_123_456).0.sec, 1_605_441_502);
+    }
+}
@@ -1,12 +1,15 @@
 pub mod items;
+pub mod opml;
+pub mod output_feeds;
 pub mod sources;
+pub mod tags;
 pub mod users;
 
 use crate::db::{tokens, DbConn, Pool};
 use bcrypt::BcryptError;
 use rocket::{
-    http::Status,
-    request::{FromRequest, Outcome, Request},
+    http::{RawStr, Status},
+    request::{FromFormValue, FromParam, FromRequest, Outcome, Request},
     response::{status::Custom, Responder, Response},
     State,
 };
@@ -25,7 +28,7 @@ pub struct Resp<T> {
 #[derive(Debug)]
 pub struct ApiError(Custom<Json<Resp<String>>>);
 impl ApiError {
-    fn new(status: Status, contents: String) -> ApiError {
+    pub fn new(status: Status, contents: String) -> ApiError {
         ApiError(Custom(
             status,
             Json(Resp {
@@ -76,10 +79,39 @@ pub fn user_err_resp<U: Into<String>, T>(x: U) -> JSONResp<T> {
     Err(ApiError::new(Status::BadRequest, x.into()))
 }
 
+pub fn rate_limited_resp<U: Into<String>, T>(x: U) -> JSONResp<T> {
+    Err(ApiError::new(Status::TooManyRequests, x.into()))
+}
+
 pub fn internal_err_resp<U: Into<String>, T>(x: U) -> JSONResp<T> {
     Err(ApiError::new(Status::InternalServerError, x.into()))
 }
 
+/// FromParam/FromFormValue are not implemented on rocket_contrib's UUID, so
+/// this wrapper is used for UUIDs in paths & query strings
+#[derive(Debug, Clone, Copy)]
+pub struct UuidParam(pub Uuid);
+
+impl<'a> FromParam<'a> for UuidParam {
+    type Error = &'a RawStr;
+
+    fn from_param(param: &'a RawStr) -> Result<Self, Self::Error> {
+        Uuid::parse_str(param.as_str())
+            .map(UuidParam)
+            .map_err(|_| param)
+    }
+}
+
+impl<'v> FromFormValue<'v> for UuidParam {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        Uuid::parse_str(form_value.as_str())
+            .map(UuidParam)
+            .map_err(|_| form_value)
+    }
+}
+
 pub struct ValidToken {
     pub id: tokens::TokenId,
     pub username: String,
@@ -95,14 +127,19 @@ impl<'a, 'r> FromRequest<'a, 'r> for ValidToken {
             .headers()
             .get_one("Authorization")
             .and_then(|bearer| {
-                // This is kinda awful, but whatever
+                // This is kinda awful, but whatever. GReader clients send
+                // `GoogleLogin auth=<token>`.
                 let split_bearer: Vec<&str> =
                     bearer.split_ascii_whitespace().collect();
                 match split_bearer[..] {
-                    [_, token] => Uuid::parse_str(token).ok().map(|token| {
-                        log::debug!("Found header token {}", token);
-                        token
-                    }),
+                    [_, token] => {
+                        Uuid::parse_str(token.trim_start_matches("auth="))
+                            .ok()
+                            .map(|token| {
+                                log::debug!("Found header token {}", token);
+                                token
+                            })
+                    }
                     _ => None,
                 }
             })
@@ -1,32 +1,381 @@
-use atom_syndication::Feed;
-use rss::Channel;
-use std::io::BufReader;
-
-use log::debug;
-
-extern crate reqwest;
-
-#[get("/items")]
-pub fn index() -> String {
-    let resp = reqwest::blocking::get(
-        "https://www.benningtonbanner.com/category/local-news/browse.xml",
-    )
-    .unwrap()
-    .text()
-    .unwrap();
-
-    match Channel::read_from(BufReader::new(resp.as_bytes())) {
-        Ok(content) => return content.to_string(),
-        Err(e) => debug!("RSS Channel parse failed: {}", e),
+use crate::{
+    api::v1::{ok_resp, user_err_resp, JSONResp, UuidParam, ValidToken},
+    db::{
+        article_states::{self, ArticleState, StateChange, UnreadCount},
+        articles::{self, Article, ArticleFilter, Cursor},
+        sources, DbConn,
+    },
+    search,
+    timestamp::Timestamp,
+};
+
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::{http::RawStr, request::FromFormValue};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// An article, along with the user's state for it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArticleItem {
+    #[serde(flatten)]
+    pub article: Article,
+    pub read: bool,
+    pub starred: bool,
+    pub read_at: Option<Timestamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnreadCounts {
+    pub sources: HashMap<Uuid, i64>,
+    pub tags: HashMap<Uuid, i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArticlePage {
+    pub articles: Vec<ArticleItem>,
+    /// Pass as `cursor` to get the next page. `None` on the last page.
+    pub next_cursor: Option<String>,
+    pub unread: UnreadCounts,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArticleStatePayload {
+    pub ids: Vec<Uuid>,
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarkReadPayload {
+    pub source: Option<Uuid>,
+    pub tag: Option<Uuid>,
+    /// Articles published at or before this time are marked as read.
+    /// Undated articles go by when they were stored.
+    pub until: Timestamp,
+}
+
+impl<'v> FromFormValue<'v> for Cursor {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        form_value.as_str().parse().map_err(|_| form_value)
     }
+}
+
+/// An article's summary & content as the feed sent them, before sanitizing.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawArticle {
+    pub summary: Option<String>,
+    pub content: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub item: ArticleItem,
+    pub rank: f32,
+    /// Matching text, with matches wrapped in `<mark>` tags
+    pub snippet: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    /// Pass as `offset` to get the next page. `None` on the last page.
+    pub next_offset: Option<i64>,
+}
+
+/// Pair articles with the user's state for them, keeping their order.
+pub fn with_states(
+    articles: Vec<Article>,
+    username: String,
+    conn: &PgConnection,
+) -> QueryResult<Vec<ArticleItem>> {
+    let ids: Vec<Uuid> = articles.iter().map(|a| a.id).collect();
+    let mut states: HashMap<Uuid, ArticleState> =
+        article_states::all_for_articles(&ids, username, conn)?
+            .into_iter()
+            .map(|s| (s.article, s))
+            .collect();
+    Ok(articles
+        .into_iter()
+        .map(|article| {
+            let (read, starred, read_at) = states
+                .remove(&article.id)
+                .map(|s| (s.read, s.starred, s.read_at))
+                .unwrap_or((false, false, None));
+            ArticleItem {
+                article,
+                read,
+                starred,
+                read_at,
+            }
+        })
+        .collect())
+}
+
+fn to_map(counts: Vec<UnreadCount>) -> HashMap<Uuid, i64> {
+    counts.into_iter().map(|c| (c.id, c.unread)).collect()
+}
 
-    match Feed::read_from(BufReader::new(resp.as_bytes())) {
-        Ok(content) => {
-            debug!("{}", content.to_string());
-            return content.to_string();
-        }
-        Err(e) => debug!("Atom Feed parse failed: {}", e),
+#[get(
+    "/items?<source>&<tag>&<before>&<after>&<read>&<starred>&<cursor>&<limit>"
+)]
+#[allow(clippy::too_many_arguments)]
+pub fn items_list(
+    conn: DbConn,
+    token: ValidToken,
+    source: Option<UuidParam>,
+    tag: Option<UuidParam>,
+    before: Option<Timestamp>,
+    after: Option<Timestamp>,
+    read: Option<bool>,
+    starred: Option<bool>,
+    cursor: Option<Cursor>,
+    limit: Option<i64>,
+) -> JSONResp<ArticlePage> {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1).min(MAX_PAGE_SIZE);
+    let filter = ArticleFilter {
+        source: source.map(|s| s.0),
+        tag: tag.map(|t| t.0),
+        published_before: before,
+        published_after: after,
+        read,
+        starred,
+        ..ArticleFilter::default()
+    };
+    let articles = articles::all_from_user(
+        token.username.clone(),
+        &filter,
+        cursor,
+        limit,
+        &conn,
+    )?;
+    let next_cursor = if articles.len() as i64 == limit {
+        articles.last().map(|a| Cursor::after(a).to_string())
+    } else {
+        None
+    };
+
+    let articles = with_states(articles, token.username.clone(), &conn)?;
+
+    let unread = UnreadCounts {
+        sources: to_map(article_states::unread_by_source(
+            token.username.clone(),
+            &conn,
+        )?),
+        tags: to_map(article_states::unread_by_tag(token.username, &conn)?),
+    };
+
+    ok_resp(ArticlePage {
+        articles,
+        next_cursor,
+        unread,
+    })
+}
+
+/// Search the user's articles. See `crate::search` for the query syntax.
+#[get("/items/search?<q>&<source>&<tag>&<offset>&<limit>")]
+pub fn items_search(
+    conn: DbConn,
+    token: ValidToken,
+    q: String,
+    source: Option<UuidParam>,
+    tag: Option<UuidParam>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> JSONResp<SearchPage> {
+    let tsquery = match search::to_tsquery(&q) {
+        Some(tsquery) => tsquery,
+        None => return user_err_resp("Nothing to search for"),
+    };
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1).min(MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let hits = articles::search(
+        token.username.clone(),
+        &tsquery,
+        source.map(|s| s.0),
+        tag.map(|t| t.0),
+        limit,
+        offset,
+        &conn,
+    )?;
+    let next_offset = if hits.len() as i64 == limit {
+        Some(offset + limit)
+    } else {
+        None
+    };
+
+    let ids: Vec<Uuid> = hits.iter().map(|h| h.id).collect();
+    let mut items: HashMap<Uuid, ArticleItem> = with_states(
+        articles::all_with_ids(&ids, &conn)?,
+        token.username,
+        &conn,
+    )?
+    .into_iter()
+    .map(|item| (item.article.id, item))
+    .collect();
+    let results = hits
+        .into_iter()
+        .filter_map(|hit| {
+            items.remove(&hit.id).map(|item| SearchResult {
+                item,
+                rank: hit.rank,
+                snippet: hit.snippet,
+            })
+        })
+        .collect();
+
+    ok_resp(SearchPage {
+        results,
+        next_offset,
+    })
+}
+
+/// For debugging sanitizing. `None` for articles stored before sanitizing
+/// was added.
+#[get("/items/<id>/raw")]
+pub fn items_raw(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+) -> JSONResp<RawArticle> {
+    let article = articles::get(id.0, &conn)?;
+    if sources::get(article.source, &conn)?.creator != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to view article {}",
+            article.id
+        ));
+    }
+    ok_resp(RawArticle {
+        summary: article.raw_summary,
+        content: article.raw_content,
+    })
+}
+
+#[put("/items/state", data = "<payload>")]
+pub fn items_state_update(
+    conn: DbConn,
+    token: ValidToken,
+    payload: Json<ArticleStatePayload>,
+) -> JSONResp<Vec<ArticleState>> {
+    let p = payload.into_inner();
+    if p.read.is_none() && p.starred.is_none() {
+        return user_err_resp("Nothing to update: set read and/or starred");
+    }
+    let states = article_states::set(
+        &p.ids,
+        token.username,
+        &StateChange::new(p.read, p.starred),
+        &conn,
+    )?;
+    ok_resp(states)
+}
+
+#[post("/items/mark_read", data = "<payload>")]
+pub fn items_mark_read(
+    conn: DbConn,
+    token: ValidToken,
+    payload: Json<MarkReadPayload>,
+) -> JSONResp<String> {
+    let p = payload.into_inner();
+    let marked = article_states::mark_read_until(
+        token.username,
+        p.source,
+        p.tag,
+        p.until,
+        &conn,
+    )?;
+    ok_resp(format!("Marked {} articles as read", marked))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::{
+            article_states,
+            articles::{self, Article},
+        },
+        testing::{self, article},
+        timestamp::Timestamp,
+    };
+    use rocket::http::{ContentType, Status};
+    use serde_json::json;
+
+    #[test]
+    fn api_mark_read_undated() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("mark-read", "hunter22", &conn);
+        let undated = articles::insert(
+            Article {
+                source,
+                title: Some("Undated".into()),
+                ..article()
+            },
+            &conn,
+        )
+        .unwrap();
+        let auth = testing::login(&client, &username, "hunter22");
+
+        let response = client
+            .post("/api/v1/items/mark_read")
+            .header(auth)
+            .header(ContentType::JSON)
+            .body(
+                json!({
+                    "source": source,
+                    "until": Timestamp::now() + time::Duration::minutes(1),
+                })
+                .to_string(),
+            )
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let state =
+            article_states::get(undated.id, username.clone(), &conn).unwrap();
+        assert!(state.read);
+
+        testing::remove_user(username, &conn);
     }
 
-    String::from("No format was compatable")
+    #[test]
+    fn api_search_snippet() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("search", "hunter22", &conn);
+        articles::insert(
+            Article {
+                source,
+                title: Some("Tagged".into()),
+                summary: Some("<p>A <b>summary</b> with tags</p>".into()),
+                ..article()
+            },
+            &conn,
+        )
+        .unwrap();
+        let auth = testing::login(&client, &username, "hunter22");
+
+        let mut response = client
+            .get("/api/v1/items/search?q=summary")
+            .header(auth)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = testing::json(&mut response);
+        let snippet = body["contents"]["results"][0]["snippet"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(snippet.contains("<mark>summary</mark>"), "{}", snippet);
+        assert!(!snippet.contains("&lt;"), "{}", snippet);
+
+        testing::remove_user(username, &conn);
+    }
 }
@@ -0,0 +1,202 @@
+use crate::{
+    api::v1::{ok_resp, user_err_resp, ApiError, JSONResp, ValidToken},
+    db::{
+        sources::{self, Source, SourceData},
+        tagged_sources::{self, TaggedSource},
+        tags::{self, Tag},
+        DbConn,
+    },
+    opml::{self, OpmlFeed, OpmlFolder},
+    sources::rssatom::RSSAtom,
+};
+
+use diesel::{pg::PgConnection, prelude::*};
+use rocket::{response::content, Data};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::Read,
+};
+use uuid::Uuid;
+
+/// Largest OPML document accepted for import, in bytes
+const OPML_SIZE_LIMIT: u64 = 5 * 1024 * 1024;
+
+/// Outcome of importing a single feed from an OPML document.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpmlImportResult {
+    pub xml_url: String,
+    pub source: Option<Source>,
+    pub error: Option<String>,
+}
+
+/// Create a source for `feed`, tagging it with each of its folders.
+///
+/// `tag_ids` maps the user's tag names to IDs, and is updated with any
+/// tags created along the way.
+fn import_feed(
+    feed: &OpmlFeed,
+    username: &str,
+    tag_ids: &mut HashMap<String, Uuid>,
+    conn: &PgConnection,
+) -> Result<Source, String> {
+    match reqwest::Url::parse(&feed.xml_url) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => (),
+        _ => return Err(format!("Invalid feed URL {}", feed.xml_url)),
+    }
+
+    let mut new_tags = Vec::new();
+    let source = conn
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let id = Uuid::new_v4();
+            let source_data =
+                SourceData::RSSAtom(RSSAtom::new(feed.xml_url.to_owned(), id));
+            let source = sources::insert(
+                Source::new(
+                    Some(id),
+                    feed.title.to_owned().unwrap_or_default(),
+                    serde_json::to_value(source_data).unwrap(),
+                    "".to_string(),
+                    username.to_string(),
+                ),
+                conn,
+            )?;
+
+            for name in &feed.tags {
+                let tag_id = match tag_ids.get(name) {
+                    Some(tag_id) => *tag_id,
+                    None => {
+                        let tag = tags::insert(
+                            Tag {
+                                id: Uuid::new_v4(),
+                                name: name.to_owned(),
+                                owner: username.to_string(),
+                            },
+                            conn,
+                        )?;
+                        new_tags.push((tag.name, tag.id));
+                        tag.id
+                    }
+                };
+                tagged_sources::insert(
+                    TaggedSource {
+                        id: Uuid::new_v4(),
+                        tag: tag_id,
+                        source: source.id,
+                    },
+                    conn,
+                )?;
+            }
+            Ok(source)
+        })
+        .map_err(|e| format!("Could not create source: {}", e))?;
+
+    // Only remember new tags once they've been committed
+    tag_ids.extend(new_tags);
+    Ok(source)
+}
+
+/// Import sources & tags from an OPML document.
+///
+/// Feeds are imported independently: each feed's result is reported, and
+/// a failure on one feed does not stop the others.
+#[post("/opml", data = "<data>")]
+pub fn opml_import(
+    conn: DbConn,
+    token: ValidToken,
+    data: Data,
+) -> JSONResp<Vec<OpmlImportResult>> {
+    let mut body = String::new();
+    if let Err(e) = data.open().take(OPML_SIZE_LIMIT).read_to_string(&mut body)
+    {
+        return user_err_resp(format!("Could not read OPML: {}", e));
+    }
+    let feeds = match opml::parse(&body) {
+        Ok(feeds) => feeds,
+        Err(e) => return user_err_resp(format!("Invalid OPML: {}", e)),
+    };
+
+    let existing_urls: HashSet<String> =
+        sources::all_from_user(token.username.clone(), &conn)?
+            .into_iter()
+            .filter_map(|s| {
+                serde_json::from_value::<SourceData>(s.source_data).ok()
+            })
+            .map(|data| data.url().to_string())
+            .collect();
+    let mut tag_ids: HashMap<String, Uuid> =
+        tags::all_from_user(token.username.clone(), &conn)?
+            .into_iter()
+            .map(|tag| (tag.name, tag.id))
+            .collect();
+
+    let results = feeds
+        .into_iter()
+        .map(|feed| {
+            let result = if existing_urls.contains(&feed.xml_url) {
+                Err(format!("Already subscribed to {}", feed.xml_url))
+            } else {
+                import_feed(&feed, &token.username, &mut tag_ids, &conn)
+            };
+            match result {
+                Ok(source) => OpmlImportResult {
+                    xml_url: feed.xml_url,
+                    source: Some(source),
+                    error: None,
+                },
+                Err(e) => OpmlImportResult {
+                    xml_url: feed.xml_url,
+                    source: None,
+                    error: Some(e),
+                },
+            }
+        })
+        .collect();
+    ok_resp(results)
+}
+
+fn opml_feed(source: &Source) -> Option<(String, String)> {
+    serde_json::from_value::<SourceData>(source.source_data.to_owned())
+        .ok()
+        .map(|data| (source.title.to_owned(), data.url().to_string()))
+}
+
+/// Export the user's sources as OPML, with a folder per tag.
+///
+/// Sources with several tags appear in each of their folders. Untagged
+/// sources are at the top level.
+#[get("/opml")]
+pub fn opml_export(
+    conn: DbConn,
+    token: ValidToken,
+) -> Result<content::Xml<String>, ApiError> {
+    let sources = sources::all_from_user(token.username.clone(), &conn)?;
+
+    let mut tagged = HashSet::new();
+    let mut folders = Vec::new();
+    for tag in tags::all_from_user(token.username.clone(), &conn)? {
+        let name = tag.name.to_owned();
+        let tag_sources = sources::all_from_tag(tag, &conn)?;
+        tagged.extend(tag_sources.iter().map(|s| s.id));
+        folders.push(OpmlFolder {
+            name: Some(name),
+            feeds: tag_sources.iter().filter_map(opml_feed).collect(),
+        });
+    }
+    folders.insert(
+        0,
+        OpmlFolder {
+            name: None,
+            feeds: sources
+                .iter()
+                .filter(|s| !tagged.contains(&s.id))
+                .filter_map(opml_feed)
+                .collect(),
+        },
+    );
+
+    Ok(content::Xml(opml::generate(
+        &format!("speedwagon subscriptions for {}", token.username),
+        &folders,
+    )))
+}
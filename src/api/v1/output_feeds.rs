@@ -0,0 +1,336 @@
+use crate::{
+    api::{
+        source_url,
+        v1::{
+            ok_resp, user_err_resp, ApiError, JSONResp, UuidParam, ValidToken,
+        },
+    },
+    db::{
+        articles,
+        output_feeds::{self, OutputFeed},
+        sources, tags, DbConn,
+    },
+    output::{OutputFormat, OutputInfo},
+    timestamp::Timestamp,
+};
+
+use diesel::{pg::PgConnection, result::OptionalExtension};
+use rocket::{
+    http::{ContentType, Status},
+    response::content::Content,
+};
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Most articles in an output feed, newest first.
+const OUTPUT_SIZE: i64 = 50;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputFeedCreatePayload {
+    /// Defaults to the source's title or tag's name
+    #[serde(default)]
+    pub title: String,
+    pub source: Option<Uuid>,
+    pub tag: Option<Uuid>,
+    #[serde(default)]
+    pub starred: bool,
+    #[serde(default)]
+    pub public: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputFeedUpdatePayload {
+    pub id: Uuid,
+    pub title: String,
+    pub public: bool,
+}
+
+/// FromData is not implemented on rocket_contrib's UUID, so
+/// this JSON payload is used
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputFeedIDPayload {
+    pub id: Uuid,
+}
+
+fn not_found<T>() -> Result<T, ApiError> {
+    Err(ApiError::new(Status::NotFound, "Not found".into()))
+}
+
+/// The user's output feed with this id.
+fn user_feed(
+    id: Uuid,
+    username: &str,
+    conn: &PgConnection,
+) -> Result<OutputFeed, ApiError> {
+    match output_feeds::get(id, conn).optional()? {
+        Some(feed) if feed.owner == username => Ok(feed),
+        _ => Err(ApiError::new(
+            Status::BadRequest,
+            format!("Unauthorized to view output feed {}", id),
+        )),
+    }
+}
+
+/// The feed's newest articles, as `format`.
+fn render(
+    feed: &OutputFeed,
+    format: &str,
+    conn: &PgConnection,
+) -> Result<Content<String>, ApiError> {
+    let format = match OutputFormat::from_name(format) {
+        Some(format) => format,
+        None => return not_found(),
+    };
+    let (feed_sources, link) = match (feed.source, feed.tag) {
+        (Some(source), _) => {
+            let source = sources::get(source, conn)?;
+            // The feed's URL until a fetch finds the site's
+            let link = source
+                .site_link
+                .clone()
+                .unwrap_or_else(|| source_url(&source));
+            (vec![source], Some(link))
+        }
+        (None, Some(tag)) => {
+            (sources::all_from_tag(tags::get(tag, conn)?, conn)?, None)
+        }
+        (None, None) => {
+            (sources::all_from_user(feed.owner.clone(), conn)?, None)
+        }
+    };
+    let info = OutputInfo {
+        id: feed.id,
+        title: feed.title.clone(),
+        link,
+        updated: feed_sources
+            .iter()
+            .map(|source| source.last_post)
+            .max_by_key(|last_post| last_post.0.sec)
+            .unwrap_or_else(Timestamp::now),
+    };
+    let articles = articles::all_from_user(
+        feed.owner.clone(),
+        &feed.filter(),
+        None,
+        OUTPUT_SIZE,
+        conn,
+    )?;
+    let (top, sub) = format.mime_type();
+    Ok(Content(
+        ContentType::new(top, sub),
+        format.render(&info, &articles),
+    ))
+}
+
+#[get("/output")]
+pub fn output_feeds_list(
+    conn: DbConn,
+    token: ValidToken,
+) -> JSONResp<Vec<OutputFeed>> {
+    ok_resp(output_feeds::all_from_user(token.username, &conn)?)
+}
+
+#[post("/output", data = "<feed>")]
+pub fn output_feed_create(
+    conn: DbConn,
+    token: ValidToken,
+    feed: Json<OutputFeedCreatePayload>,
+) -> JSONResp<OutputFeed> {
+    let f = feed.into_inner();
+    let default_title = match (f.source, f.tag) {
+        (Some(_), Some(_)) => {
+            return user_err_resp("Output feeds can't have a source and tag")
+        }
+        (Some(source), None) => {
+            let source = sources::get(source, &conn)?;
+            if source.creator != token.username {
+                return user_err_resp(format!(
+                    "Unauthorized to view source {}",
+                    source.id
+                ));
+            }
+            source.title
+        }
+        (None, Some(tag)) => {
+            let tag = tags::get(tag, &conn)?;
+            if tag.owner != token.username {
+                return user_err_resp(format!(
+                    "Unauthorized to view tag {}",
+                    tag.id
+                ));
+            }
+            tag.name
+        }
+        (None, None) if f.starred => "Starred".to_string(),
+        (None, None) => {
+            return user_err_resp(
+                "Output feeds need a source, tag, or to be starred",
+            )
+        }
+    };
+    let title = if f.title.trim().is_empty() {
+        default_title
+    } else {
+        f.title
+    };
+    ok_resp(output_feeds::insert(
+        OutputFeed {
+            id: Uuid::new_v4(),
+            owner: token.username,
+            title,
+            source: f.source,
+            tag: f.tag,
+            starred: f.starred,
+            public: f.public,
+            token: Uuid::new_v4(),
+        },
+        &conn,
+    )?)
+}
+
+#[put("/output", data = "<feed>")]
+pub fn output_feed_update(
+    conn: DbConn,
+    token: ValidToken,
+    feed: Json<OutputFeedUpdatePayload>,
+) -> JSONResp<OutputFeed> {
+    let f = feed.into_inner();
+    let mut old_feed = user_feed(f.id, &token.username, &conn)?;
+    old_feed.title = f.title;
+    old_feed.public = f.public;
+    ok_resp(output_feeds::update(&old_feed, &conn)?)
+}
+
+#[delete("/output", data = "<feed>")]
+pub fn output_feed_delete(
+    conn: DbConn,
+    token: ValidToken,
+    feed: Json<OutputFeedIDPayload>,
+) -> JSONResp<String> {
+    let feed = user_feed(feed.into_inner().id, &token.username, &conn)?;
+    output_feeds::delete(feed.id, &conn)?;
+    ok_resp(format!("Successfully deleted output feed {}", feed.id))
+}
+
+/// Replace the feed's token, so its old public URL stops working.
+#[post("/output/<id>/token")]
+pub fn output_feed_revoke(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+) -> JSONResp<OutputFeed> {
+    let mut feed = user_feed(id.0, &token.username, &conn)?;
+    feed.token = Uuid::new_v4();
+    ok_resp(output_feeds::update(&feed, &conn)?)
+}
+
+/// An output feed, for its owner. `format` is `atom`, `rss` or `json`.
+#[get("/output/<id>/<format>")]
+pub fn output_feed_private(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+    format: String,
+) -> Result<Content<String>, ApiError> {
+    let feed = user_feed(id.0, &token.username, &conn)?;
+    render(&feed, &format, &conn)
+}
+
+/// A public output feed, for anyone with its token.
+#[get("/output/public/<feed_token>/<format>")]
+pub fn output_feed_public(
+    conn: DbConn,
+    feed_token: UuidParam,
+    format: String,
+) -> Result<Content<String>, ApiError> {
+    match output_feeds::get_by_token(feed_token.0, &conn).optional()? {
+        Some(feed) if feed.public => render(&feed, &format, &conn),
+        _ => not_found(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing;
+    use rocket::{
+        http::{ContentType, Status},
+        local::LocalRequest,
+    };
+    use serde_json::{json, Value};
+
+    fn send(request: LocalRequest) -> (Status, String) {
+        let mut response = request.dispatch();
+        (
+            response.status(),
+            response.body_string().unwrap_or_default(),
+        )
+    }
+
+    #[test]
+    fn api_output_feed_tokens() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("output", "hunter22", &conn);
+
+        let auth = testing::login(&client, &username, "hunter22");
+
+        let (status, body) = send(
+            client
+                .post("/api/v1/output")
+                .header(auth.clone())
+                .header(ContentType::JSON)
+                .body(json!({ "source": source }).to_string()),
+        );
+        assert_eq!(status, Status::Ok);
+        let feed: Value = serde_json::from_str(&body).unwrap();
+        let feed = &feed["contents"];
+        assert_eq!(feed["title"], "Example Blog");
+        assert_eq!(feed["public"], false);
+        let id = feed["id"].as_str().unwrap().to_string();
+        let public_url = format!(
+            "/api/v1/output/public/{}/atom",
+            feed["token"].as_str().unwrap()
+        );
+
+        // Private feeds are only for their owner
+        let (status, body) = send(
+            client
+                .get(format!("/api/v1/output/{}/rss", id))
+                .header(auth.clone()),
+        );
+        assert_eq!(status, Status::Ok);
+        assert!(body.contains("<title>Second post</title>"));
+        assert!(body.contains("<link>https://example.org/</link>"));
+        let (status, _) = send(client.get(public_url.clone()));
+        assert_eq!(status, Status::NotFound);
+
+        let (status, _) = send(
+            client
+                .put("/api/v1/output")
+                .header(auth.clone())
+                .header(ContentType::JSON)
+                .body(
+                    json!({ "id": id, "title": "Shared", "public": true })
+                        .to_string(),
+                ),
+        );
+        assert_eq!(status, Status::Ok);
+        let (status, body) = send(client.get(public_url.clone()));
+        assert_eq!(status, Status::Ok);
+        assert!(body.contains("<title>Shared</title>"));
+
+        // A new token revokes the old URL
+        let (status, _) = send(
+            client
+                .post(format!("/api/v1/output/{}/token", id))
+                .header(auth.clone()),
+        );
+        assert_eq!(status, Status::Ok);
+        let (status, _) = send(client.get(public_url));
+        assert_eq!(status, Status::NotFound);
+
+        testing::remove_user(username, &conn);
+    }
+}
@@ -1,20 +1,141 @@
 use crate::{
-    api::v1::{ok_resp, user_err_resp, JSONResp, ValidToken},
+    api::v1::{
+        ok_resp, rate_limited_resp, user_err_resp, JSONResp, UuidParam,
+        ValidToken,
+    },
     db::{
+        article_states,
+        articles::Article,
+        fetch_attempts::{self, FetchAttempt},
+        source_events::{self, SourceEvent},
+        source_refreshes,
         sources::{self, Source, SourceData},
         users, DbConn,
     },
+    fetch::{error_category, error_status, fetch_from_source},
+    post_filter::PostFilter,
+    sources::{
+        detect,
+        discovery::FeedCandidate,
+        full_content,
+        http::{FetchCache, Fetched},
+        rssatom::{FeedFormat, ParsedFeed},
+        Detected,
+    },
+    state::RefreshLimiter,
 };
 
+use rocket::State;
 use rocket_contrib::{self, json::Json};
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, error::Error};
 use uuid::Uuid;
 
+/// Articles in a source preview, unless the payload asks for more
+const PREVIEW_LIMIT: usize = 10;
+const MAX_PREVIEW_LIMIT: usize = 50;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct SourceCreatePayload {
     pub title: Option<String>,
-    pub source_data: SourceData,
+    /// What to fetch. If missing, it's detected from `url`.
+    pub source_data: Option<SourceData>,
+    pub url: Option<String>,
     pub post_filter: String,
+    pub min_fetch_interval: Option<i32>,
+    pub max_fetch_interval: Option<i32>,
+    pub adaptive_fetch: Option<bool>,
+    pub retention_max_age: Option<i32>,
+    pub retention_max_count: Option<i32>,
+    pub retention_keep_starred: Option<bool>,
+    #[serde(default)]
+    pub fetch_full_content: bool,
+    pub content_selector: Option<String>,
+    /// Refuse to create the source unless it can be fetched & parsed
+    #[serde(default)]
+    pub validate: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourcePreviewPayload {
+    /// What to fetch. If missing, it's detected from `url`.
+    pub source_data: Option<SourceData>,
+    pub url: Option<String>,
+    pub post_filter: Option<String>,
+    pub limit: Option<usize>,
+}
+
+/// Why a source couldn't be fetched.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceError {
+    /// One of the `fetch_attempts::ERROR_*` categories
+    pub category: String,
+    pub http_status: Option<u16>,
+    pub message: String,
+}
+
+impl SourceError {
+    fn new(e: &(dyn Error + 'static)) -> SourceError {
+        SourceError {
+            category: error_category(e).to_string(),
+            http_status: error_status(e),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// What a source would give, if it was saved.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourcePreview {
+    pub source_data: Option<SourceData>,
+    pub format: Option<FeedFormat>,
+    pub title: Option<String>,
+    pub articles: Vec<Article>,
+    /// Feeds to pick from, if `url` was a page linking to several
+    pub candidates: Vec<FeedCandidate>,
+    pub error: Option<SourceError>,
+}
+
+fn check_fetch_intervals(source: &Source) -> Result<(), String> {
+    if source.min_fetch_interval < 1 {
+        return Err("min_fetch_interval must be at least 1 minute".into());
+    }
+    if source.max_fetch_interval < source.min_fetch_interval {
+        return Err(
+            "max_fetch_interval must be at least min_fetch_interval".into()
+        );
+    }
+    Ok(())
+}
+
+fn check_retention(source: &Source) -> Result<(), String> {
+    if source.retention_max_age.map(|age| age < 0).unwrap_or(false) {
+        return Err("retention_max_age can't be negative".into());
+    }
+    if source
+        .retention_max_count
+        .map(|count| count < 0)
+        .unwrap_or(false)
+    {
+        return Err("retention_max_count can't be negative".into());
+    }
+    Ok(())
+}
+
+fn check_content_selector(source: &Source) -> Result<(), String> {
+    match &source.content_selector {
+        Some(selector) => full_content::parse_selector(selector).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// The new source, or if `url` was a page linking to several feeds, those
+/// feeds to pick from.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SourceCreated {
+    Source(Source),
+    Candidates { candidates: Vec<FeedCandidate> },
 }
 
 /// FromData is not implemented on rocket_contrib's UUID, so
@@ -24,10 +145,32 @@ pub struct SourceIDPayload {
     pub id: Uuid,
 }
 
+/// A source, along with the number of articles the user hasn't read.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SourceListing {
+    #[serde(flatten)]
+    pub source: Source,
+    pub unread: i64,
+}
+
 #[get("/source")]
-pub fn sources_list(conn: DbConn, token: ValidToken) -> JSONResp<Vec<Source>> {
+pub fn sources_list(
+    conn: DbConn,
+    token: ValidToken,
+) -> JSONResp<Vec<SourceListing>> {
     let user = users::get(token.username, &conn)?;
-    let sources = sources::all_from_user(user.username, &conn)?;
+    let mut unread: HashMap<Uuid, i64> =
+        article_states::unread_by_source(user.username.clone(), &conn)?
+            .into_iter()
+            .map(|c| (c.id, c.unread))
+            .collect();
+    let sources = sources::all_from_user(user.username, &conn)?
+        .into_iter()
+        .map(|source| SourceListing {
+            unread: unread.remove(&source.id).unwrap_or(0),
+            source,
+        })
+        .collect();
     ok_resp(sources)
 }
 
@@ -45,28 +188,176 @@ pub fn source_update(
             source.id
         ));
     }
-    let updated_source = sources::update(&source.into_inner(), &conn)?;
+    if let Err(e) = PostFilter::parse(&source.post_filter) {
+        return user_err_resp(format!("Invalid post_filter {}", e));
+    }
+    if let Err(e) = check_fetch_intervals(&source) {
+        return user_err_resp(e);
+    }
+    if let Err(e) = check_retention(&source) {
+        return user_err_resp(e);
+    }
+    if let Err(e) = check_content_selector(&source) {
+        return user_err_resp(e);
+    }
+    let mut source = source.into_inner();
+    // Only fetching finds the site's link
+    source.site_link = old_source.site_link;
+    let updated_source = sources::update(&source, &conn)?;
     ok_resp(updated_source)
 }
 
+/// Fetch & parse a source, like the worker would.
+fn fetch_feed(source_data: &SourceData) -> crate::Result<ParsedFeed> {
+    match fetch_from_source(source_data, &FetchCache::default())?.fetched {
+        Fetched::Modified(feed, _) => Ok(feed),
+        Fetched::NotModified => {
+            Err("Source responded 304 Not Modified to an unconditional \
+                 request"
+                .into())
+        }
+    }
+}
+
+/// Fetch a source without saving it, to check it works.
+///
+/// Fetch errors are part of the preview, rather than an error response.
+#[post("/source/preview", data = "<preview>")]
+pub fn source_preview(
+    _token: ValidToken,
+    preview: Json<SourcePreviewPayload>,
+) -> JSONResp<SourcePreview> {
+    let p = preview.into_inner();
+    let post_filter =
+        match PostFilter::parse(p.post_filter.as_deref().unwrap_or("")) {
+            Ok(post_filter) => post_filter,
+            Err(e) => {
+                return user_err_resp(format!("Invalid post_filter {}", e))
+            }
+        };
+    let limit = p.limit.unwrap_or(PREVIEW_LIMIT).min(MAX_PREVIEW_LIMIT);
+
+    let mut result = SourcePreview {
+        source_data: None,
+        format: None,
+        title: None,
+        articles: Vec::new(),
+        candidates: Vec::new(),
+        error: None,
+    };
+    let fetched = match (p.source_data, p.url) {
+        (Some(source_data), _) => {
+            fetch_feed(&source_data).map(|feed| (source_data, feed))
+        }
+        (None, Some(url)) => match detect(&url, Uuid::new_v4()) {
+            Ok(Detected::Feed(detected)) => {
+                Ok((detected.source_data, detected.feed))
+            }
+            Ok(Detected::Candidates(candidates)) => {
+                result.candidates = candidates;
+                return ok_resp(result);
+            }
+            Err(e) => Err(e),
+        },
+        (None, None) => {
+            return user_err_resp("One of source_data or url is required")
+        }
+    };
+
+    match fetched {
+        Ok((source_data, mut feed)) => {
+            feed.articles.retain(|article| post_filter.matches(article));
+            feed.articles.truncate(limit);
+            result.source_data = Some(source_data);
+            result.format = Some(feed.format);
+            result.title = feed.title;
+            result.articles = feed.articles;
+        }
+        Err(e) => result.error = Some(SourceError::new(&*e)),
+    }
+    ok_resp(result)
+}
+
 #[post("/source", data = "<source>")]
 pub fn source_create(
     conn: DbConn,
     token: ValidToken,
     source: Json<SourceCreatePayload>,
-) -> JSONResp<Source> {
+) -> JSONResp<SourceCreated> {
     let s = source.into_inner();
-    let new_source = sources::insert(
-        Source::new(
-            None,
-            s.title.unwrap_or_else(|| "".to_string()),
-            serde_json::to_value(s.source_data).unwrap(),
-            s.post_filter,
-            token.username,
-        ),
-        &conn,
-    )?;
-    ok_resp(new_source)
+    if let Err(e) = PostFilter::parse(&s.post_filter) {
+        return user_err_resp(format!("Invalid post_filter {}", e));
+    }
+    let id = Uuid::new_v4();
+    let (source_data, feed_title) = match (s.source_data, s.url) {
+        (Some(source_data), _) => {
+            let feed_title = if s.validate || s.title.is_none() {
+                match fetch_feed(&source_data) {
+                    Ok(feed) => feed.title,
+                    Err(e) if s.validate => {
+                        return user_err_resp(format!(
+                            "Source did not validate ({}): {}",
+                            error_category(&*e),
+                            e
+                        ))
+                    }
+                    // Only needed for the title, so this isn't an error
+                    Err(_) => None,
+                }
+            } else {
+                None
+            };
+            (source_data, feed_title)
+        }
+        (None, Some(url)) => match detect(&url, id) {
+            Ok(Detected::Feed(detected)) => {
+                (detected.source_data, detected.feed.title)
+            }
+            Ok(Detected::Candidates(candidates)) => {
+                return ok_resp(SourceCreated::Candidates { candidates })
+            }
+            Err(e) => {
+                return user_err_resp(format!("Could not fetch {}: {}", url, e))
+            }
+        },
+        (None, None) => {
+            return user_err_resp("One of source_data or url is required")
+        }
+    };
+    let mut new_source = Source::new(
+        Some(id),
+        s.title
+            .or(feed_title)
+            .map(|title| title.trim().to_string())
+            .unwrap_or_else(|| "".to_string()),
+        serde_json::to_value(source_data).unwrap(),
+        s.post_filter,
+        token.username,
+    );
+    if let Some(min) = s.min_fetch_interval {
+        new_source.min_fetch_interval = min;
+    }
+    if let Some(max) = s.max_fetch_interval {
+        new_source.max_fetch_interval = max;
+    }
+    if let Some(adaptive) = s.adaptive_fetch {
+        new_source.adaptive_fetch = adaptive;
+    }
+    new_source.retention_max_age = s.retention_max_age;
+    new_source.retention_max_count = s.retention_max_count;
+    new_source.retention_keep_starred = s.retention_keep_starred;
+    new_source.fetch_full_content = s.fetch_full_content;
+    new_source.content_selector = s.content_selector;
+    if let Err(e) = check_fetch_intervals(&new_source) {
+        return user_err_resp(e);
+    }
+    if let Err(e) = check_retention(&new_source) {
+        return user_err_resp(e);
+    }
+    if let Err(e) = check_content_selector(&new_source) {
+        return user_err_resp(e);
+    }
+    ok_resp(SourceCreated::Source(sources::insert(new_source, &conn)?))
 }
 
 #[delete("/source", data = "<source>")]
@@ -89,3 +380,116 @@ pub fn source_delete(
         source_to_delete.id
     ))
 }
+
+/// Changes the fetcher made to a source on its own (ex: following a
+/// permanent redirect), newest first.
+#[get("/source/<id>/events")]
+pub fn source_events_list(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+) -> JSONResp<Vec<SourceEvent>> {
+    let source = sources::get(id.0, &conn)?;
+    if source.creator != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to view source {}",
+            source.id
+        ));
+    }
+    ok_resp(source_events::all_from_source(source.id, &conn)?)
+}
+
+/// The source's recent fetch attempts, newest first.
+#[get("/source/<id>/fetches")]
+pub fn source_fetches_list(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+) -> JSONResp<Vec<FetchAttempt>> {
+    let source = sources::get(id.0, &conn)?;
+    if source.creator != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to view source {}",
+            source.id
+        ));
+    }
+    ok_resp(fetch_attempts::all_from_source(source.id, &conn)?)
+}
+
+/// Fetch a source as soon as possible, instead of waiting until it's due.
+#[post("/source/<id>/refresh")]
+pub fn source_refresh(
+    conn: DbConn,
+    token: ValidToken,
+    limiter: State<RefreshLimiter>,
+    id: UuidParam,
+) -> JSONResp<String> {
+    let source = sources::get(id.0, &conn)?;
+    if source.creator != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to refresh source {}",
+            source.id
+        ));
+    }
+    if source.disabled {
+        return user_err_resp(format!("Source {} is disabled", source.id));
+    }
+    if !limiter.allow(&token.username, source.id) {
+        return rate_limited_resp("Too many refresh requests, try again later");
+    }
+    source_refreshes::request(&[source.id], &conn)?;
+    ok_resp(format!("Refreshing source {}", source.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{self, serve};
+    use rocket::http::{ContentType, Status};
+    use serde_json::{json, Value};
+    use std::fs;
+
+    #[test]
+    fn api_source_preview() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("preview", "hunter22", &conn);
+        let auth = testing::login(&client, &username, "hunter22");
+        let preview = |url: &str| -> Value {
+            let mut response = client
+                .post("/api/v1/source/preview")
+                .header(auth.clone())
+                .header(ContentType::JSON)
+                .body(json!({ "url": url }).to_string())
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            let body: Value = testing::json(&mut response);
+            body["contents"].clone()
+        };
+
+        let rss = fs::read_to_string("test_data/test_rss.xml").unwrap();
+        let url = serve(&[&format!("HTTP/1.1 200 OK\r\n\r\n{}", rss)]);
+        let feed = preview(&url);
+        assert_eq!(feed["error"], Value::Null);
+        assert_eq!(feed["format"], "rss");
+        assert_eq!(feed["source_data"]["RSSAtom"]["url"], url.as_str());
+        assert!(!feed["articles"].as_array().unwrap().is_empty());
+
+        // Fetch errors are part of the preview
+        let invalid = preview("not a url");
+        assert_eq!(invalid["error"]["category"], "other");
+        assert_eq!(invalid["source_data"], Value::Null);
+
+        let url =
+            serve(&["HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n\
+             <html><head><title>Hi</title></head><body></body></html>"]);
+        let page = preview(&url);
+        assert!(page["error"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("No feeds are linked"));
+        assert!(page["articles"].as_array().unwrap().is_empty());
+        assert!(page["candidates"].as_array().unwrap().is_empty());
+
+        testing::remove_user(username, &conn);
+    }
+}
@@ -0,0 +1,313 @@
+use crate::{
+    api::v1::{
+        ok_resp, rate_limited_resp, user_err_resp, JSONResp, UuidParam,
+        ValidToken,
+    },
+    db::{
+        article_states, source_refreshes,
+        sources::{self, Source},
+        tagged_sources::{self, TaggedSource},
+        tags::{self, Tag},
+        DbConn,
+    },
+    state::RefreshLimiter,
+};
+
+use diesel::result::{Error as DieselError, OptionalExtension};
+use rocket::State;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCreatePayload {
+    pub name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagRenamePayload {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// FromData is not implemented on rocket_contrib's UUID, so
+/// this JSON payload is used
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagIDPayload {
+    pub id: Uuid,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagSourcePayload {
+    pub tag: Uuid,
+    pub source: Uuid,
+}
+
+/// A tag, along with the number of its articles the user hasn't read.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagListing {
+    #[serde(flatten)]
+    pub tag: Tag,
+    pub unread: i64,
+}
+
+#[get("/tag")]
+pub fn tags_list(conn: DbConn, token: ValidToken) -> JSONResp<Vec<TagListing>> {
+    let mut unread: HashMap<Uuid, i64> =
+        article_states::unread_by_tag(token.username.clone(), &conn)?
+            .into_iter()
+            .map(|c| (c.id, c.unread))
+            .collect();
+    let tags = tags::all_from_user(token.username, &conn)?
+        .into_iter()
+        .map(|tag| TagListing {
+            unread: unread.remove(&tag.id).unwrap_or(0),
+            tag,
+        })
+        .collect();
+    ok_resp(tags)
+}
+
+#[post("/tag", data = "<tag>")]
+pub fn tag_create(
+    conn: DbConn,
+    token: ValidToken,
+    tag: Json<TagCreatePayload>,
+) -> JSONResp<Tag> {
+    let new_tag = tags::insert(
+        Tag {
+            id: Uuid::new_v4(),
+            name: tag.into_inner().name,
+            owner: token.username,
+        },
+        &conn,
+    )?;
+    ok_resp(new_tag)
+}
+
+#[put("/tag", data = "<tag>")]
+pub fn tag_rename(
+    conn: DbConn,
+    token: ValidToken,
+    tag: Json<TagRenamePayload>,
+) -> JSONResp<Tag> {
+    let t = tag.into_inner();
+    let mut old_tag = tags::get(t.id, &conn)?;
+    if old_tag.owner != token.username {
+        return user_err_resp(format!("Unauthorized to update tag {}", t.id));
+    }
+    old_tag.name = t.name;
+    ok_resp(tags::update(old_tag, &conn)?)
+}
+
+#[delete("/tag", data = "<tag>")]
+pub fn tag_delete(
+    conn: DbConn,
+    token: ValidToken,
+    tag: Json<TagIDPayload>,
+) -> JSONResp<String> {
+    let tag_to_delete = tags::get(tag.into_inner().id, &conn)?;
+    if tag_to_delete.owner != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to delete tag {}",
+            tag_to_delete.id
+        ));
+    }
+    tags::delete(tag_to_delete.id, &conn)?;
+    ok_resp(format!("Successfully deleted tag {}", tag_to_delete.id))
+}
+
+#[get("/tag/<id>/source")]
+pub fn tag_sources_list(
+    conn: DbConn,
+    token: ValidToken,
+    id: UuidParam,
+) -> JSONResp<Vec<Source>> {
+    let tag = tags::get(id.0, &conn)?;
+    if tag.owner != token.username {
+        return user_err_resp(format!("Unauthorized to view tag {}", tag.id));
+    }
+    ok_resp(sources::all_from_tag(tag, &conn)?)
+}
+
+/// Fetch each of the tag's sources as soon as possible.
+#[post("/tag/<id>/refresh")]
+pub fn tag_refresh(
+    conn: DbConn,
+    token: ValidToken,
+    limiter: State<RefreshLimiter>,
+    id: UuidParam,
+) -> JSONResp<String> {
+    let tag = tags::get(id.0, &conn)?;
+    if tag.owner != token.username {
+        return user_err_resp(format!(
+            "Unauthorized to refresh tag {}",
+            tag.id
+        ));
+    }
+    if !limiter.allow(&token.username, tag.id) {
+        return rate_limited_resp("Too many refresh requests, try again later");
+    }
+    let tag_id = tag.id;
+    let ids: Vec<Uuid> = sources::all_from_tag(tag, &conn)?
+        .into_iter()
+        .filter(|source| !source.disabled)
+        .map(|source| source.id)
+        .collect();
+    source_refreshes::request(&ids, &conn)?;
+    ok_resp(format!(
+        "Refreshing {} sources in tag {}",
+        ids.len(),
+        tag_id
+    ))
+}
+
+/// Check that both the tag & source in `payload` belong to `username`.
+fn check_tag_source_owner(
+    payload: &TagSourcePayload,
+    username: &str,
+    conn: &DbConn,
+) -> Result<Option<String>, DieselError> {
+    let tag = tags::get(payload.tag, conn)?;
+    if tag.owner != username {
+        return Ok(Some(format!("Unauthorized to update tag {}", tag.id)));
+    }
+    let source = sources::get(payload.source, conn)?;
+    if source.creator != username {
+        return Ok(Some(format!("Unauthorized to tag source {}", source.id)));
+    }
+    Ok(None)
+}
+
+#[post("/tag/source", data = "<tagged>")]
+pub fn tag_attach(
+    conn: DbConn,
+    token: ValidToken,
+    tagged: Json<TagSourcePayload>,
+) -> JSONResp<TaggedSource> {
+    let t = tagged.into_inner();
+    if let Some(err) = check_tag_source_owner(&t, &token.username, &conn)? {
+        return user_err_resp(err);
+    }
+    if let Some(existing) =
+        tagged_sources::get_by_tag_and_source(t.tag, t.source, &conn)
+            .optional()?
+    {
+        return ok_resp(existing);
+    }
+    let tagged_source = tagged_sources::insert(
+        TaggedSource {
+            id: Uuid::new_v4(),
+            tag: t.tag,
+            source: t.source,
+        },
+        &conn,
+    )?;
+    ok_resp(tagged_source)
+}
+
+#[delete("/tag/source", data = "<tagged>")]
+pub fn tag_detach(
+    conn: DbConn,
+    token: ValidToken,
+    tagged: Json<TagSourcePayload>,
+) -> JSONResp<String> {
+    let t = tagged.into_inner();
+    if let Some(err) = check_tag_source_owner(&t, &token.username, &conn)? {
+        return user_err_resp(err);
+    }
+    match tagged_sources::get_by_tag_and_source(t.tag, t.source, &conn)
+        .optional()?
+    {
+        Some(tagged_source) => {
+            tagged_sources::delete(tagged_source.id, &conn)?;
+            ok_resp(format!(
+                "Successfully removed tag {} from source {}",
+                t.tag, t.source
+            ))
+        }
+        None => user_err_resp(format!(
+            "Source {} is not tagged with {}",
+            t.source, t.tag
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db::tags, testing};
+    use rocket::{
+        http::{ContentType, Header, Status},
+        local::Client,
+    };
+    use serde_json::{json, Value};
+
+    /// Names of the tags listed for `auth`.
+    fn tag_names(client: &Client, auth: &Header<'static>) -> Vec<String> {
+        let mut response =
+            client.get("/api/v1/tag").header(auth.clone()).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = testing::json(&mut response);
+        body["contents"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|tag| tag["name"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn api_tags() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (owner, _) = testing::seed_user("tags", "hunter22", &conn);
+        let (other, _) = testing::seed_user("tags", "hunter22", &conn);
+        let auth = testing::login(&client, &owner, "hunter22");
+        let other_auth = testing::login(&client, &other, "hunter22");
+
+        let mut response = client
+            .post("/api/v1/tag")
+            .header(auth.clone())
+            .header(ContentType::JSON)
+            .body(json!({ "name": "News" }).to_string())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = testing::json(&mut response);
+        let id = body["contents"]["id"].as_str().unwrap().to_string();
+        assert_eq!(body["contents"]["owner"], owner.as_str());
+        assert_eq!(tag_names(&client, &auth), vec!["News"]);
+        // Tags are only listed for their owner
+        assert!(tag_names(&client, &other_auth).is_empty());
+
+        let rename = |auth: &Header<'static>, name: &str| {
+            client
+                .put("/api/v1/tag")
+                .header(auth.clone())
+                .header(ContentType::JSON)
+                .body(json!({ "id": id, "name": name }).to_string())
+                .dispatch()
+                .status()
+        };
+        assert_eq!(rename(&other_auth, "Stolen"), Status::BadRequest);
+        assert_eq!(rename(&auth, "World news"), Status::Ok);
+        assert_eq!(tag_names(&client, &auth), vec!["World news"]);
+
+        let delete = |auth: &Header<'static>| {
+            client
+                .delete("/api/v1/tag")
+                .header(auth.clone())
+                .header(ContentType::JSON)
+                .body(json!({ "id": id }).to_string())
+                .dispatch()
+                .status()
+        };
+        assert_eq!(delete(&other_auth), Status::BadRequest);
+        assert_eq!(tags::all_from_user(owner.clone(), &conn).unwrap().len(), 1);
+        assert_eq!(delete(&auth), Status::Ok);
+        assert!(tag_names(&client, &auth).is_empty());
+
+        testing::remove_user(owner, &conn);
+        testing::remove_user(other, &conn);
+    }
+}
@@ -21,6 +21,23 @@ pub struct ApiTokenResp {
     api_token: tokens::TokenId,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchLanguagePayload {
+    search_language: String,
+}
+
+/// Not the whole `User`, which has their password hash & Fever API key.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SearchLanguageResp {
+    username: String,
+    search_language: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FeverPayload {
+    password: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserLogin {
     username: String,
@@ -74,6 +91,13 @@ pub fn user_create(
     user: Json<User>,
     rocket_env: State<Environment>,
 ) -> JSONResp<String> {
+    if !users::is_search_language(&user.search_language, &conn)? {
+        return user_err_resp(format!(
+            "Unknown search language {}",
+            user.search_language
+        ));
+    }
+
     let hashed_pass = hash(user.password.clone(), DEFAULT_COST)?;
     if !rocket_env.inner().0.is_prod() {
         log::debug!("Hashed {} as {}", user.password.clone(), hashed_pass);
@@ -82,6 +106,8 @@ pub fn user_create(
     let user = User {
         username: user.username.clone(),
         password: hashed_pass,
+        search_language: user.search_language.clone(),
+        fever_api_key: None,
     };
 
     let username = user.username.clone();
@@ -110,17 +136,67 @@ pub fn user_change_pass(
         log::debug!("Hashed {} as {}", user.password.clone(), hashed_pass);
     }
 
+    let existing = users::get(user.username.clone(), &conn)?;
+    let password = user.password.clone();
     let user = User {
         username: user.username.clone(),
         password: hashed_pass,
+        search_language: existing.search_language,
+        fever_api_key: None,
     };
 
     let username = user.username.clone();
     users::update(user, &conn)?;
+    // The Fever API key is derived from the password
+    if existing.fever_api_key.is_some() {
+        users::set_fever_api_key(username.clone(), Some(&password), &conn)?;
+    }
 
     ok_resp(format!("Created user {}", username))
 }
 
+#[put("/user/search_language", data = "<payload>")]
+pub fn user_search_language_update(
+    conn: DbConn,
+    token: ValidToken,
+    payload: Json<SearchLanguagePayload>,
+) -> JSONResp<SearchLanguageResp> {
+    let language = payload.into_inner().search_language;
+    if !users::is_search_language(&language, &conn)? {
+        return user_err_resp(format!("Unknown search language {}", language));
+    }
+    let user = users::set_search_language(token.username, language, &conn)?;
+    ok_resp(SearchLanguageResp {
+        username: user.username,
+        search_language: user.search_language,
+    })
+}
+
+/// Turn on the Fever API, for clients that only speak it. Fever clients
+/// sign in with the same username & password.
+#[put("/user/fever", data = "<payload>")]
+pub fn user_fever_enable(
+    conn: DbConn,
+    token: ValidToken,
+    payload: Json<FeverPayload>,
+) -> JSONResp<&'static str> {
+    let user = users::get(token.username, &conn)?;
+    if !verify(payload.password.clone(), &user.password)? {
+        return user_err_resp("Invalid username/password.");
+    }
+    users::set_fever_api_key(user.username, Some(&payload.password), &conn)?;
+    ok_resp("Fever API enabled")
+}
+
+#[delete("/user/fever")]
+pub fn user_fever_disable(
+    conn: DbConn,
+    token: ValidToken,
+) -> JSONResp<&'static str> {
+    users::set_fever_api_key(token.username, None, &conn)?;
+    ok_resp("Fever API disabled")
+}
+
 #[post("/user/logout")]
 pub fn user_logout(
     conn: DbConn,
@@ -162,7 +238,7 @@ pub fn user_index(conn: DbConn, token: ValidToken) -> JSONResp<User> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{api::v1::Resp, setup_rocket::setup_rocket};
+    use crate::{api::v1::Resp, setup_rocket::setup_rocket, testing};
     use rocket::{
         http::{ContentType, Header, Status},
         local::Client,
@@ -177,6 +253,8 @@ mod tests {
         let payload = serde_json::to_value(users::User {
             username: "foo".into(),
             password: "bar".into(),
+            search_language: users::DEFAULT_SEARCH_LANGUAGE.into(),
+            fever_api_key: None,
         })
         .unwrap()
         .to_string();
@@ -230,4 +308,30 @@ mod tests {
         assert_eq!(response.status(), Status::Ok);
         assert_eq!(resp_obj.contents, "Successfully deleted user".to_string());
     }
+
+    #[test]
+    fn api_search_language() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("language", "hunter22", &conn);
+        let auth = testing::login(&client, &username, "hunter22");
+
+        let mut response = client
+            .put("/api/v1/user/search_language")
+            .header(auth)
+            .header(ContentType::JSON)
+            .body(r#"{"search_language": "simple"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = testing::json(&mut response);
+        assert_eq!(
+            body["contents"],
+            serde_json::json!({
+                "username": username,
+                "search_language": "simple",
+            })
+        );
+
+        testing::remove_user(username, &conn);
+    }
 }
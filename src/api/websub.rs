@@ -0,0 +1,322 @@
+//! Callbacks for WebSub hubs. Mounted at `/api/websub`.
+//!
+//! Hubs confirm subscriptions (see `websub::subscribe`) with a GET, then
+//! POST new content for the source, signed with the subscription's secret.
+
+use crate::{
+    api::{v1::ApiError, Params},
+    db::{sources, websub_subscriptions, DbConn},
+    fetch,
+    retention::RetentionPolicy,
+    timestamp::Timestamp,
+    websub::{self, LEASE_SECONDS, MAX_LEASE_SECONDS, VERIFY_SECONDS},
+};
+use diesel::result::OptionalExtension;
+use rocket::{
+    http::Status,
+    request::{Form, FromRequest, Outcome, Request},
+    Data, State,
+};
+use std::io::Read;
+use uuid::Uuid;
+
+/// Most pushed content read, the same as a fetched feed would be.
+const PUSH_SIZE_LIMIT: u64 = 10 * 1024 * 1024;
+
+/// The `X-Hub-Signature` header, if any.
+pub struct HubSignature(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for HubSignature {
+    type Error = ();
+
+    fn from_request(
+        request: &'a Request<'r>,
+    ) -> Outcome<HubSignature, Self::Error> {
+        Outcome::Success(HubSignature(
+            request
+                .headers()
+                .get_one("X-Hub-Signature")
+                .map(|signature| signature.to_string()),
+        ))
+    }
+}
+
+fn source_id(source: String) -> Result<Uuid, ApiError> {
+    Uuid::parse_str(&source)
+        .map_err(|_| ApiError::new(Status::NotFound, "Not found".into()))
+}
+
+/// Verify the hub's intent to (un)subscribe, by echoing `hub.challenge`.
+#[get("/<source>?<params..>")]
+pub fn verify(
+    conn: DbConn,
+    source: String,
+    params: Form<Params>,
+) -> Result<String, ApiError> {
+    let source = source_id(source)?;
+    let topic = params.get("hub.topic").unwrap_or_default();
+    let challenge = params.get("hub.challenge").unwrap_or_default();
+    let subscription = websub_subscriptions::get(source, &conn)
+        .optional()?
+        .filter(|subscription| subscription.topic == topic);
+    let now = Timestamp::now();
+    // Subscriptions are only confirmed while a request is waiting on them
+    let requested = subscription
+        .as_ref()
+        .and_then(|subscription| subscription.requested_at)
+        .map_or(false, |requested_at| {
+            requested_at >= now - time::Duration::seconds(VERIFY_SECONDS)
+        });
+
+    match (params.get("hub.mode"), subscription) {
+        (Some("subscribe"), Some(mut subscription)) if requested => {
+            let lease = params
+                .number("hub.lease_seconds")
+                .filter(|seconds| *seconds > 0)
+                .unwrap_or(LEASE_SECONDS)
+                .min(MAX_LEASE_SECONDS);
+            subscription.lease_expires =
+                Some(now + time::Duration::seconds(lease));
+            websub_subscriptions::update(&subscription, &conn)?;
+            Ok(challenge.to_string())
+        }
+        // Only confirm unsubscribing from what's no longer wanted
+        (Some("unsubscribe"), None) => Ok(challenge.to_string()),
+        // Retried later, see `websub::subscribe_due`
+        (Some("denied"), Some(mut subscription)) => {
+            log::warn!(
+                "Hub {} denied the subscription for source {}: {}",
+                subscription.hub,
+                source,
+                params.get("hub.reason").unwrap_or_default()
+            );
+            subscription.lease_expires = None;
+            websub_subscriptions::update(&subscription, &conn)?;
+            Ok(String::new())
+        }
+        _ => Err(ApiError::new(Status::NotFound, "Not found".into())),
+    }
+}
+
+/// Content pushed by the hub. Content without a valid signature is
+/// acknowledged, but ignored.
+#[post("/<source>", data = "<data>")]
+pub fn receive(
+    conn: DbConn,
+    retention: State<RetentionPolicy>,
+    source: String,
+    signature: HubSignature,
+    data: Data,
+) -> Result<(), ApiError> {
+    let source = source_id(source)?;
+    let subscription =
+        match websub_subscriptions::get(source, &conn).optional()? {
+            Some(subscription) => subscription,
+            None => {
+                return Err(ApiError::new(Status::NotFound, "Not found".into()))
+            }
+        };
+    let mut body = Vec::new();
+    if let Err(e) = data.open().take(PUSH_SIZE_LIMIT).read_to_end(&mut body) {
+        return Err(ApiError::new(
+            Status::BadRequest,
+            format!("Could not read content: {}", e),
+        ));
+    }
+
+    let valid = signature.0.map_or(false, |signature| {
+        websub::valid_signature(&subscription.secret, &signature, &body)
+    });
+    if !valid {
+        log::warn!("Ignored badly signed content pushed for {}", source);
+        return Ok(());
+    }
+
+    let source = sources::get(source, &conn)?;
+    match fetch::store_pushed(&conn, &source, &body, *retention) {
+        Ok(count) => {
+            log::info!("Stored {} pushed articles for {}", count, source.id);
+            Ok(())
+        }
+        Err(e) => Err(ApiError::new(
+            Status::BadRequest,
+            format!("Invalid content: {}", e),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::{
+            self, articles,
+            sources::SourceData,
+            websub_subscriptions::{self, WebSubSubscription},
+        },
+        sources::rssatom::RSSAtom,
+        testing,
+        timestamp::Timestamp,
+        websub::{self, WebSubConfig},
+    };
+    use diesel::pg::PgConnection;
+    use hmac::{Hmac, Mac};
+    use rocket::http::{Header, Status};
+    use sha2::Sha256;
+    use std::{collections::HashMap, fs, sync::mpsc, thread};
+    use uuid::Uuid;
+
+    /// A stand-in hub, accepting one subscription request. Returns its URL,
+    /// & the request's form once it's sent.
+    fn stand_in_hub() -> (String, mpsc::Receiver<HashMap<String, String>>) {
+        let (url, bodies) =
+            testing::serve_recording(&["HTTP/1.1 202 Accepted\r\n\r\n"]);
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let body = bodies.recv().unwrap();
+            let form = reqwest::Url::parse(&format!(
+                "http://hub/?{}",
+                String::from_utf8(body).unwrap()
+            ))
+            .unwrap()
+            .query_pairs()
+            .into_owned()
+            .collect();
+            tx.send(form).unwrap();
+        });
+        (url, rx)
+    }
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+        mac.input(body);
+        let code: String = mac
+            .result()
+            .code()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        format!("sha256={}", code)
+    }
+
+    /// An RSS source for `topic`.
+    fn insert_source(username: &str, topic: &str, conn: &PgConnection) -> Uuid {
+        let source = Uuid::new_v4();
+        let data = SourceData::RSSAtom(RSSAtom::new(topic.to_string(), source));
+        testing::insert_source(source, "Pushed", data, username, conn);
+        source
+    }
+
+    #[test]
+    fn api_websub_push() {
+        let client = testing::client();
+        let pool = db::init_pool();
+        let conn = pool.get().unwrap();
+        let (username, _) = testing::seed_user("websub", "hunter22", &conn);
+
+        let topic = "https://example.org/rss".to_string();
+        let source = insert_source(&username, &topic, &conn);
+        let (hub, subscribed) = stand_in_hub();
+        websub_subscriptions::insert(
+            WebSubSubscription::new(source, hub, topic.clone()),
+            &conn,
+        )
+        .unwrap();
+
+        let config = WebSubConfig {
+            base_url: "http://localhost:8000".into(),
+        };
+        websub::subscribe_due(&pool, &config).unwrap();
+        let form = subscribed.recv().unwrap();
+        assert_eq!(form["hub.mode"], "subscribe");
+        assert_eq!(form["hub.topic"], topic);
+        assert_eq!(form["hub.callback"], config.callback(source));
+        let secret = form["hub.secret"].clone();
+        let callback = format!("/api/websub/{}", source);
+
+        // The hub verifies the subscription
+        let mut response = client
+            .get(format!(
+                "{}?hub.mode=subscribe&hub.topic={}&hub.challenge=abc123\
+                 &hub.lease_seconds=600",
+                callback, topic
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.body_string().unwrap(), "abc123");
+        let subscription = websub_subscriptions::get(source, &conn).unwrap();
+        assert!(subscription.is_active(Timestamp::now()));
+        let response = client
+            .get(format!(
+                "{}?hub.mode=subscribe&hub.topic=https://other.example/\
+                 &hub.challenge=abc123",
+                callback
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Content with a bad signature is ignored
+        let body = fs::read("test_data/test_rss.xml").unwrap();
+        let response = client
+            .post(callback.clone())
+            .header(Header::new("X-Hub-Signature", sign("wrong", &body)))
+            .body(body.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert!(articles::all_from_source(source, &conn).unwrap().is_empty());
+
+        let response = client
+            .post(callback.clone())
+            .header(Header::new("X-Hub-Signature", sign(&secret, &body)))
+            .body(body.clone())
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(articles::all_from_source(source, &conn).unwrap().len(), 1);
+
+        // Pushing it again doesn't duplicate it
+        let response = client
+            .post(callback)
+            .header(Header::new("X-Hub-Signature", sign(&secret, &body)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(articles::all_from_source(source, &conn).unwrap().len(), 1);
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn api_websub_lease() {
+        let client = testing::client();
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("websub", "hunter22", &conn);
+        let topic = "https://example.org/rss";
+        let source = insert_source(&username, topic, &conn);
+        let mut subscription = websub_subscriptions::insert(
+            WebSubSubscription::new(source, "http://hub/".into(), topic.into()),
+            &conn,
+        )
+        .unwrap();
+        let verify = format!(
+            "/api/websub/{}?hub.mode=subscribe&hub.topic={}\
+             &hub.challenge=abc123&hub.lease_seconds=9223372036854775807",
+            source, topic
+        );
+
+        // Nothing's been requested yet
+        let response = client.get(verify.clone()).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        // Oversized leases are shortened
+        subscription.requested_at = Some(Timestamp::now());
+        websub_subscriptions::update(&subscription, &conn).unwrap();
+        let response = client.get(verify).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let subscription = websub_subscriptions::get(source, &conn).unwrap();
+        let latest = Timestamp::now()
+            + time::Duration::seconds(websub::MAX_LEASE_SECONDS);
+        assert!(subscription.lease_expires.unwrap() <= latest);
+        assert!(subscription.is_active(Timestamp::now()));
+
+        testing::remove_user(username, &conn);
+    }
+}
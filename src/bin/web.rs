@@ -1,9 +1,31 @@
+extern crate clokwerk;
 extern crate speedwagon;
 
-use speedwagon::{logger, setup_rocket::setup_rocket};
+use clokwerk::{Scheduler, TimeUnits};
+use std::{thread, time::Duration};
+
+use speedwagon::{db, logger, setup_rocket::setup_rocket, websub};
 
 fn main() {
     logger::setup_logging(log::LevelFilter::Debug)
         .expect("failed to initialize logging");
-    setup_rocket().launch();
+    let rocket = setup_rocket();
+
+    // Hubs push to this server, so it's the one that subscribes to them
+    if let Some(config) = websub::WebSubConfig::from_env() {
+        let pool = db::init_pool();
+        thread::spawn(move || {
+            let mut scheduler = Scheduler::new();
+            scheduler.every(1.minute()).run(move || {
+                if let Err(e) = websub::subscribe_due(&pool, &config) {
+                    log::error!("WebSub subscribing failed: {}", e);
+                }
+            });
+            loop {
+                scheduler.run_pending();
+                thread::sleep(Duration::from_secs(1));
+            }
+        });
+    }
+    rocket.launch();
 }
@@ -2,24 +2,58 @@ extern crate clokwerk;
 extern crate speedwagon;
 
 use clokwerk::{Scheduler, TimeUnits};
+use std::{thread, time::Duration};
 
-use speedwagon::{db, fetch, logger};
+use speedwagon::{db, fetch, logger, retention, sanitize, sources};
 
 fn main() {
     dotenv::dotenv().ok();
     logger::setup_logging(log::LevelFilter::Debug)
         .expect("failed to initialize logging");
 
-    let mut pool = db::init_pool();
-    // TODO would an "update_requested" flag on each source be better?
-    // A background worker could then do these pulls in parallel,
-    //  and another task sets "update_requested=True" on each source
-    //  every ~10 mins.
+    let pool = db::init_pool();
+    match sanitize::sanitize_stored(&pool) {
+        Ok(0) => (),
+        Ok(count) => log::info!("Sanitized {} stored articles", count),
+        Err(e) => log::error!("Sanitizing stored articles failed: {}", e),
+    }
+    let limits = fetch::FetchLimits::from_env();
+    let retention = retention::RetentionPolicy::from_env();
+    let fetch_pool = pool.clone();
+    let full_content_pool = pool.clone();
     let f = move || {
-        if let Err(e) = fetch::fetch_new_from_all_sources(&mut pool) {
+        if let Err(e) =
+            fetch::fetch_new_from_all_sources(&fetch_pool, limits, retention)
+        {
             log::error!("{}", e);
         }
     };
+    let cleanup = move || {
+        if let Err(e) = retention::clean_all_sources(&pool, retention) {
+            log::error!("Article cleanup failed: {}", e);
+        }
+    };
+    // Article pages can be slow, so they're fetched on their own thread
+    thread::spawn(move || {
+        let mut scheduler = Scheduler::new();
+        scheduler.every(1.minute()).run(move || {
+            if let Err(e) = sources::full_content::backfill(&full_content_pool)
+            {
+                log::error!("Fetching full content failed: {}", e);
+            }
+        });
+        loop {
+            scheduler.run_pending();
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
     let mut scheduler = Scheduler::new();
-    scheduler.every(10.minutes()).run(f);
+    // Sources are only fetched once they're due or refreshed, so check
+    // often
+    scheduler.every(5.seconds()).run(f);
+    scheduler.every(1.hour()).run(cleanup);
+    loop {
+        scheduler.run_pending();
+        thread::sleep(Duration::from_secs(1));
+    }
 }
@@ -1,11 +1,19 @@
+pub mod article_states;
 pub mod articles;
+pub mod expired_articles;
+pub mod fetch_attempts;
+pub mod full_content_queue;
+pub mod output_feeds;
+pub mod source_events;
+pub mod source_refreshes;
 pub mod sources;
 pub mod tagged_sources;
 pub mod tags;
 pub mod tokens;
 pub mod users;
+pub mod websub_subscriptions;
 
-use diesel::pg::PgConnection;
+use diesel::{pg::PgConnection, sql_types};
 
 use r2d2_diesel::ConnectionManager;
 use rocket::{
@@ -14,6 +22,19 @@ use rocket::{
     Outcome, Request, State,
 };
 use std::{env, ops::Deref};
+use uuid::Uuid;
+
+/// A row's numeric id, for APIs that can't use UUIDs (ex: GReader, Fever).
+///
+/// `short_id` columns aren't in the schema, since they're always set by
+/// Postgres.
+#[derive(QueryableByName, Debug, Clone, Copy)]
+pub struct ShortId {
+    #[sql_type = "sql_types::Uuid"]
+    pub id: Uuid,
+    #[sql_type = "sql_types::BigInt"]
+    pub short_id: i64,
+}
 
 pub type Pool = r2d2::Pool<ConnectionManager<PgConnection>>;
 
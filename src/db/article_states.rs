@@ -0,0 +1,283 @@
+use crate::{
+    db::{articles::Article, users::User},
+    schema::{article_states, articles, sources},
+    timestamp::Timestamp,
+};
+use diesel::{prelude::*, sql_types};
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+/// A user's read/starred state for an article.
+///
+/// Articles without a row are unread & unstarred.
+#[derive(
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+)]
+#[table_name = "article_states"]
+#[primary_key(article, username)]
+#[belongs_to(Article, foreign_key = "article")]
+#[belongs_to(User, foreign_key = "username")]
+pub struct ArticleState {
+    pub article: Uuid,
+    pub username: String,
+    pub read: bool,
+    pub starred: bool,
+    pub read_at: Option<Timestamp>,
+    /// When `read` or `starred` last changed
+    pub updated: Timestamp,
+}
+
+impl ArticleState {
+    pub fn new(article: Uuid, username: String) -> ArticleState {
+        ArticleState {
+            article,
+            username,
+            read: false,
+            starred: false,
+            read_at: None,
+            updated: Timestamp::now(),
+        }
+    }
+}
+
+/// Fields to change on an `ArticleState`. `None` leaves a field untouched.
+#[derive(AsChangeset, Debug, Default)]
+#[table_name = "article_states"]
+pub struct StateChange {
+    pub read: Option<bool>,
+    pub starred: Option<bool>,
+    pub read_at: Option<Option<Timestamp>>,
+    pub updated: Option<Timestamp>,
+}
+
+impl StateChange {
+    pub fn new(read: Option<bool>, starred: Option<bool>) -> StateChange {
+        StateChange {
+            read,
+            starred,
+            read_at: read
+                .map(|r| if r { Some(Timestamp::now()) } else { None }),
+            updated: Some(Timestamp::now()),
+        }
+    }
+
+    fn apply(&self, state: &mut ArticleState) {
+        if let Some(read) = self.read {
+            state.read = read;
+        }
+        if let Some(starred) = self.starred {
+            state.starred = starred;
+        }
+        if let Some(read_at) = self.read_at {
+            state.read_at = read_at;
+        }
+        if let Some(updated) = self.updated {
+            state.updated = updated;
+        }
+    }
+}
+
+#[derive(QueryableByName, Debug, Serialize, Deserialize)]
+pub struct UnreadCount {
+    #[sql_type = "sql_types::Uuid"]
+    pub id: Uuid,
+    #[sql_type = "sql_types::BigInt"]
+    pub unread: i64,
+}
+
+pub fn get(
+    article: Uuid,
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<ArticleState> {
+    article_states::table
+        .find((article, username))
+        .get_result::<ArticleState>(connection)
+}
+
+pub fn all_for_articles(
+    articles: &[Uuid],
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<ArticleState>> {
+    article_states::table
+        .filter(article_states::username.eq(username))
+        .filter(article_states::article.eq_any(articles))
+        .load::<ArticleState>(connection)
+}
+
+/// Apply `change` to each of the given articles owned by `username`.
+///
+/// Articles from other users' sources are ignored.
+pub fn set(
+    articles: &[Uuid],
+    username: String,
+    change: &StateChange,
+    connection: &PgConnection,
+) -> QueryResult<Vec<ArticleState>> {
+    connection.transaction(|| {
+        let owned: Vec<Uuid> = articles::table
+            .select(articles::id)
+            .filter(articles::id.eq_any(articles))
+            .filter(
+                articles::source.eq_any(
+                    sources::table
+                        .select(sources::id)
+                        .filter(sources::creator.eq(username.clone())),
+                ),
+            )
+            .load(connection)?;
+
+        owned
+            .into_iter()
+            .map(|article| {
+                let mut state = ArticleState::new(article, username.clone());
+                change.apply(&mut state);
+                diesel::insert_into(article_states::table)
+                    .values(&state)
+                    .on_conflict((
+                        article_states::article,
+                        article_states::username,
+                    ))
+                    .do_update()
+                    .set(change)
+                    .get_result(connection)
+            })
+            .collect()
+    })
+}
+
+/// Mark every article published up to `until` as read. Articles without a
+/// publish time go by when they were stored.
+///
+/// Optionally limited to a source and/or tag. Returns the number of articles
+/// newly marked as read.
+pub fn mark_read_until(
+    username: String,
+    source: Option<Uuid>,
+    tag: Option<Uuid>,
+    until: Timestamp,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::sql_query(
+        "INSERT INTO article_states
+           (article, username, read, starred, read_at, updated)
+         SELECT a.id, $1, TRUE, FALSE, $2, $2
+         FROM articles a
+         JOIN sources s ON s.id = a.source
+         WHERE s.creator = $1
+           AND COALESCE(a.published, a.added) <= $3
+           AND ($4 IS NULL OR a.source = $4)
+           AND ($5 IS NULL OR a.source IN
+             (SELECT source FROM tagged_sources WHERE tag = $5))
+         ON CONFLICT (article, username) DO UPDATE
+         SET read = TRUE, read_at = EXCLUDED.read_at,
+           updated = EXCLUDED.updated
+         WHERE NOT article_states.read",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .bind::<sql_types::Timestamp, _>(Timestamp::now())
+    .bind::<sql_types::Timestamp, _>(until)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(source)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(tag)
+    .execute(connection)
+}
+
+/// `mark_read_until`, but by `short_id`, for APIs that mark articles read
+/// up to the newest one a client has seen.
+pub fn mark_read_through(
+    username: String,
+    source: Option<Uuid>,
+    tag: Option<Uuid>,
+    newest_short_id: i64,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::sql_query(
+        "INSERT INTO article_states
+           (article, username, read, starred, read_at, updated)
+         SELECT a.id, $1, TRUE, FALSE, $2, $2
+         FROM articles a
+         JOIN sources s ON s.id = a.source
+         WHERE s.creator = $1
+           AND a.short_id <= $3
+           AND ($4 IS NULL OR a.source = $4)
+           AND ($5 IS NULL OR a.source IN
+             (SELECT source FROM tagged_sources WHERE tag = $5))
+         ON CONFLICT (article, username) DO UPDATE
+         SET read = TRUE, read_at = EXCLUDED.read_at,
+           updated = EXCLUDED.updated
+         WHERE NOT article_states.read",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .bind::<sql_types::Timestamp, _>(Timestamp::now())
+    .bind::<sql_types::BigInt, _>(newest_short_id)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(source)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(tag)
+    .execute(connection)
+}
+
+/// How many articles from the user's sources they've starred.
+pub fn starred_count(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<i64> {
+    let own_articles = articles::table.select(articles::id).filter(
+        articles::source.eq_any(
+            sources::table
+                .select(sources::id)
+                .filter(sources::creator.eq(username.clone())),
+        ),
+    );
+    article_states::table
+        .filter(article_states::username.eq(username))
+        .filter(article_states::starred.eq(true))
+        .filter(article_states::article.eq_any(own_articles))
+        .count()
+        .get_result(connection)
+}
+
+/// Unread article counts for each of the user's sources with unread
+/// articles.
+pub fn unread_by_source(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<UnreadCount>> {
+    diesel::sql_query(
+        "SELECT a.source AS id, COUNT(*) AS unread
+         FROM articles a
+         JOIN sources s ON s.id = a.source
+         LEFT JOIN article_states st
+           ON st.article = a.id AND st.username = $1
+         WHERE s.creator = $1 AND NOT COALESCE(st.read, FALSE)
+         GROUP BY a.source",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .load(connection)
+}
+
+/// Unread article counts for each of the user's tags with unread articles.
+pub fn unread_by_tag(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<UnreadCount>> {
+    diesel::sql_query(
+        "SELECT ts.tag AS id, COUNT(*) AS unread
+         FROM articles a
+         JOIN tagged_sources ts ON ts.source = a.source
+         JOIN tags t ON t.id = ts.tag
+         LEFT JOIN article_states st
+           ON st.article = a.id AND st.username = $1
+         WHERE t.owner = $1 AND NOT COALESCE(st.read, FALSE)
+         GROUP BY ts.tag",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .load(connection)
+}
@@ -1,31 +1,63 @@
-use crate::{db::sources::Source, schema::articles};
-use diesel::prelude::*;
+use crate::{
+    db::{sources::Source, ShortId},
+    sanitize,
+    schema::{article_states, articles, sources, tagged_sources},
+    timestamp::Timestamp,
+};
+use diesel::{
+    deserialize::{self, FromSql},
+    dsl::sql,
+    pg::Pg,
+    prelude::*,
+    serialize::{self, Output, ToSql},
+    sql_types,
+};
 use serde::{Deserialize, Serialize};
+use std::{fmt, io::Write, str::FromStr};
 
 use uuid::Uuid;
 
 #[derive(
-    Associations, Queryable, AsChangeset, Debug, Identifiable, Insertable,
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
 )]
 #[table_name = "articles"]
 #[belongs_to(Source, foreign_key = "source")]
 pub struct Article {
     pub id: Uuid,
     pub title: Option<String>,
-    pub published: Option<time::Timespec>,
+    pub published: Option<Timestamp>,
     pub source_info: serde_json::Value,
     pub summary: Option<String>,
-    pub content: serde_json::Value,
+    pub content: ArticleContent,
     pub rights: Option<String>,
-    pub links: serde_json::Value,
-    pub authors: serde_json::Value,
+    pub links: ArticleLinks,
+    pub authors: ArticleAuthors,
     pub categories: serde_json::Value,
     pub comments_url: Option<String>,
     pub extensions: serde_json::Value,
     pub source: Uuid,
     pub id_from_source: Option<String>,
-    /* TODO more
-     * icon/thumbnail? */
+    /// `summary` before sanitizing
+    #[serde(skip)]
+    pub raw_summary: Option<String>,
+    /// `content` before sanitizing
+    #[serde(skip)]
+    pub raw_content: Option<serde_json::Value>,
+    /// Attached media, ex: podcast episodes
+    pub enclosures: ArticleEnclosures,
+    pub thumbnail: Option<String>,
+    /// Seconds, for podcasts & videos
+    pub duration: Option<i32>,
+    /// Sanitized main content of the article's page, for sources with
+    /// `fetch_full_content`
+    pub full_content: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -34,11 +66,691 @@ pub struct ArticleSource {
     pub links: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Stores a type as JSON, with serde.
+macro_rules! json_column {
+    ($type:ty) => {
+        impl FromSql<sql_types::Json, Pg> for $type {
+            fn from_sql(bytes: Option<&[u8]>) -> deserialize::Result<Self> {
+                let value = <serde_json::Value as FromSql<
+                    sql_types::Json,
+                    Pg,
+                >>::from_sql(bytes)?;
+                Ok(serde_json::from_value(value)?)
+            }
+        }
+
+        impl ToSql<sql_types::Json, Pg> for $type {
+            fn to_sql<W: Write>(
+                &self,
+                out: &mut Output<W, Pg>,
+            ) -> serialize::Result {
+                serde_json::to_writer(out, self)
+                    .map(|_| serialize::IsNull::No)
+                    .map_err(Into::into)
+            }
+        }
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    Html,
+    Text,
+    Xhtml,
+}
+
+impl ContentType {
+    /// From an Atom `type`, which defaults to text. Out-of-line content can
+    /// have any MIME type.
+    pub fn from_atom(content_type: Option<&str>) -> ContentType {
+        match content_type.map(|t| t.trim().to_lowercase()) {
+            Some(t) if t == "xhtml" => ContentType::Xhtml,
+            Some(t) if t == "html" || t.contains("html") => ContentType::Html,
+            _ => ContentType::Text,
+        }
+    }
+}
+
+/// An article's content, the same for every kind of source.
+#[derive(
+    Debug, Clone, PartialEq, Serialize, Deserialize, AsExpression, FromSqlRow,
+)]
+#[sql_type = "sql_types::Json"]
+pub struct ArticleContent {
+    pub content_type: ContentType,
+    /// The body, if it's included in the feed
+    pub value: Option<String>,
+    /// Where the body can be fetched from, if it's not included
+    pub src: Option<String>,
+    pub lang: Option<String>,
+    /// What relative URLs in `value` are relative to (Atom's `xml:base`)
+    pub base: Option<String>,
+}
+
+json_column!(ArticleContent);
+
+impl ArticleContent {
+    pub fn new(content_type: ContentType, value: Option<String>) -> Self {
+        ArticleContent {
+            content_type,
+            value,
+            src: None,
+            lang: None,
+            base: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ArticleLink {
-    pub url: Option<String>,
+    pub url: String,
+    /// Ex: `alternate`, `enclosure`. `None` is the article itself.
     pub relationship: Option<String>,
     pub title: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+impl ArticleLink {
+    pub fn new(url: String) -> Self {
+        ArticleLink {
+            url,
+            relationship: None,
+            title: None,
+            mime_type: None,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[sql_type = "sql_types::Json"]
+#[serde(transparent)]
+pub struct ArticleLinks(pub Vec<ArticleLink>);
+
+json_column!(ArticleLinks);
+
+impl ArticleLinks {
+    /// The link to the article itself, if it has one.
+    pub fn article_url(&self) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|link| {
+                link.relationship.is_none()
+                    || link.relationship.as_deref() == Some("alternate")
+            })
+            .map(|link| link.url.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArticleAuthor {
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub url: Option<String>,
+    pub avatar: Option<String>,
+}
+
+impl ArticleAuthor {
+    /// From an RSS `<author>`, usually `email (Name)`.
+    pub fn from_rss(author: &str) -> Self {
+        let author = author.trim();
+        let (email, name) = match (author.find(' '), author.ends_with(')')) {
+            (Some(space), true)
+                if author[..space].contains('@')
+                    && author[space..].trim_start().starts_with('(') =>
+            {
+                let name = author[space..].trim_start();
+                (
+                    Some(author[..space].to_string()),
+                    name[1..name.len() - 1].trim().to_string(),
+                )
+            }
+            _ => (None, author.to_string()),
+        };
+        ArticleAuthor {
+            name: Some(name).filter(|n| !n.is_empty()),
+            email,
+            url: None,
+            avatar: None,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[sql_type = "sql_types::Json"]
+#[serde(transparent)]
+pub struct ArticleAuthors(pub Vec<ArticleAuthor>);
+
+json_column!(ArticleAuthors);
+
+/// A file attached to an article, ex: an RSS `<enclosure>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArticleEnclosure {
+    pub url: String,
+    pub mime_type: Option<String>,
+    /// Bytes
+    pub length: Option<i64>,
+    /// Seconds
+    pub duration: Option<i32>,
+}
+
+impl ArticleEnclosure {
+    pub fn new(url: String) -> Self {
+        ArticleEnclosure {
+            url,
+            mime_type: None,
+            length: None,
+            duration: None,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Default,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    AsExpression,
+    FromSqlRow,
+)]
+#[sql_type = "sql_types::Json"]
+#[serde(transparent)]
+pub struct ArticleEnclosures(pub Vec<ArticleEnclosure>);
+
+json_column!(ArticleEnclosures);
+
+/// Optional constraints when listing articles.
+#[derive(Debug, Default)]
+pub struct ArticleFilter {
+    pub source: Option<Uuid>,
+    pub tag: Option<Uuid>,
+    pub published_before: Option<Timestamp>,
+    pub published_after: Option<Timestamp>,
+    /// Only articles stored before this
+    pub added_before: Option<Timestamp>,
+    /// Only articles stored after this
+    pub added_after: Option<Timestamp>,
+    /// Only read (`true`) or unread (`false`) articles.
+    pub read: Option<bool>,
+    /// Only starred (`true`) or unstarred (`false`) articles.
+    pub starred: Option<bool>,
+}
+
+/// Which articles `short_ids_from_user` returns, by `short_id`.
+#[derive(Debug, Default)]
+pub struct ShortIdRange {
+    pub after: Option<i64>,
+    pub before: Option<i64>,
+    pub newest_first: bool,
+    /// Only articles added, or whose state changed, after this
+    pub modified_since: Option<Timestamp>,
+    /// `None` is every article
+    pub limit: Option<i64>,
+}
+
+/// When an article was stored. `articles.added` isn't in the schema, since
+/// it's always set by Postgres.
+#[derive(QueryableByName, Debug, Clone, Copy)]
+pub struct Added {
+    #[sql_type = "sql_types::Uuid"]
+    pub id: Uuid,
+    #[sql_type = "sql_types::Timestamp"]
+    pub added: Timestamp,
+}
+
+/// An article matching a search.
+#[derive(QueryableByName, Debug)]
+pub struct SearchHit {
+    #[sql_type = "sql_types::Uuid"]
+    pub id: Uuid,
+    #[sql_type = "sql_types::Float"]
+    pub rank: f32,
+    /// Matching text as HTML, with matches wrapped in `<mark>` tags
+    #[sql_type = "sql_types::Text"]
+    pub snippet: String,
+}
+
+/// Position of the last article in a page of results.
+///
+/// Articles are ordered newest first (by `published`, then `id`), with
+/// unpublished articles last. The string form is `<seconds>_<id>`, where
+/// `<seconds>` is empty for unpublished articles, and has nine digits of
+/// nanoseconds after a `.` for articles published mid-second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cursor {
+    pub published: Option<Timestamp>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn after(article: &Article) -> Cursor {
+        Cursor {
+            published: article.published,
+            id: article.id,
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.published {
+            Some(ts) if ts.0.nsec == 0 => write!(f, "{}_{}", ts.0.sec, self.id),
+            Some(ts) => {
+                write!(f, "{}.{:09}_{}", ts.0.sec, ts.0.nsec, self.id)
+            }
+            None => write!(f, "_{}", self.id),
+        }
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("Bad cursor {}", s);
+        let mut parts = s.splitn(2, '_');
+        let published = match parts.next() {
+            Some("") => None,
+            Some(published) => {
+                let mut published = published.splitn(2, '.');
+                let sec = published.next().and_then(|sec| sec.parse().ok());
+                let nsec = match published.next() {
+                    Some(nsec)
+                        if nsec.len() == 9
+                            && nsec.bytes().all(|b| b.is_ascii_digit()) =>
+                    {
+                        nsec.parse().ok()
+                    }
+                    Some(_) => None,
+                    None => Some(0),
+                };
+                match (sec, nsec) {
+                    (Some(sec), Some(nsec)) => {
+                        Some(Timestamp(time::Timespec { sec, nsec }))
+                    }
+                    _ => return Err(bad()),
+                }
+            }
+            None => return Err(bad()),
+        };
+        let id = parts
+            .next()
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(bad)?;
+        Ok(Cursor { published, id })
+    }
+}
+
+/// Articles from sources created by `username`, after `cursor`.
+fn user_query<'a>(
+    username: String,
+    filter: &ArticleFilter,
+    cursor: Option<Cursor>,
+) -> articles::BoxedQuery<'a, Pg> {
+    let mut query = articles::table
+        .filter(
+            articles::source.eq_any(
+                sources::table
+                    .select(sources::id)
+                    .filter(sources::creator.eq(username.clone())),
+            ),
+        )
+        .into_boxed();
+
+    if let Some(source) = filter.source {
+        query = query.filter(articles::source.eq(source));
+    }
+    if let Some(tag) = filter.tag {
+        query = query.filter(
+            articles::source.eq_any(
+                tagged_sources::table
+                    .select(tagged_sources::source)
+                    .filter(tagged_sources::tag.eq(tag)),
+            ),
+        );
+    }
+    if let Some(before) = filter.published_before {
+        query = query.filter(articles::published.lt(before));
+    }
+    if let Some(after) = filter.published_after {
+        query = query.filter(articles::published.gt(after));
+    }
+    // `articles.added` isn't in the schema, see `Added`
+    if let Some(before) = filter.added_before {
+        query = query.filter(
+            sql::<sql_types::Bool>("articles.added < ")
+                .bind::<sql_types::Timestamp, _>(before),
+        );
+    }
+    if let Some(after) = filter.added_after {
+        query = query.filter(
+            sql::<sql_types::Bool>("articles.added > ")
+                .bind::<sql_types::Timestamp, _>(after),
+        );
+    }
+
+    if let Some(read) = filter.read {
+        let read_articles = article_states::table
+            .select(article_states::article)
+            .filter(article_states::username.eq(username.clone()))
+            .filter(article_states::read.eq(true));
+        query = if read {
+            query.filter(articles::id.eq_any(read_articles))
+        } else {
+            query.filter(articles::id.ne_all(read_articles))
+        };
+    }
+    if let Some(starred) = filter.starred {
+        let starred_articles = article_states::table
+            .select(article_states::article)
+            .filter(article_states::username.eq(username))
+            .filter(article_states::starred.eq(true));
+        query = if starred {
+            query.filter(articles::id.eq_any(starred_articles))
+        } else {
+            query.filter(articles::id.ne_all(starred_articles))
+        };
+    }
+
+    if let Some(cursor) = cursor {
+        query = match cursor.published {
+            Some(published) => query.filter(
+                articles::published
+                    .lt(published)
+                    .or(articles::published
+                        .eq(published)
+                        .and(articles::id.lt(cursor.id)))
+                    .or(articles::published.is_null()),
+            ),
+            None => query.filter(
+                articles::published
+                    .is_null()
+                    .and(articles::id.lt(cursor.id)),
+            ),
+        };
+    }
+
+    query
+}
+
+/// Get a page of articles from sources created by `username`, newest first.
+pub fn all_from_user(
+    username: String,
+    filter: &ArticleFilter,
+    cursor: Option<Cursor>,
+    limit: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Article>> {
+    user_query(username, filter, cursor)
+        .order((articles::published.desc().nulls_last(), articles::id.desc()))
+        .limit(limit)
+        .load::<Article>(connection)
+}
+
+/// `all_from_user`, but only each article's position.
+pub fn cursors_from_user(
+    username: String,
+    filter: &ArticleFilter,
+    cursor: Option<Cursor>,
+    limit: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Cursor>> {
+    user_query(username, filter, cursor)
+        .select((articles::id, articles::published))
+        .order((articles::published.desc().nulls_last(), articles::id.desc()))
+        .limit(limit)
+        .load::<(Uuid, Option<Timestamp>)>(connection)
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(id, published)| Cursor { published, id })
+                .collect()
+        })
+}
+
+/// Ids of every article from sources created by `username` matching
+/// `filter`.
+pub fn ids_from_user(
+    username: String,
+    filter: &ArticleFilter,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Uuid>> {
+    user_query(username, filter, None)
+        .select(articles::id)
+        .load(connection)
+}
+
+pub fn count_from_user(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<i64> {
+    user_query(username, &ArticleFilter::default(), None)
+        .count()
+        .get_result(connection)
+}
+
+/// Search articles from sources created by `username`, best match first.
+///
+/// `tsquery` is `to_tsquery` input, parsed with the user's
+/// `search_language`. `articles.search` and `articles.search_language`
+/// aren't in the schema (see `src/schema.patch`), since Diesel has no
+/// `tsvector` or `regconfig` types. Snippets are built from the title,
+/// summary & content with their tags stripped, then escaped.
+pub fn search(
+    username: String,
+    tsquery: &str,
+    source: Option<Uuid>,
+    tag: Option<Uuid>,
+    limit: i64,
+    offset: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<SearchHit>> {
+    diesel::sql_query(
+        "SELECT a.id, ts_rank_cd(a.search, q.query) AS rank,
+           ts_headline(
+             a.search_language,
+             concat_ws(' ... ', a.title,
+               regexp_replace(a.summary, '<[^>]*>', ' ', 'g'),
+               regexp_replace(a.content ->> 'value', '<[^>]*>', ' ', 'g')),
+             q.query,
+             $7
+           ) AS snippet
+         FROM articles a
+         JOIN sources s ON s.id = a.source
+         JOIN users u ON u.username = s.creator
+         CROSS JOIN LATERAL
+           to_tsquery(u.search_language::REGCONFIG, $2) AS q(query)
+         WHERE s.creator = $1
+           AND a.search @@ q.query
+           AND ($3 IS NULL OR a.source = $3)
+           AND ($4 IS NULL OR a.source IN
+             (SELECT source FROM tagged_sources WHERE tag = $4))
+         ORDER BY rank DESC, a.published DESC NULLS LAST, a.id DESC
+         LIMIT $5 OFFSET $6",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .bind::<sql_types::Text, _>(tsquery)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(source)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(tag)
+    .bind::<sql_types::BigInt, _>(limit)
+    .bind::<sql_types::BigInt, _>(offset)
+    .bind::<sql_types::Text, _>(format!(
+        r#"StartSel="{}", StopSel="{}", MaxFragments=2"#,
+        sanitize::MATCH_START,
+        sanitize::MATCH_END
+    ))
+    .load::<SearchHit>(connection)
+    .map(|hits| {
+        hits.into_iter()
+            .map(|hit| SearchHit {
+                snippet: sanitize::highlight_snippet(&hit.snippet),
+                ..hit
+            })
+            .collect()
+    })
+}
+
+/// Delete a source's articles published before `published_before` (or stored
+/// before it, if they're undated), or past its `keep_newest` newest articles. With `keep_starred`, articles anyone
+/// starred are never deleted.
+///
+/// Deleted articles' `id_from_source`s are kept in `expired_articles`, so
+/// they aren't stored again while they're still in the feed.
+pub fn delete_expired(
+    source: Uuid,
+    published_before: Option<Timestamp>,
+    keep_newest: Option<i64>,
+    keep_starred: bool,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    // Selecting from `expired` counts the deleted articles
+    diesel::sql_query(
+        "WITH expired AS (
+           DELETE FROM articles a
+           WHERE a.source = $1
+             AND (COALESCE(a.published, a.added) < $2
+               OR ($3 IS NOT NULL AND a.id NOT IN
+                 (SELECT id FROM articles
+                  WHERE source = $1
+                  ORDER BY COALESCE(published, added) DESC, id DESC
+                  LIMIT $3)))
+             AND NOT ($4 AND EXISTS
+               (SELECT 1 FROM article_states st
+                WHERE st.article = a.id AND st.starred))
+           RETURNING a.source, a.id_from_source
+         ), tombstones AS (
+           INSERT INTO expired_articles (source, id_from_source)
+           SELECT source, id_from_source FROM expired
+           WHERE id_from_source IS NOT NULL
+           ON CONFLICT DO NOTHING
+         )
+         SELECT 1 FROM expired",
+    )
+    .bind::<sql_types::Uuid, _>(source)
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(published_before)
+    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(keep_newest)
+    .bind::<sql_types::Bool, _>(keep_starred)
+    .execute(connection)
+}
+
+/// Articles stored before sanitizing was added.
+pub fn all_unsanitized(
+    limit: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Article>> {
+    articles::table
+        .filter(articles::raw_content.is_null())
+        .limit(limit)
+        .load::<Article>(connection)
+}
+
+pub fn all_with_ids(
+    ids: &[Uuid],
+    connection: &PgConnection,
+) -> QueryResult<Vec<Article>> {
+    articles::table
+        .filter(articles::id.eq_any(ids))
+        .load::<Article>(connection)
+}
+
+pub fn short_ids(
+    ids: &[Uuid],
+    connection: &PgConnection,
+) -> QueryResult<Vec<ShortId>> {
+    diesel::sql_query("SELECT id, short_id FROM articles WHERE id = ANY($1)")
+        .bind::<sql_types::Array<sql_types::Uuid>, _>(ids)
+        .load(connection)
+}
+
+pub fn from_short_ids(
+    short_ids: &[i64],
+    connection: &PgConnection,
+) -> QueryResult<Vec<ShortId>> {
+    diesel::sql_query(
+        "SELECT id, short_id FROM articles WHERE short_id = ANY($1)",
+    )
+    .bind::<sql_types::Array<sql_types::BigInt>, _>(short_ids)
+    .load(connection)
+}
+
+pub fn added(
+    ids: &[Uuid],
+    connection: &PgConnection,
+) -> QueryResult<Vec<Added>> {
+    diesel::sql_query("SELECT id, added FROM articles WHERE id = ANY($1)")
+        .bind::<sql_types::Array<sql_types::Uuid>, _>(ids)
+        .load(connection)
+}
+
+/// Numeric ids of articles from sources created by `username` matching
+/// `filter` & `range`.
+pub fn short_ids_from_user(
+    username: String,
+    filter: &ArticleFilter,
+    range: &ShortIdRange,
+    connection: &PgConnection,
+) -> QueryResult<Vec<ShortId>> {
+    let order = if range.newest_first { "DESC" } else { "ASC" };
+    diesel::sql_query(format!(
+        "SELECT a.id, a.short_id
+         FROM articles a
+         JOIN sources s ON s.id = a.source
+         LEFT JOIN article_states st
+           ON st.article = a.id AND st.username = $1
+         WHERE s.creator = $1
+           AND ($2 IS NULL OR a.source = $2)
+           AND ($3 IS NULL OR a.source IN
+             (SELECT source FROM tagged_sources WHERE tag = $3))
+           AND ($4 IS NULL OR a.published < $4)
+           AND ($5 IS NULL OR a.published > $5)
+           AND ($6 IS NULL OR COALESCE(st.read, FALSE) = $6)
+           AND ($7 IS NULL OR COALESCE(st.starred, FALSE) = $7)
+           AND ($8 IS NULL OR a.short_id > $8)
+           AND ($9 IS NULL OR a.short_id < $9)
+           AND ($10 IS NULL OR a.added > $10 OR st.updated > $10)
+           AND ($12 IS NULL OR a.added < $12)
+           AND ($13 IS NULL OR a.added > $13)
+         ORDER BY a.short_id {}
+         LIMIT $11",
+        order
+    ))
+    .bind::<sql_types::Text, _>(username)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(filter.source)
+    .bind::<sql_types::Nullable<sql_types::Uuid>, _>(filter.tag)
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(
+        filter.published_before,
+    )
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(
+        filter.published_after,
+    )
+    .bind::<sql_types::Nullable<sql_types::Bool>, _>(filter.read)
+    .bind::<sql_types::Nullable<sql_types::Bool>, _>(filter.starred)
+    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(range.after)
+    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(range.before)
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(range.modified_since)
+    .bind::<sql_types::Nullable<sql_types::BigInt>, _>(range.limit)
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(filter.added_before)
+    .bind::<sql_types::Nullable<sql_types::Timestamp>, _>(filter.added_after)
+    .load(connection)
 }
 
 pub fn all_from_source(
@@ -47,7 +759,43 @@ pub fn all_from_source(
 ) -> QueryResult<Vec<Article>> {
     articles::table
         .filter(articles::source.eq(&source))
-        .load::<Article>(&*connection)
+        .load::<Article>(connection)
+}
+
+/// Publish times of a source's newest articles, newest first.
+pub fn recent_published(
+    source: Uuid,
+    limit: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Timestamp>> {
+    articles::table
+        .select(articles::published)
+        .filter(articles::source.eq(source))
+        .filter(articles::published.is_not_null())
+        .order(articles::published.desc())
+        .limit(limit)
+        .load::<Option<Timestamp>>(connection)
+        .map(|v| v.into_iter().flatten().collect())
+}
+
+/// Dates of a source's newest articles, newest first, as ordered by
+/// `delete_expired`: when they were published, or else stored.
+pub fn recent_dated(
+    source: Uuid,
+    limit: i64,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Timestamp>> {
+    diesel::sql_query(
+        "SELECT id, COALESCE(published, added) AS added
+         FROM articles
+         WHERE source = $1
+         ORDER BY COALESCE(published, added) DESC, id DESC
+         LIMIT $2",
+    )
+    .bind::<sql_types::Uuid, _>(source)
+    .bind::<sql_types::BigInt, _>(limit)
+    .load::<Added>(connection)
+    .map(|dated| dated.into_iter().map(|a| a.added).collect())
 }
 
 pub fn get(id: Uuid, connection: &PgConnection) -> QueryResult<Article> {
@@ -72,6 +820,117 @@ pub fn update(
         .get_result(connection)
 }
 
+pub fn set_full_content(
+    id: Uuid,
+    full_content: Option<String>,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::update(articles::table.find(id))
+        .set(articles::full_content.eq(full_content))
+        .execute(connection)
+}
+
 pub fn delete(id: Uuid, connection: &PgConnection) -> QueryResult<usize> {
     diesel::delete(articles::table.find(id)).execute(connection)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn cursor_round_trip() {
+        let id = Uuid::new_v4();
+        let published = Cursor {
+            published: Some(Timestamp(time::Timespec::new(1_600_000_000, 0))),
+            id,
+        };
+        assert_eq!(published.to_string(), format!("1600000000_{}", id));
+        assert_eq!(published.to_string().parse(), Ok(published));
+
+        let mid_second = Cursor {
+            published: Some(Timestamp(time::Timespec::new(
+                1_600_000_000,
+                5_000,
+            ))),
+            id,
+        };
+        assert_eq!(
+            mid_second.to_string(),
+            format!("1600000000.000005000_{}", id)
+        );
+        assert_eq!(mid_second.to_string().parse(), Ok(mid_second));
+
+        let unpublished = Cursor {
+            published: None,
+            id,
+        };
+        assert_eq!(unpublished.to_string(), format!("_{}", id));
+        assert_eq!(unpublished.to_string().parse(), Ok(unpublished));
+    }
+
+    #[test]
+    fn malformed_cursors() {
+        let id = Uuid::new_v4();
+        for cursor in &[
+            "".to_string(),
+            "1600000000".to_string(),
+            "1600000000_".to_string(),
+            "1600000000_not-a-uuid".to_string(),
+            format!("soon_{}", id),
+            format!("1.5_{}", id),
+            format!("1._{}", id),
+            format!("1.+00000005_{}", id),
+            format!("1.0000000050_{}", id),
+            id.to_string(),
+        ] {
+            assert!(cursor.parse::<Cursor>().is_err(), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn pages_within_a_second() {
+        let conn = testing::conn();
+        let (username, source) =
+            testing::seed_user("cursor", "hunter22", &conn);
+        // Ordered by time, not by id
+        let mut ids = vec![];
+        for usec in &[900, 500, 100] {
+            let article = insert(
+                Article {
+                    source,
+                    published: Some(Timestamp(time::Timespec::new(
+                        2_000_000_000,
+                        usec * 1_000,
+                    ))),
+                    ..testing::article()
+                },
+                &conn,
+            )
+            .unwrap();
+            ids.push(article.id);
+        }
+
+        let filter = ArticleFilter {
+            published_after: Some(Timestamp(time::Timespec::new(
+                1_999_999_999,
+                0,
+            ))),
+            ..ArticleFilter::default()
+        };
+        let mut cursor = None;
+        let mut paged = vec![];
+        for _ in 0..ids.len() {
+            let page =
+                all_from_user(username.clone(), &filter, cursor, 1, &conn)
+                    .unwrap();
+            paged.push(page[0].id);
+            // Through its string form, as clients see it
+            cursor = Some(Cursor::after(&page[0]).to_string().parse().unwrap());
+        }
+        assert_eq!(paged, ids);
+
+        testing::remove_user(username, &conn);
+    }
+}
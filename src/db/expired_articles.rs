@@ -0,0 +1,18 @@
+use crate::schema::expired_articles;
+use diesel::prelude::*;
+
+use uuid::Uuid;
+
+/// Which of a source's `id_from_source`s belong to articles retention has
+/// deleted, see `articles::delete_expired`.
+pub fn all_with_ids(
+    source: Uuid,
+    ids_from_source: &[&str],
+    connection: &PgConnection,
+) -> QueryResult<Vec<String>> {
+    expired_articles::table
+        .select(expired_articles::id_from_source)
+        .filter(expired_articles::source.eq(source))
+        .filter(expired_articles::id_from_source.eq_any(ids_from_source))
+        .load(connection)
+}
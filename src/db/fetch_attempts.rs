@@ -0,0 +1,107 @@
+use crate::{
+    db::sources::Source, schema::fetch_attempts, timestamp::Timestamp,
+};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+/// Fetch attempts kept per source. Older attempts are deleted.
+pub const MAX_FETCH_ATTEMPTS: i64 = 50;
+
+// Values for `FetchAttempt.error_category`
+pub const ERROR_DNS: &str = "dns";
+pub const ERROR_TLS: &str = "tls";
+pub const ERROR_TIMEOUT: &str = "timeout";
+/// Couldn't connect, or the connection failed partway
+pub const ERROR_CONNECTION: &str = "connection";
+/// An unsuccessful HTTP status
+pub const ERROR_HTTP: &str = "http";
+pub const ERROR_PARSE_RSS: &str = "parse_rss";
+pub const ERROR_PARSE_ATOM: &str = "parse_atom";
+pub const ERROR_PARSE_JSON_FEED: &str = "parse_json_feed";
+pub const ERROR_DB: &str = "db";
+pub const ERROR_OTHER: &str = "other";
+
+/// A single attempt at fetching a source.
+#[derive(
+    Associations,
+    Queryable,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+)]
+#[table_name = "fetch_attempts"]
+#[belongs_to(Source, foreign_key = "source")]
+pub struct FetchAttempt {
+    pub id: Uuid,
+    pub source: Uuid,
+    pub started: Timestamp,
+    pub duration_ms: i32,
+    pub http_status: Option<i32>,
+    /// One of the `ERROR_*` categories, or `None` on success
+    pub error_category: Option<String>,
+    pub error: Option<String>,
+    /// Size of the response body, if one was read
+    pub bytes: Option<i64>,
+    pub new_articles: i32,
+}
+
+impl FetchAttempt {
+    pub fn new(
+        source: Uuid,
+        started: Timestamp,
+        duration_ms: i32,
+    ) -> FetchAttempt {
+        FetchAttempt {
+            id: Uuid::new_v4(),
+            source,
+            started,
+            duration_ms,
+            http_status: None,
+            error_category: None,
+            error: None,
+            bytes: None,
+            new_articles: 0,
+        }
+    }
+}
+
+/// A source's fetch attempts, newest first.
+pub fn all_from_source(
+    source: Uuid,
+    connection: &PgConnection,
+) -> QueryResult<Vec<FetchAttempt>> {
+    fetch_attempts::table
+        .filter(fetch_attempts::source.eq(source))
+        .order(fetch_attempts::started.desc())
+        .load::<FetchAttempt>(connection)
+}
+
+/// Insert an attempt, dropping the source's oldest attempts past
+/// `MAX_FETCH_ATTEMPTS`.
+pub fn insert(
+    attempt: FetchAttempt,
+    connection: &PgConnection,
+) -> QueryResult<FetchAttempt> {
+    connection.transaction(|| {
+        let attempt: FetchAttempt = diesel::insert_into(fetch_attempts::table)
+            .values(attempt)
+            .get_result(connection)?;
+        let newest = fetch_attempts::table
+            .select(fetch_attempts::id)
+            .filter(fetch_attempts::source.eq(attempt.source))
+            .order(fetch_attempts::started.desc())
+            .limit(MAX_FETCH_ATTEMPTS)
+            .load::<Uuid>(connection)?;
+        diesel::delete(
+            fetch_attempts::table
+                .filter(fetch_attempts::source.eq(attempt.source))
+                .filter(fetch_attempts::id.ne_all(newest)),
+        )
+        .execute(connection)?;
+        Ok(attempt)
+    })
+}
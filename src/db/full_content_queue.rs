@@ -0,0 +1,84 @@
+use crate::{schema::full_content_queue, timestamp::Timestamp};
+use diesel::prelude::*;
+
+use uuid::Uuid;
+
+/// Queue articles to have their full content fetched, see
+/// `sources::full_content::backfill`.
+pub fn push(
+    articles: &[Uuid],
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    if articles.is_empty() {
+        return Ok(0);
+    }
+    let now = Timestamp::now();
+    let rows: Vec<_> = articles
+        .iter()
+        .map(|article| {
+            (
+                full_content_queue::article.eq(*article),
+                full_content_queue::queued_at.eq(now),
+                full_content_queue::next_attempt.eq(now),
+            )
+        })
+        .collect();
+    diesel::insert_into(full_content_queue::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(connection)
+}
+
+/// A queued article, & how many times fetching it has failed.
+#[derive(Queryable, Debug, Clone, Copy)]
+pub struct Queued {
+    pub article: Uuid,
+    pub attempts: i32,
+}
+
+/// Claim up to `limit` of the longest queued articles that are due, & return
+/// them. They stay queued, but aren't due again until `lease` from now, so
+/// they're retried if they're never `remove`d or `retry`ed.
+pub fn take(
+    limit: i64,
+    lease: time::Duration,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Queued>> {
+    let now = Timestamp::now();
+    connection.transaction(|| {
+        let queued: Vec<Queued> = full_content_queue::table
+            .select((full_content_queue::article, full_content_queue::attempts))
+            .filter(full_content_queue::next_attempt.le(now))
+            .order(full_content_queue::queued_at)
+            .limit(limit)
+            .for_update()
+            .load(connection)?;
+        let articles: Vec<Uuid> = queued.iter().map(|q| q.article).collect();
+        diesel::update(
+            full_content_queue::table
+                .filter(full_content_queue::article.eq_any(&articles[..])),
+        )
+        .set(full_content_queue::next_attempt.eq(now + lease))
+        .execute(connection)?;
+        Ok(queued)
+    })
+}
+
+/// Try an article again at `next_attempt`, after `attempts` failures.
+pub fn retry(
+    article: Uuid,
+    attempts: i32,
+    next_attempt: Timestamp,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::update(full_content_queue::table.find(article))
+        .set((
+            full_content_queue::attempts.eq(attempts),
+            full_content_queue::next_attempt.eq(next_attempt),
+        ))
+        .execute(connection)
+}
+
+pub fn remove(article: Uuid, connection: &PgConnection) -> QueryResult<usize> {
+    diesel::delete(full_content_queue::table.find(article)).execute(connection)
+}
@@ -0,0 +1,97 @@
+use crate::{
+    db::{articles::ArticleFilter, users::User},
+    schema::output_feeds,
+};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+/// A user's articles republished as a feed: those of a source, of a tag, or
+/// that they've starred.
+///
+/// Public feeds can be read by anyone with `token`, which is replaced to
+/// revoke access. Private feeds can only be read by their owner.
+#[derive(
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+    Clone,
+)]
+#[table_name = "output_feeds"]
+#[belongs_to(User, foreign_key = "owner")]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct OutputFeed {
+    pub id: Uuid,
+    pub owner: String,
+    pub title: String,
+    pub source: Option<Uuid>,
+    pub tag: Option<Uuid>,
+    /// Only starred articles. With no source or tag, every starred article.
+    pub starred: bool,
+    pub public: bool,
+    pub token: Uuid,
+}
+
+impl OutputFeed {
+    /// The articles this feed republishes.
+    pub fn filter(&self) -> ArticleFilter {
+        ArticleFilter {
+            source: self.source,
+            tag: self.tag,
+            starred: if self.starred { Some(true) } else { None },
+            ..ArticleFilter::default()
+        }
+    }
+}
+
+pub fn all_from_user(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<OutputFeed>> {
+    output_feeds::table
+        .filter(output_feeds::owner.eq(username))
+        .load::<OutputFeed>(connection)
+}
+
+pub fn get(id: Uuid, connection: &PgConnection) -> QueryResult<OutputFeed> {
+    output_feeds::table
+        .find(id)
+        .get_result::<OutputFeed>(connection)
+}
+
+pub fn get_by_token(
+    token: Uuid,
+    connection: &PgConnection,
+) -> QueryResult<OutputFeed> {
+    output_feeds::table
+        .filter(output_feeds::token.eq(token))
+        .get_result::<OutputFeed>(connection)
+}
+
+pub fn insert(
+    feed: OutputFeed,
+    connection: &PgConnection,
+) -> QueryResult<OutputFeed> {
+    diesel::insert_into(output_feeds::table)
+        .values(feed)
+        .get_result(connection)
+}
+
+pub fn update(
+    feed: &OutputFeed,
+    connection: &PgConnection,
+) -> QueryResult<OutputFeed> {
+    diesel::update(output_feeds::table.find(feed.id))
+        .set(feed)
+        .get_result(connection)
+}
+
+pub fn delete(id: Uuid, connection: &PgConnection) -> QueryResult<usize> {
+    diesel::delete(output_feeds::table.find(id)).execute(connection)
+}
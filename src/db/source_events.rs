@@ -0,0 +1,85 @@
+use crate::{db::sources::Source, schema::source_events, timestamp::Timestamp};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use uuid::Uuid;
+
+/// Events kept per source. Older events are deleted.
+pub const MAX_SOURCE_EVENTS: i64 = 50;
+
+/// The source's URL was permanently redirected, and has been updated.
+pub const MOVED: &str = "moved";
+/// The source is gone for good, and has been disabled.
+pub const GONE: &str = "gone";
+/// The source asked to be fetched later, and its next fetch was delayed.
+pub const RETRY_AFTER: &str = "retry_after";
+
+/// A change the fetcher made to a source on its own, & why.
+#[derive(
+    Associations,
+    Queryable,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
+)]
+#[table_name = "source_events"]
+#[belongs_to(Source, foreign_key = "source")]
+pub struct SourceEvent {
+    pub id: Uuid,
+    pub source: Uuid,
+    pub at: Timestamp,
+    /// One of `MOVED`, `GONE`, or `RETRY_AFTER`
+    pub kind: String,
+    pub detail: String,
+}
+
+impl SourceEvent {
+    pub fn new(source: Uuid, kind: &str, detail: String) -> SourceEvent {
+        SourceEvent {
+            id: Uuid::new_v4(),
+            source,
+            at: Timestamp::now(),
+            kind: kind.to_string(),
+            detail,
+        }
+    }
+}
+
+/// A source's events, newest first.
+pub fn all_from_source(
+    source: Uuid,
+    connection: &PgConnection,
+) -> QueryResult<Vec<SourceEvent>> {
+    source_events::table
+        .filter(source_events::source.eq(source))
+        .order(source_events::at.desc())
+        .load::<SourceEvent>(connection)
+}
+
+/// Insert an event, dropping the source's oldest events past
+/// `MAX_SOURCE_EVENTS`.
+pub fn insert(
+    event: SourceEvent,
+    connection: &PgConnection,
+) -> QueryResult<SourceEvent> {
+    connection.transaction(|| {
+        let event: SourceEvent = diesel::insert_into(source_events::table)
+            .values(event)
+            .get_result(connection)?;
+        let newest = source_events::table
+            .select(source_events::id)
+            .filter(source_events::source.eq(event.source))
+            .order(source_events::at.desc())
+            .limit(MAX_SOURCE_EVENTS)
+            .load::<Uuid>(connection)?;
+        diesel::delete(
+            source_events::table
+                .filter(source_events::source.eq(event.source))
+                .filter(source_events::id.ne_all(newest)),
+        )
+        .execute(connection)?;
+        Ok(event)
+    })
+}
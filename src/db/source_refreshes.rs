@@ -0,0 +1,30 @@
+use crate::{schema::source_refreshes, timestamp::Timestamp};
+use diesel::prelude::*;
+
+use uuid::Uuid;
+
+/// Ask for sources to be fetched as soon as possible.
+///
+/// Sources that already have a refresh pending keep their original request.
+pub fn request(
+    sources: &[Uuid],
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    if sources.is_empty() {
+        return Ok(0);
+    }
+    let now = Timestamp::now();
+    let rows: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            (
+                source_refreshes::source.eq(*source),
+                source_refreshes::requested_at.eq(now),
+            )
+        })
+        .collect();
+    diesel::insert_into(source_refreshes::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(connection)
+}
@@ -1,18 +1,41 @@
 use crate::{
-    db::{tagged_sources::TaggedSource, tags::Tag, users::User},
-    schema::{sources, tagged_sources},
-    sources::rssatom,
+    db::{tagged_sources::TaggedSource, tags::Tag, users::User, ShortId},
+    schema::{articles, source_refreshes, sources, tagged_sources},
+    sources::{jsonfeed, rssatom},
     timestamp::Timestamp,
 };
 use chrono::Duration;
-use diesel::prelude::*;
+use diesel::{prelude::*, sql_types};
 use serde::{Deserialize, Serialize};
 
 use uuid::Uuid;
 
+pub const DEFAULT_MIN_FETCH_INTERVAL: i32 = 15;
+pub const DEFAULT_MAX_FETCH_INTERVAL: i32 = 24 * 60;
+/// Minutes before a source stuck in `fetching` can be picked up again
+const FETCH_LOCK_TIMEOUT: i64 = 15;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum SourceData {
     RSSAtom(rssatom::RSSAtom),
+    JSONFeed(jsonfeed::JSONFeed),
+}
+
+impl SourceData {
+    /// Where this source's articles are fetched from.
+    pub fn url(&self) -> &str {
+        match self {
+            SourceData::RSSAtom(r) => r.url(),
+            SourceData::JSONFeed(j) => j.url(),
+        }
+    }
+
+    pub fn set_url(&mut self, url: String) {
+        match self {
+            SourceData::RSSAtom(r) => r.set_url(url),
+            SourceData::JSONFeed(j) => j.set_url(url),
+        }
+    }
 }
 
 #[derive(
@@ -27,6 +50,7 @@ pub enum SourceData {
 )]
 #[table_name = "sources"]
 #[belongs_to(User, foreign_key = "creator")]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct Source {
     pub id: Uuid,
     pub title: String,
@@ -36,10 +60,41 @@ pub struct Source {
     pub post_filter: String,
     pub last_post: Timestamp,
     pub last_successful_fetch: Timestamp,
-    pub fetch_errors: Vec<String>,
     pub creator: String,
     pub fetching: bool,
     pub last_fetch_started: Timestamp,
+    /// HTTP `ETag` from the last successful fetch
+    pub etag: Option<String>,
+    /// HTTP `Last-Modified` from the last successful fetch
+    pub last_modified: Option<String>,
+    /// Minutes between fetches, or the lower bound with `adaptive_fetch`
+    pub min_fetch_interval: i32,
+    /// Upper bound on minutes between fetches with `adaptive_fetch`
+    pub max_fetch_interval: i32,
+    /// Schedule fetches based on how often the source posts
+    pub adaptive_fetch: bool,
+    /// Minutes between updates the feed itself advertises
+    pub feed_ttl: Option<i32>,
+    pub next_fetch: Timestamp,
+    /// Skipped by the fetcher, ex: after the source reported it's gone
+    pub disabled: bool,
+    /// Failed fetches since the last successful one
+    pub consecutive_failures: i32,
+    /// Days to keep articles for. `None` uses the worker's default, and 0
+    /// keeps them forever.
+    pub retention_max_age: Option<i32>,
+    /// Articles to keep, newest first. `None` uses the worker's default,
+    /// and 0 keeps every article.
+    pub retention_max_count: Option<i32>,
+    /// Never delete starred articles. `None` uses the worker's default.
+    pub retention_keep_starred: Option<bool>,
+    /// Download each new article's page for its full content
+    pub fetch_full_content: bool,
+    /// CSS selector for the content on article pages. `None` finds it
+    /// heuristically.
+    pub content_selector: Option<String>,
+    /// The site the feed is for, from the last successful fetch
+    pub site_link: Option<String>,
     /* TODO optional config line for sharing
      * TODO optional config arg to make copies on Source changes, on
      * untrusted servers */
@@ -61,15 +116,29 @@ impl Source {
             creator,
             last_successful_fetch: Timestamp::now(),
             last_post: Timestamp::now(),
-            fetch_errors: Vec::new(),
             fetching: false,
             last_fetch_started: Timestamp::now(),
+            etag: None,
+            last_modified: None,
+            min_fetch_interval: DEFAULT_MIN_FETCH_INTERVAL,
+            max_fetch_interval: DEFAULT_MAX_FETCH_INTERVAL,
+            adaptive_fetch: false,
+            feed_ttl: None,
+            next_fetch: Timestamp::now(),
+            disabled: false,
+            consecutive_failures: 0,
+            retention_max_age: None,
+            retention_max_count: None,
+            retention_keep_starred: None,
+            fetch_full_content: false,
+            content_selector: None,
+            site_link: None,
         }
     }
 }
 
 pub fn all(connection: &PgConnection) -> QueryResult<Vec<Source>> {
-    sources::table.load::<Source>(&*connection)
+    sources::table.load::<Source>(connection)
 }
 
 pub fn all_from_user(
@@ -78,7 +147,17 @@ pub fn all_from_user(
 ) -> QueryResult<Vec<Source>> {
     sources::table
         .filter(sources::creator.eq(username))
-        .load::<Source>(&*connection)
+        .load::<Source>(connection)
+}
+
+/// Numeric ids of the user's sources.
+pub fn short_ids_from_user(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<ShortId>> {
+    diesel::sql_query("SELECT id, short_id FROM sources WHERE creator = $1")
+        .bind::<sql_types::Text, _>(username)
+        .load(connection)
 }
 
 pub fn all_from_tag(
@@ -88,7 +167,7 @@ pub fn all_from_tag(
     tagged_sources::table
         .filter(tagged_sources::tag.eq(tag.id))
         .inner_join(sources::table)
-        .load::<(TaggedSource, Source)>(&*connection)
+        .load::<(TaggedSource, Source)>(connection)
         .map(|v| v.into_iter().map(|(_tagged_src, src)| src).collect())
 }
 
@@ -96,34 +175,72 @@ pub fn get(id: Uuid, connection: &PgConnection) -> QueryResult<Source> {
     sources::table.find(id).get_result::<Source>(connection)
 }
 
-/// Get all sources that need to be fetched.
+/// Get all sources that need to be fetched, starting with those that had a
+/// refresh requested.
 ///
 /// `get`, but checks & sets `fetching=true` & last_fetch_started.
-/// The client is responsible for setting  `fetching=false` and
+/// The client is responsible for setting  `fetching=false`, next_fetch, and
 /// last_successful_fetch upon success.
 pub fn get_for_fetch(connection: &PgConnection) -> QueryResult<Vec<Source>> {
+    lock_for_fetch(true, connection)
+}
+
+/// `get_for_fetch`, but only sources that had a refresh requested.
+pub fn get_refreshed_for_fetch(
+    connection: &PgConnection,
+) -> QueryResult<Vec<Source>> {
+    lock_for_fetch(false, connection)
+}
+
+fn lock_for_fetch(
+    include_due: bool,
+    connection: &PgConnection,
+) -> QueryResult<Vec<Source>> {
     let this_fetch = Timestamp::now();
     connection.transaction(|| {
+        let refreshed: Vec<Uuid> = source_refreshes::table
+            .select(source_refreshes::source)
+            .order(source_refreshes::requested_at)
+            .load(connection)?;
+        // Sources being fetched keep their refresh until they're done
         let mut sources = sources::table
             .for_update()
-            .filter(
-                sources::fetching
-                    .eq(false)
-                    .and(
-                        sources::last_successful_fetch
-                            .le(this_fetch - Duration::minutes(15)),
-                    )
-                    .or(sources::fetching.eq(true).and(
-                        sources::last_fetch_started
-                            .le(this_fetch - Duration::minutes(15)),
-                    )),
-            )
-            .load::<Source>(&*connection)?;
+            .filter(sources::disabled.eq(false))
+            .filter(sources::fetching.eq(false))
+            .filter(sources::id.eq_any(&refreshed[..]))
+            .load::<Source>(connection)?;
+        sources.sort_by_key(|source| {
+            refreshed.iter().position(|id| *id == source.id)
+        });
+        let selected: Vec<Uuid> = sources.iter().map(|s| s.id).collect();
+        diesel::delete(
+            source_refreshes::table
+                .filter(source_refreshes::source.eq_any(&selected[..])),
+        )
+        .execute(connection)?;
+
+        if include_due {
+            let due = sources::table
+                .for_update()
+                .filter(sources::disabled.eq(false))
+                .filter(sources::id.ne_all(&selected[..]))
+                .filter(
+                    sources::fetching
+                        .eq(false)
+                        .and(sources::next_fetch.le(this_fetch))
+                        .or(sources::fetching
+                            .eq(true)
+                            .and(sources::last_fetch_started.le(this_fetch
+                                - Duration::minutes(FETCH_LOCK_TIMEOUT)))),
+                )
+                .load::<Source>(connection)?;
+            sources.extend(due);
+        }
 
         for source in &mut sources {
             source.fetching = true;
             source.last_fetch_started = this_fetch;
-            update(source, &connection)?;
+            update(source, connection)?;
         }
         Ok(sources)
     })
@@ -147,7 +264,49 @@ pub fn update(
         .get_result(connection)
 }
 
+/// Save what fetching a source changed, leaving everything users can edit
+/// as it is. Edits made through the API while a fetch was running would be
+/// reverted by `update`.
+pub fn update_fetched(
+    source: &Source,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::update(sources::table.find(source.id))
+        .set((
+            sources::fetching.eq(source.fetching),
+            sources::etag.eq(&source.etag),
+            sources::last_modified.eq(&source.last_modified),
+            sources::feed_ttl.eq(source.feed_ttl),
+            sources::site_link.eq(&source.site_link),
+            sources::next_fetch.eq(source.next_fetch),
+            sources::consecutive_failures.eq(source.consecutive_failures),
+            sources::last_successful_fetch.eq(source.last_successful_fetch),
+            // Fetching only ever disables a source
+            sources::disabled.eq(sources::disabled.or(source.disabled)),
+        ))
+        .execute(connection)
+}
+
+/// Point a source at a new URL, for a source that moved permanently.
+pub fn set_source_data(
+    id: Uuid,
+    source_data: &serde_json::Value,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::update(sources::table.find(id))
+        .set(sources::source_data.eq(source_data))
+        .execute(connection)
+}
+
+/// Delete a source, along with its articles & tags.
 pub fn delete(id: Uuid, connection: &PgConnection) -> QueryResult<usize> {
-    diesel::delete(sources::table.find(id)).execute(connection)
-    // TODO delete articles under this source?
+    connection.transaction(|| {
+        diesel::delete(
+            tagged_sources::table.filter(tagged_sources::source.eq(id)),
+        )
+        .execute(connection)?;
+        diesel::delete(articles::table.filter(articles::source.eq(id)))
+            .execute(connection)?;
+        diesel::delete(sources::table.find(id)).execute(connection)
+    })
 }
@@ -1,12 +1,20 @@
 use crate::{
     db::{sources::Source, tags::Tag},
-    schema::tagged_sources,
+    schema::{tagged_sources, tags},
 };
 use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(
-    Associations, Queryable, AsChangeset, Debug, Identifiable, Insertable,
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
 )]
 #[table_name = "tagged_sources"]
 #[belongs_to(Tag, foreign_key = "tag")]
@@ -23,6 +31,33 @@ pub fn get(id: Uuid, connection: &PgConnection) -> QueryResult<TaggedSource> {
         .get_result::<TaggedSource>(connection)
 }
 
+pub fn get_by_tag_and_source(
+    tag: Uuid,
+    source: Uuid,
+    connection: &PgConnection,
+) -> QueryResult<TaggedSource> {
+    tagged_sources::table
+        .filter(tagged_sources::tag.eq(tag))
+        .filter(tagged_sources::source.eq(source))
+        .get_result::<TaggedSource>(connection)
+}
+
+/// Every tagging of the user's tags.
+pub fn all_from_user(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<TaggedSource>> {
+    tagged_sources::table
+        .filter(
+            tagged_sources::tag.eq_any(
+                tags::table
+                    .select(tags::id)
+                    .filter(tags::owner.eq(username)),
+            ),
+        )
+        .load::<TaggedSource>(connection)
+}
+
 pub fn insert(
     tagged_src: TaggedSource,
     connection: &PgConnection,
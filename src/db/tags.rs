@@ -1,9 +1,20 @@
-use crate::{db::users::User, schema::tags};
-use diesel::prelude::*;
+use crate::{
+    db::{users::User, ShortId},
+    schema::{tagged_sources, tags},
+};
+use diesel::{prelude::*, sql_types};
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(
-    Associations, Queryable, AsChangeset, Debug, Identifiable, Insertable,
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Serialize,
+    Deserialize,
 )]
 #[table_name = "tags"]
 #[belongs_to(User, foreign_key = "owner")]
@@ -21,7 +32,17 @@ pub fn all_from_user(
 ) -> QueryResult<Vec<Tag>> {
     tags::table
         .filter(tags::owner.eq(username))
-        .load::<Tag>(&*connection)
+        .load::<Tag>(connection)
+}
+
+/// Numeric ids of the user's tags.
+pub fn short_ids_from_user(
+    username: String,
+    connection: &PgConnection,
+) -> QueryResult<Vec<ShortId>> {
+    diesel::sql_query("SELECT id, short_id FROM tags WHERE owner = $1")
+        .bind::<sql_types::Text, _>(username)
+        .load(connection)
 }
 
 pub fn get(id: Uuid, connection: &PgConnection) -> QueryResult<Tag> {
@@ -40,6 +61,13 @@ pub fn update(tag: Tag, connection: &PgConnection) -> QueryResult<Tag> {
         .get_result(connection)
 }
 
+/// Delete a tag, along with every `tagged_sources` row that references it.
 pub fn delete(id: Uuid, connection: &PgConnection) -> QueryResult<usize> {
-    diesel::delete(tags::table.find(id)).execute(connection)
+    connection.transaction(|| {
+        diesel::delete(
+            tagged_sources::table.filter(tagged_sources::tag.eq(id)),
+        )
+        .execute(connection)?;
+        diesel::delete(tags::table.find(id)).execute(connection)
+    })
 }
@@ -36,7 +36,7 @@ pub fn all_for_user(
 ) -> QueryResult<Vec<Token>> {
     tokens::table
         .filter(tokens::username.eq(username))
-        .load::<Token>(&*connection)
+        .load::<Token>(connection)
 }
 
 pub fn delete(id: Uuid, connection: &PgConnection) -> QueryResult<usize> {
@@ -1,7 +1,14 @@
 use crate::schema::users;
-use diesel::prelude::*;
+use diesel::{prelude::*, sql_types};
 use serde::{Deserialize, Serialize};
 
+/// Postgres text search configuration used for new users.
+pub const DEFAULT_SEARCH_LANGUAGE: &str = "english";
+
+fn default_search_language() -> String {
+    DEFAULT_SEARCH_LANGUAGE.to_string()
+}
+
 #[derive(
     Queryable,
     AsChangeset,
@@ -12,14 +19,26 @@ use serde::{Deserialize, Serialize};
     Insertable,
 )]
 #[table_name = "users"]
-#[primary_key("username")]
+#[primary_key(username)]
 pub struct User {
     pub username: String,
     pub password: String,
+    /// Postgres text search configuration for searching the user's articles
+    #[serde(default = "default_search_language")]
+    pub search_language: String,
+    /// Set while the Fever API is on. See `set_fever_api_key`.
+    #[serde(skip)]
+    pub fever_api_key: Option<String>,
+}
+
+#[derive(QueryableByName)]
+struct Exists {
+    #[sql_type = "sql_types::Bool"]
+    exists: bool,
 }
 
 pub fn all(connection: &PgConnection) -> QueryResult<Vec<User>> {
-    users::table.load::<User>(&*connection)
+    users::table.load::<User>(connection)
 }
 
 pub fn get(username: String, connection: &PgConnection) -> QueryResult<User> {
@@ -38,6 +57,65 @@ pub fn update(user: User, connection: &PgConnection) -> QueryResult<User> {
         .get_result(connection)
 }
 
+/// Whether `language` is a Postgres text search configuration.
+pub fn is_search_language(
+    language: &str,
+    connection: &PgConnection,
+) -> QueryResult<bool> {
+    diesel::sql_query(
+        "SELECT EXISTS (SELECT 1 FROM pg_ts_config WHERE cfgname = $1)",
+    )
+    .bind::<sql_types::Text, _>(language)
+    .get_result::<Exists>(connection)
+    .map(|e| e.exists)
+}
+
+/// Change a user's search language, re-indexing their articles with it.
+pub fn set_search_language(
+    username: String,
+    language: String,
+    connection: &PgConnection,
+) -> QueryResult<User> {
+    connection.transaction(|| {
+        diesel::sql_query(
+            "UPDATE articles SET search_language = $2::REGCONFIG
+             WHERE source IN (SELECT id FROM sources WHERE creator = $1)",
+        )
+        .bind::<sql_types::Text, _>(&username)
+        .bind::<sql_types::Text, _>(&language)
+        .execute(connection)?;
+        diesel::update(users::table.find(username))
+            .set(users::search_language.eq(language))
+            .get_result(connection)
+    })
+}
+
+/// Set the user's Fever API key to `md5("<username>:<password>")`, the way
+/// Fever clients work it out. Without a password, the Fever API is turned
+/// off for them.
+pub fn set_fever_api_key(
+    username: String,
+    password: Option<&str>,
+    connection: &PgConnection,
+) -> QueryResult<usize> {
+    diesel::sql_query(
+        "UPDATE users SET fever_api_key = md5(username || ':' || $2)
+         WHERE username = $1",
+    )
+    .bind::<sql_types::Text, _>(username)
+    .bind::<sql_types::Nullable<sql_types::Text>, _>(password)
+    .execute(connection)
+}
+
+pub fn get_by_fever_api_key(
+    api_key: &str,
+    connection: &PgConnection,
+) -> QueryResult<User> {
+    users::table
+        .filter(users::fever_api_key.eq(api_key.to_lowercase()))
+        .get_result::<User>(connection)
+}
+
 pub fn delete(
     username: String,
     connection: &PgConnection,
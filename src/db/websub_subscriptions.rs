@@ -0,0 +1,109 @@
+use crate::{
+    db::sources::Source, schema::websub_subscriptions, timestamp::Timestamp,
+};
+use diesel::prelude::*;
+
+use uuid::Uuid;
+
+/// A source's subscription to its WebSub hub, which pushes new content to
+/// `/api/websub/<source>` instead of it being polled.
+///
+/// Rows are added by the worker when a feed advertises a hub, and the web
+/// server (which receives the pushes) asks the hub to subscribe.
+#[derive(
+    Associations,
+    Queryable,
+    AsChangeset,
+    Debug,
+    Identifiable,
+    Insertable,
+    Clone,
+    PartialEq,
+)]
+#[table_name = "websub_subscriptions"]
+#[primary_key(source)]
+#[belongs_to(Source, foreign_key = "source")]
+#[changeset_options(treat_none_as_null = "true")]
+pub struct WebSubSubscription {
+    pub source: Uuid,
+    pub hub: String,
+    /// The feed URL the hub knows the source by
+    pub topic: String,
+    /// Key for the HMAC signatures on pushed content
+    pub secret: String,
+    /// When the hub was last asked to subscribe
+    pub requested_at: Option<Timestamp>,
+    /// When the hub stops pushing, unless the subscription's renewed. Unset
+    /// until the hub verifies the subscription.
+    pub lease_expires: Option<Timestamp>,
+}
+
+impl WebSubSubscription {
+    pub fn new(source: Uuid, hub: String, topic: String) -> Self {
+        WebSubSubscription {
+            source,
+            hub,
+            topic,
+            secret: Uuid::new_v4().to_simple().to_string(),
+            requested_at: None,
+            lease_expires: None,
+        }
+    }
+
+    /// Whether the hub is pushing updates.
+    pub fn is_active(&self, now: Timestamp) -> bool {
+        self.lease_expires.map_or(false, |expires| expires > now)
+    }
+}
+
+pub fn get(
+    source: Uuid,
+    connection: &PgConnection,
+) -> QueryResult<WebSubSubscription> {
+    websub_subscriptions::table
+        .find(source)
+        .get_result::<WebSubSubscription>(connection)
+}
+
+/// Subscriptions to request: new ones, those whose request wasn't verified
+/// before `retry_before`, & those expiring before `renew_before`.
+pub fn all_due(
+    retry_before: Timestamp,
+    renew_before: Timestamp,
+    connection: &PgConnection,
+) -> QueryResult<Vec<WebSubSubscription>> {
+    websub_subscriptions::table
+        .filter(
+            websub_subscriptions::requested_at.is_null().or(
+                websub_subscriptions::requested_at.le(retry_before).and(
+                    websub_subscriptions::lease_expires
+                        .is_null()
+                        .or(websub_subscriptions::lease_expires
+                            .le(renew_before)),
+                ),
+            ),
+        )
+        .load::<WebSubSubscription>(connection)
+}
+
+pub fn insert(
+    subscription: WebSubSubscription,
+    connection: &PgConnection,
+) -> QueryResult<WebSubSubscription> {
+    diesel::insert_into(websub_subscriptions::table)
+        .values(subscription)
+        .get_result(connection)
+}
+
+pub fn update(
+    subscription: &WebSubSubscription,
+    connection: &PgConnection,
+) -> QueryResult<WebSubSubscription> {
+    diesel::update(websub_subscriptions::table.find(subscription.source))
+        .set(subscription)
+        .get_result(connection)
+}
+
+pub fn delete(source: Uuid, connection: &PgConnection) -> QueryResult<usize> {
+    diesel::delete(websub_subscriptions::table.find(source)).execute(connection)
+}
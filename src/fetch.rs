@@ -1,69 +1,524 @@
 use crate::{
     db,
-    db::{articles, articles::Article, sources},
-    sources::rssatom::SourceData,
+    db::{
+        articles, articles::Article, fetch_attempts,
+        fetch_attempts::FetchAttempt, full_content_queue, source_events,
+        source_events::SourceEvent, sources, websub_subscriptions,
+        websub_subscriptions::WebSubSubscription,
+    },
+    post_filter::PostFilter,
+    retention::RetentionPolicy,
+    schedule,
+    sources::{
+        http::{
+            ConnectError, FetchCache, FetchResponse, Fetched, MovedError,
+            StatusError,
+        },
+        jsonfeed::JSONFeedError,
+        rssatom::{HubLinks, ParsedFeed, RSSFetchError, SourceData},
+    },
+    timestamp::Timestamp,
     Result,
 };
 
-const MAX_FETCH_ERRORS: usize = 10;
+use diesel::{result::OptionalExtension, Connection};
+use std::{
+    collections::{HashMap, VecDeque},
+    env,
+    error::Error,
+    sync::mpsc::{self, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// How often to check for refresh requests while fetching.
+const REFRESH_POLL: Duration = Duration::from_secs(5);
+
+/// Limits on how many sources are fetched at once.
+#[derive(Debug, Clone, Copy)]
+pub struct FetchLimits {
+    pub max_in_flight: usize,
+    /// Max sources fetched at once from a single host
+    pub max_per_host: usize,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        FetchLimits {
+            max_in_flight: 8,
+            max_per_host: 2,
+        }
+    }
+}
+
+impl FetchLimits {
+    /// Read limits from `FETCH_MAX_IN_FLIGHT` & `FETCH_MAX_PER_HOST`, using
+    /// the defaults for any that are unset.
+    pub fn from_env() -> FetchLimits {
+        let default = FetchLimits::default();
+        let var = |name, default| {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .filter(|v| *v > 0)
+                .unwrap_or(default)
+        };
+        FetchLimits {
+            max_in_flight: var("FETCH_MAX_IN_FLIGHT", default.max_in_flight),
+            max_per_host: var("FETCH_MAX_PER_HOST", default.max_per_host),
+        }
+    }
+}
+
+/// Tells the scheduler a fetch finished when dropped, even if the fetching
+/// thread panicked.
+struct FetchDone {
+    host: String,
+    done: Sender<String>,
+}
+
+impl Drop for FetchDone {
+    fn drop(&mut self) {
+        let _ = self.done.send(std::mem::take(&mut self.host));
+    }
+}
+
+/// Used to limit concurrent fetches per host. Sources without a parseable
+/// URL get their own bucket.
+fn source_host(source: &sources::Source) -> String {
+    serde_json::from_value::<sources::SourceData>(source.source_data.to_owned())
+        .ok()
+        .and_then(|data| reqwest::Url::parse(data.url()).ok())
+        .and_then(|url| url.host_str().map(|h| h.to_string()))
+        .unwrap_or_else(|| source.id.to_string())
+}
+
+/// Fetch every source that's due, several at a time.
+///
+/// Each source is fetched on its own thread with its own db connection, so
+/// a slow or failing source doesn't hold up the others. Refreshes requested
+/// in the meantime are fetched next.
+pub fn fetch_new_from_all_sources(
+    pool: &db::Pool,
+    limits: FetchLimits,
+    retention: RetentionPolicy,
+) -> Result<()> {
+    let mut pending: VecDeque<(String, sources::Source)> = {
+        let conn = db::DbConn(pool.get()?);
+        sources::get_for_fetch(&conn)?
+            .into_iter()
+            .map(|source| (source_host(&source), source))
+            .collect()
+    };
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut per_host: HashMap<String, usize> = HashMap::new();
+    let mut in_flight = 0;
+    let mut last_refresh_poll = Instant::now();
+
+    while !pending.is_empty() || in_flight > 0 {
+        while in_flight < limits.max_in_flight {
+            let next = pending.iter().position(|(host, _)| {
+                per_host.get(host).copied().unwrap_or(0) < limits.max_per_host
+            });
+            let (host, source) = match next.and_then(|i| pending.remove(i)) {
+                Some(next) => next,
+                None => break,
+            };
+
+            *per_host.entry(host.clone()).or_insert(0) += 1;
+            in_flight += 1;
+
+            let pool = pool.clone();
+            let done = FetchDone {
+                host,
+                done: done_tx.clone(),
+            };
+            thread::spawn(move || {
+                let _done = done;
+                let id = source.id;
+                if let Err(e) = fetch_and_store(&pool, source, retention) {
+                    log::error!("Fetching source {} failed: {}", id, e);
+                }
+            });
+        }
+
+        match done_rx.recv_timeout(REFRESH_POLL) {
+            Ok(host) => {
+                in_flight -= 1;
+                if let Some(count) = per_host.get_mut(&host) {
+                    *count -= 1;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        // Don't make refreshes wait for the rest of this round
+        if last_refresh_poll.elapsed() >= REFRESH_POLL {
+            last_refresh_poll = Instant::now();
+            let conn = db::DbConn(pool.get()?);
+            for source in
+                sources::get_refreshed_for_fetch(&conn)?.into_iter().rev()
+            {
+                pending.push_front((source_host(&source), source));
+            }
+        }
+    }
+
+    Ok(())
+}
 
-pub fn fetch_new_from_all_sources(pool: &mut db::Pool) -> Result<()> {
+/// Fetch a single source, and store its new articles. The source is always
+/// saved afterwards, so it isn't left `fetching` if recording the fetch
+/// fails.
+fn fetch_and_store(
+    pool: &db::Pool,
+    mut source: sources::Source,
+    retention: RetentionPolicy,
+) -> Result<()> {
     let conn = db::DbConn(pool.get()?);
-    let mut sources = sources::get_for_fetch(&*conn)?;
-
-    for source in &mut sources {
-        let new_articles = match fetch_new_from_source(&conn, &source) {
-            Ok(articles) => articles,
-            Err(e) => {
-                // Likely communication problems when connecting to the source
-                source.fetch_errors.push(format!(
-                    "{}: {}",
-                    time::at_utc(source.last_fetch_started.0).rfc822(),
-                    e
-                ));
-                // Only keep the latest errors
-                if source.fetch_errors.len() > MAX_FETCH_ERRORS {
-                    source.fetch_errors.drain(
-                        0..(source.fetch_errors.len() - MAX_FETCH_ERRORS),
-                    );
+    let result = fetch_and_record(&conn, &mut source, retention);
+    source.fetching = false;
+    sources::update_fetched(&source, &conn)?;
+    result
+}
+
+/// Fetch and store a source's new articles, and record how it went in its
+/// fetch attempts & schedule.
+fn fetch_and_record(
+    conn: &db::DbConn,
+    source: &mut sources::Source,
+    retention: RetentionPolicy,
+) -> Result<()> {
+    let started = Instant::now();
+    let result =
+        fetch_new_from_source(conn, source).and_then(|mut response| {
+            // Kept even if storing the rest fails
+            if let Some(new_url) = response.moved_to.take() {
+                move_source(conn, source, &new_url)?;
+            }
+            store(conn, source, response, retention)
+        });
+    let mut attempt = FetchAttempt::new(
+        source.id,
+        source.last_fetch_started,
+        started.elapsed().as_millis().min(i32::MAX as u128) as i32,
+    );
+
+    match result {
+        Ok(stored) => {
+            attempt.http_status = Some(i32::from(stored.status));
+            attempt.bytes = Some(stored.bytes as i64);
+            attempt.new_articles = stored.new_articles as i32;
+            source.consecutive_failures = 0;
+            source.last_successful_fetch = source.last_fetch_started;
+            let published = articles::recent_published(
+                source.id,
+                schedule::ADAPTIVE_SAMPLE_SIZE,
+                conn,
+            )?;
+            let now = Timestamp::now();
+            let pushed = websub_subscriptions::get(source.id, conn)
+                .optional()?
+                .map_or(false, |subscription| subscription.is_active(now));
+            source.next_fetch = if pushed {
+                schedule::next_pushed_fetch(source, now)
+            } else {
+                schedule::next_fetch(source, &published, now)
+            };
+        }
+        Err(e) => {
+            // The source moved before the fetch failed
+            let e = match e.downcast::<MovedError>() {
+                Ok(moved) => {
+                    move_source(conn, source, &moved.moved_to)?;
+                    moved.error
+                }
+                Err(e) => e,
+            };
+            attempt.http_status = error_status(&*e).map(i32::from);
+            attempt.error_category = Some(error_category(&*e).to_string());
+            attempt.error = Some(e.to_string());
+            source.consecutive_failures += 1;
+            // Backing off, so broken sources aren't fetched constantly
+            source.next_fetch = schedule::next_retry(source, Timestamp::now());
+            match e.downcast_ref::<StatusError>() {
+                Some(StatusError::Gone) => {
+                    source.disabled = true;
+                    source_events::insert(
+                        SourceEvent::new(
+                            source.id,
+                            source_events::GONE,
+                            "Source responded 410 Gone, and was disabled"
+                                .to_string(),
+                        ),
+                        conn,
+                    )?;
                 }
-                // TODO only update fetch_errors
-                sources::update(source, &conn)?;
-                continue;
+                Some(StatusError::RetryAfter { at, .. }) => {
+                    // Don't let the source push its next fetch back further
+                    // than the user would
+                    let latest = Timestamp::now()
+                        + time::Duration::minutes(i64::from(
+                            source.max_fetch_interval,
+                        ));
+                    let at = if *at > latest { latest } else { *at };
+                    if at > source.next_fetch {
+                        source.next_fetch = at;
+                    }
+                    source_events::insert(
+                        SourceEvent::new(
+                            source.id,
+                            source_events::RETRY_AFTER,
+                            format!(
+                                "Source asked to be fetched later, next \
+                                 fetch delayed until {}",
+                                time::at_utc(source.next_fetch.0).rfc822()
+                            ),
+                        ),
+                        conn,
+                    )?;
+                }
+                None => (),
             }
-        };
+        }
+    }
+
+    fetch_attempts::insert(attempt, conn)?;
+    Ok(())
+}
+
+/// What was kept from a successful fetch.
+struct Stored {
+    status: u16,
+    bytes: usize,
+    new_articles: usize,
+}
+
+/// Point a source at where it moved permanently.
+fn move_source(
+    conn: &db::DbConn,
+    source: &mut sources::Source,
+    new_url: &str,
+) -> Result<()> {
+    let mut source_data: sources::SourceData =
+        serde_json::from_value(source.source_data.to_owned())?;
+    let old_url = source_data.url().to_string();
+    source_data.set_url(new_url.to_string());
+    source.source_data = serde_json::to_value(source_data)?;
+    sources::set_source_data(source.id, &source.source_data, conn)?;
+    // They were for the old URL
+    source.etag = None;
+    source.last_modified = None;
+    source_events::insert(
+        SourceEvent::new(
+            source.id,
+            source_events::MOVED,
+            format!("Source moved permanently from {} to {}", old_url, new_url),
+        ),
+        conn,
+    )?;
+    Ok(())
+}
 
-        for article in new_articles {
-            articles::insert(article, &conn)?;
+/// Store new articles from a fetch, and any changes to the source, all or
+/// nothing. Where the source moved to is already saved, see `move_source`.
+fn store(
+    conn: &db::DbConn,
+    source: &mut sources::Source,
+    response: FetchResponse<ParsedFeed>,
+    retention: RetentionPolicy,
+) -> Result<Stored> {
+    conn.transaction::<_, Box<dyn Error>, _>(|| {
+        let mut new_articles = 0;
+        // A 304 Not Modified is still a successful fetch
+        if let Fetched::Modified(feed, cache) = response.fetched {
+            update_hub(conn, source, feed.hub)?;
+            new_articles = insert_new(conn, source, feed.articles, retention)?;
+            source.etag = cache.etag;
+            source.last_modified = cache.last_modified;
+            source.feed_ttl = feed.ttl;
+            source.site_link = feed.link;
         }
-        source.last_successful_fetch = source.last_fetch_started;
-        source.fetching = false;
-        // TODO only update last_successful_fetch, & fetching
-        sources::update(source, &conn)?;
+
+        Ok(Stored {
+            status: response.status,
+            bytes: response.bytes,
+            new_articles,
+        })
+    })
+}
+
+/// Insert a source's new articles, other than those retention would delete.
+fn insert_new(
+    conn: &db::DbConn,
+    source: &sources::Source,
+    mut new_articles: Vec<Article>,
+    retention: RetentionPolicy,
+) -> Result<usize> {
+    retention.for_source(source).skip_expired(
+        source,
+        &mut new_articles,
+        conn,
+    )?;
+    let count = new_articles.len();
+    let ids: Vec<Uuid> = new_articles.iter().map(|a| a.id).collect();
+    for article in new_articles {
+        articles::insert(article, conn)?;
     }
+    // Fetched later, see `full_content::backfill`
+    if source.fetch_full_content {
+        full_content_queue::push(&ids, conn)?;
+    }
+    Ok(count)
+}
 
+/// Keep the source's WebSub subscription in line with the hub its feed
+/// advertises. The web server subscribes to new or changed hubs.
+fn update_hub(
+    conn: &db::DbConn,
+    source: &sources::Source,
+    hub: Option<HubLinks>,
+) -> Result<()> {
+    let hub = match hub {
+        Some(hub) => hub,
+        None => {
+            websub_subscriptions::delete(source.id, conn)?;
+            return Ok(());
+        }
+    };
+    let topic = match hub.topic {
+        Some(topic) => topic,
+        None => {
+            let source_data: sources::SourceData =
+                serde_json::from_value(source.source_data.to_owned())?;
+            source_data.url().to_string()
+        }
+    };
+    let existing = websub_subscriptions::get(source.id, conn).optional()?;
+    if let Some(existing) = existing {
+        if existing.hub == hub.hub && existing.topic == topic {
+            return Ok(());
+        }
+        websub_subscriptions::delete(source.id, conn)?;
+    }
+    // A new secret for each hub & topic
+    websub_subscriptions::insert(
+        WebSubSubscription::new(source.id, hub.hub, topic),
+        conn,
+    )?;
     Ok(())
 }
 
-fn fetch_new_from_source(
+/// Store new articles from content a WebSub hub pushed for the source.
+/// Returns how many were added.
+pub fn store_pushed(
     conn: &db::DbConn,
     source: &sources::Source,
-) -> Result<Vec<Article>> {
+    body: &[u8],
+    retention: RetentionPolicy,
+) -> Result<usize> {
     let source_data = serde_json::from_value(source.source_data.to_owned())?;
-    let mut fetched_articles = fetch_from_source(&source_data)?;
+    let mut feed = match &source_data {
+        sources::SourceData::RSSAtom(r) => r.parse(body)?,
+        // Only RSS & Atom feeds advertise hubs
+        sources::SourceData::JSONFeed(_) => return Ok(0),
+    };
+    keep_new(conn, source, &source_data, &mut feed.articles)?;
+    insert_new(conn, source, feed.articles, retention)
+}
 
+/// Remove articles the source's filter excludes, or that are already stored.
+fn keep_new(
+    conn: &db::DbConn,
+    source: &sources::Source,
+    source_data: &sources::SourceData,
+    new_articles: &mut Vec<Article>,
+) -> Result<()> {
+    let post_filter = PostFilter::parse(&source.post_filter)?;
+    new_articles.retain(|article| post_filter.matches(article));
     match source_data {
-        sources::SourceData::RSSAtom(r) => {
-            r.unique(&mut fetched_articles, conn)?
+        sources::SourceData::RSSAtom(r) => r.unique(new_articles, conn),
+        sources::SourceData::JSONFeed(j) => j.unique(new_articles, conn),
+    }
+}
+
+/// HTTP status of a failed fetch, if it got that far.
+pub fn error_status(e: &(dyn Error + 'static)) -> Option<u16> {
+    if let Some(e) = e.downcast_ref::<MovedError>() {
+        return error_status(&*e.error);
+    }
+    if let Some(e) = e.downcast_ref::<StatusError>() {
+        return Some(e.status());
+    }
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(|e| e.status())
+        .map(|status| status.as_u16())
+}
+
+/// Which of the `fetch_attempts::ERROR_*` categories a fetch error is in.
+pub fn error_category(e: &(dyn Error + 'static)) -> &'static str {
+    if let Some(e) = e.downcast_ref::<MovedError>() {
+        return error_category(&*e.error);
+    }
+    if e.is::<StatusError>() {
+        return fetch_attempts::ERROR_HTTP;
+    }
+    if let Some(e) = e.downcast_ref::<RSSFetchError>() {
+        return if e.is_atom() {
+            fetch_attempts::ERROR_PARSE_ATOM
+        } else {
+            fetch_attempts::ERROR_PARSE_RSS
+        };
+    }
+    if e.is::<JSONFeedError>() {
+        return fetch_attempts::ERROR_PARSE_JSON_FEED;
+    }
+    if e.is::<diesel::result::Error>() || e.is::<r2d2::Error>() {
+        return fetch_attempts::ERROR_DB;
+    }
+    match e.downcast_ref::<ConnectError>() {
+        Some(ConnectError::Dns(_)) => return fetch_attempts::ERROR_DNS,
+        Some(ConnectError::Tls(_)) => return fetch_attempts::ERROR_TLS,
+        None => (),
+    }
+    if let Some(re) = e.downcast_ref::<reqwest::Error>() {
+        if re.is_timeout() {
+            return fetch_attempts::ERROR_TIMEOUT;
         }
+        if re.is_status() {
+            return fetch_attempts::ERROR_HTTP;
+        }
+        return fetch_attempts::ERROR_CONNECTION;
+    }
+    fetch_attempts::ERROR_OTHER
+}
+
+/// Get articles from a source that aren't in the db yet.
+fn fetch_new_from_source(
+    conn: &db::DbConn,
+    source: &sources::Source,
+) -> Result<FetchResponse<ParsedFeed>> {
+    let source_data = serde_json::from_value(source.source_data.to_owned())?;
+    let cache = FetchCache {
+        etag: source.etag.to_owned(),
+        last_modified: source.last_modified.to_owned(),
     };
-    Ok(fetched_articles)
+
+    fetch_from_source(&source_data, &cache)?.map(|mut feed| {
+        keep_new(conn, source, &source_data, &mut feed.articles)?;
+        Ok(feed)
+    })
 }
 
-fn fetch_from_source(
+pub fn fetch_from_source(
     source_data: &sources::SourceData,
-) -> Result<Vec<Article>> {
+    cache: &FetchCache,
+) -> Result<FetchResponse<ParsedFeed>> {
     match source_data {
-        sources::SourceData::RSSAtom(r) => r.fetch(),
+        sources::SourceData::RSSAtom(r) => r.fetch(cache),
+        sources::SourceData::JSONFeed(j) => j.fetch(cache),
     }
 }
@@ -10,16 +10,28 @@ extern crate rocket_contrib;
 extern crate time;
 #[macro_use]
 extern crate diesel;
+#[macro_use]
+extern crate lazy_static;
 
 pub mod api;
 pub mod db;
 pub mod fetch;
 pub mod logger;
+pub mod opml;
+pub mod output;
+pub mod post_filter;
+pub mod retention;
+pub mod sanitize;
+pub mod schedule;
 pub mod schema;
+pub mod search;
 pub mod setup_rocket;
 pub mod sources;
 pub mod state;
+#[cfg(test)]
+pub mod testing;
 pub mod timestamp;
+pub mod websub;
 
 use std::{error::Error, result::Result as StdResult};
 type Result<T> = StdResult<T, Box<dyn Error>>;
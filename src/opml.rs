@@ -0,0 +1,256 @@
+// OPML 2.0 reading & writing, for importing/exporting subscriptions.
+// Outline folders are mapped to tags.
+
+use crate::Result;
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use std::fmt::Write;
+
+/// A feed outline from an OPML document.
+#[derive(Debug, PartialEq)]
+pub struct OpmlFeed {
+    pub title: Option<String>,
+    pub xml_url: String,
+    /// Names of the folders this feed was found in.
+    pub tags: Vec<String>,
+}
+
+/// A folder of feeds to write to an OPML document. Feeds in a `None` folder
+/// are written at the top level.
+#[derive(Debug)]
+pub struct OpmlFolder {
+    pub name: Option<String>,
+    pub feeds: Vec<(String, String)>,
+}
+
+struct OutlineAttrs {
+    text: Option<String>,
+    xml_url: Option<String>,
+}
+
+fn outline_attrs(
+    element: &BytesStart,
+    reader: &Reader<&[u8]>,
+) -> Result<OutlineAttrs> {
+    let mut attrs = OutlineAttrs {
+        text: None,
+        xml_url: None,
+    };
+    let mut title = None;
+    for attr in element.attributes() {
+        let attr = attr?;
+        let value = attr.unescape_and_decode_value(reader)?;
+        match attr.key {
+            b"text" => attrs.text = Some(value),
+            b"title" => title = Some(value),
+            b"xmlUrl" => attrs.xml_url = Some(value),
+            _ => (),
+        }
+    }
+    attrs.text = attrs.text.filter(|t| !t.is_empty()).or(title);
+    Ok(attrs)
+}
+
+/// Get every feed in an OPML document.
+///
+/// Feeds that appear in several folders are only returned once, with each
+/// folder in `tags`.
+pub fn parse(opml: &str) -> Result<Vec<OpmlFeed>> {
+    let mut reader = Reader::from_str(opml);
+    reader.trim_text(true);
+
+    let mut feeds: Vec<OpmlFeed> = Vec::new();
+    // Names of the open outlines. `None` for feeds with children.
+    let mut folders: Vec<Option<String>> = Vec::new();
+    let mut buf = Vec::new();
+
+    loop {
+        buf.clear();
+        let (attrs, is_empty) = match reader.read_event(&mut buf)? {
+            Event::Start(ref e) if e.name() == b"outline" => {
+                (outline_attrs(e, &reader)?, false)
+            }
+            Event::Empty(ref e) if e.name() == b"outline" => {
+                (outline_attrs(e, &reader)?, true)
+            }
+            Event::End(ref e) if e.name() == b"outline" => {
+                folders.pop();
+                continue;
+            }
+            Event::Eof => break,
+            _ => continue,
+        };
+
+        match attrs.xml_url {
+            Some(xml_url) => {
+                let tags = folders.iter().flatten().cloned();
+                match feeds.iter_mut().find(|f| f.xml_url == xml_url) {
+                    Some(feed) => {
+                        for tag in tags {
+                            if !feed.tags.contains(&tag) {
+                                feed.tags.push(tag);
+                            }
+                        }
+                    }
+                    None => feeds.push(OpmlFeed {
+                        title: attrs.text,
+                        xml_url,
+                        tags: tags.collect(),
+                    }),
+                }
+                if !is_empty {
+                    folders.push(None);
+                }
+            }
+            None => {
+                if !is_empty {
+                    folders.push(attrs.text);
+                }
+            }
+        }
+    }
+
+    Ok(feeds)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn write_feed(out: &mut String, indent: &str, title: &str, url: &str) {
+    let _ = writeln!(
+        out,
+        r#"{}<outline type="rss" text="{}" title="{}" xmlUrl="{}"/>"#,
+        indent,
+        escape(title),
+        escape(title),
+        escape(url)
+    );
+}
+
+/// Write an OPML 2.0 document. Each feed is a `(title, url)` pair.
+pub fn generate(title: &str, folders: &[OpmlFolder]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(out, r#"<opml version="2.0">"#);
+    let _ = writeln!(out, "  <head>");
+    let _ = writeln!(out, "    <title>{}</title>", escape(title));
+    let _ = writeln!(out, "  </head>");
+    let _ = writeln!(out, "  <body>");
+    for folder in folders {
+        match &folder.name {
+            Some(name) => {
+                let _ = writeln!(
+                    out,
+                    r#"    <outline text="{}" title="{}">"#,
+                    escape(name),
+                    escape(name)
+                );
+                for (title, url) in &folder.feeds {
+                    write_feed(&mut out, "      ", title, url);
+                }
+                let _ = writeln!(out, "    </outline>");
+            }
+            None => {
+                for (title, url) in &folder.feeds {
+                    write_feed(&mut out, "    ", title, url);
+                }
+            }
+        }
+    }
+    let _ = writeln!(out, "  </body>");
+    let _ = writeln!(out, "</opml>");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_nested_opml() {
+        let opml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Top level" type="rss" xmlUrl="https://a.example/feed"/>
+    <outline title="News">
+      <outline text="B &amp; C" type="rss" xmlUrl="https://b.example/rss"/>
+      <outline text="Local">
+        <outline text="" title="D" type="rss" xmlUrl="https://d.example/atom"/>
+      </outline>
+    </outline>
+    <outline text="Tech">
+      <outline text="B &amp; C" type="rss" xmlUrl="https://b.example/rss"/>
+    </outline>
+    <outline text="Empty"/>
+  </body>
+</opml>"#;
+
+        let feeds = parse(opml).unwrap();
+        assert_eq!(
+            feeds,
+            vec![
+                OpmlFeed {
+                    title: Some("Top level".into()),
+                    xml_url: "https://a.example/feed".into(),
+                    tags: vec![],
+                },
+                OpmlFeed {
+                    title: Some("B & C".into()),
+                    xml_url: "https://b.example/rss".into(),
+                    tags: vec!["News".into(), "Tech".into()],
+                },
+                OpmlFeed {
+                    title: Some("D".into()),
+                    xml_url: "https://d.example/atom".into(),
+                    tags: vec!["News".into(), "Local".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_roundtrip() {
+        let folders = vec![
+            OpmlFolder {
+                name: None,
+                feeds: vec![("A".into(), "https://a.example/?x=1&y=2".into())],
+            },
+            OpmlFolder {
+                name: Some("<News>".into()),
+                feeds: vec![(
+                    "B \"quoted\"".into(),
+                    "https://b.example".into(),
+                )],
+            },
+        ];
+        let feeds = parse(&generate("Export", &folders)).unwrap();
+        assert_eq!(
+            feeds,
+            vec![
+                OpmlFeed {
+                    title: Some("A".into()),
+                    xml_url: "https://a.example/?x=1&y=2".into(),
+                    tags: vec![],
+                },
+                OpmlFeed {
+                    title: Some("B \"quoted\"".into()),
+                    xml_url: "https://b.example".into(),
+                    tags: vec!["<News>".into()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_bad_opml() {
+        parse("<opml><body><outline text=\"x></body></opml>").unwrap_err();
+    }
+}
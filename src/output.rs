@@ -0,0 +1,417 @@
+// Writing articles back out as Atom, RSS 2.0, or JSON Feed, for output
+// feeds (see `db::output_feeds`).
+
+use crate::{
+    db::articles::Article,
+    sanitize::{content_html, escape_html},
+    timestamp::Timestamp,
+};
+use chrono::{DateTime, FixedOffset, TimeZone};
+use serde::Serialize;
+use std::borrow::Cow;
+use uuid::Uuid;
+
+const JSON_FEED_VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Atom,
+    Rss,
+    JsonFeed,
+}
+
+/// About the feed itself, rather than its articles.
+#[derive(Debug)]
+pub struct OutputInfo {
+    pub id: Uuid,
+    pub title: String,
+    /// The site the articles are from, if there's just one
+    pub link: Option<String>,
+    /// When its sources last had a new article
+    pub updated: Timestamp,
+}
+
+#[derive(Serialize)]
+struct JsonFeed<'a> {
+    version: &'static str,
+    title: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    home_page_url: Option<&'a str>,
+    items: Vec<JsonItem<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonItem<'a> {
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    title: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_html: Option<Cow<'a, str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    date_published: Option<String>,
+    authors: Vec<JsonAuthor<'a>>,
+    attachments: Vec<JsonAttachment<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonAuthor<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    avatar: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct JsonAttachment<'a> {
+    url: &'a str,
+    mime_type: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size_in_bytes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    duration_in_seconds: Option<i32>,
+}
+
+/// Enclosures without a type are sent as arbitrary binary data.
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+impl OutputFormat {
+    /// From the last part of an output feed's URL.
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "atom" => Some(OutputFormat::Atom),
+            "rss" => Some(OutputFormat::Rss),
+            "json" => Some(OutputFormat::JsonFeed),
+            _ => None,
+        }
+    }
+
+    /// The MIME type, as `(top, sub)`.
+    pub fn mime_type(self) -> (&'static str, &'static str) {
+        match self {
+            OutputFormat::Atom => ("application", "atom+xml"),
+            OutputFormat::Rss => ("application", "rss+xml"),
+            OutputFormat::JsonFeed => ("application", "feed+json"),
+        }
+    }
+
+    pub fn render(self, info: &OutputInfo, articles: &[Article]) -> String {
+        match self {
+            OutputFormat::Atom => atom(info, articles),
+            OutputFormat::Rss => rss(info, articles),
+            OutputFormat::JsonFeed => json_feed(info, articles),
+        }
+    }
+}
+
+/// Article ids are only unique within their source, so every article is
+/// identified by its UUID instead.
+fn article_id(article: &Article) -> String {
+    format!("urn:uuid:{}", article.id)
+}
+
+fn date_time(timestamp: Timestamp) -> DateTime<FixedOffset> {
+    FixedOffset::east(0).timestamp(timestamp.0.sec, 0)
+}
+
+/// `atom_syndication` writes element text as it's given, so text has to be
+/// escaped before it's set. Attributes are escaped by the writer.
+fn atom(info: &OutputInfo, articles: &[Article]) -> String {
+    let entries: Vec<atom_syndication::Entry> = articles
+        .iter()
+        .map(|article| {
+            let mut entry = atom_syndication::Entry::default();
+            entry.set_id(article_id(article));
+            entry.set_title(escape_html(
+                article.title.as_deref().unwrap_or_default(),
+            ));
+            let published = article.published.map(date_time);
+            entry.set_updated(
+                published.unwrap_or_else(|| date_time(info.updated)),
+            );
+            entry.set_published(published);
+            entry.set_summary(article.summary.as_deref().map(escape_html));
+            if let Some(html) = content_html(article) {
+                let mut content = atom_syndication::Content::default();
+                content.set_value(escape_html(&html));
+                content.set_content_type("html".to_string());
+                entry.set_content(content);
+            }
+
+            let mut links = Vec::new();
+            if let Some(url) = article.links.article_url() {
+                let mut link = atom_syndication::Link::default();
+                link.set_href(url);
+                links.push(link);
+            }
+            for enclosure in &article.enclosures.0 {
+                let mut link = atom_syndication::Link::default();
+                link.set_href(enclosure.url.as_str());
+                link.set_rel("enclosure");
+                link.set_mime_type(enclosure.mime_type.clone());
+                link.set_length(enclosure.length.map(|l| l.to_string()));
+                links.push(link);
+            }
+            entry.set_links(links);
+            entry.set_authors(
+                article
+                    .authors
+                    .0
+                    .iter()
+                    .filter_map(|author| {
+                        let mut person = atom_syndication::Person::default();
+                        person.set_name(escape_html(author.name.as_deref()?));
+                        person.set_email(
+                            author.email.as_deref().map(escape_html),
+                        );
+                        person.set_uri(author.url.as_deref().map(escape_html));
+                        Some(person)
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            entry
+        })
+        .collect();
+
+    let mut feed = atom_syndication::Feed::default();
+    feed.set_id(format!("urn:uuid:{}", info.id));
+    feed.set_title(escape_html(&info.title));
+    feed.set_updated(date_time(info.updated));
+    if let Some(url) = &info.link {
+        let mut link = atom_syndication::Link::default();
+        link.set_href(url.as_str());
+        feed.set_links(vec![link]);
+    }
+    feed.set_entries(entries);
+    feed.to_string()
+}
+
+fn rss(info: &OutputInfo, articles: &[Article]) -> String {
+    let items: Vec<rss::Item> = articles
+        .iter()
+        .map(|article| {
+            let mut item = rss::Item::default();
+            item.set_title(article.title.clone());
+            item.set_link(article.links.article_url().map(|url| url.into()));
+            item.set_description(
+                content_html(article).map(|html| html.into_owned()),
+            );
+            let mut guid = rss::Guid::default();
+            guid.set_value(article_id(article));
+            guid.set_permalink(false);
+            item.set_guid(guid);
+            item.set_pub_date(
+                article.published.map(|p| date_time(p).to_rfc2822()),
+            );
+            // RSS authors are email addresses, but most feeds use names
+            item.set_author(article.authors.0.iter().find_map(|author| {
+                author.email.clone().or_else(|| author.name.clone())
+            }));
+            // RSS only has room for one enclosure
+            if let Some(first) = article.enclosures.0.first() {
+                let mut enclosure = rss::Enclosure::default();
+                enclosure.set_url(first.url.as_str());
+                enclosure.set_length(first.length.unwrap_or(0).to_string());
+                enclosure.set_mime_type(
+                    first.mime_type.as_deref().unwrap_or(DEFAULT_MIME_TYPE),
+                );
+                item.set_enclosure(enclosure);
+            }
+            item
+        })
+        .collect();
+
+    let mut channel = rss::Channel::default();
+    channel.set_title(info.title.as_str());
+    channel.set_link(info.link.clone().unwrap_or_default());
+    channel.set_description(info.title.as_str());
+    channel.set_last_build_date(date_time(info.updated).to_rfc2822());
+    channel.set_items(items);
+    channel.to_string()
+}
+
+fn json_feed(info: &OutputInfo, articles: &[Article]) -> String {
+    let items = articles
+        .iter()
+        .map(|article| JsonItem {
+            id: article_id(article),
+            url: article.links.article_url(),
+            title: article.title.as_deref(),
+            content_html: content_html(article),
+            summary: article.summary.as_deref(),
+            image: article.thumbnail.as_deref(),
+            date_published: article
+                .published
+                .map(|p| date_time(p).to_rfc3339()),
+            authors: article
+                .authors
+                .0
+                .iter()
+                .map(|author| JsonAuthor {
+                    name: author.name.as_deref(),
+                    url: author.url.as_deref(),
+                    avatar: author.avatar.as_deref(),
+                })
+                .collect(),
+            attachments: article
+                .enclosures
+                .0
+                .iter()
+                .map(|enclosure| JsonAttachment {
+                    url: &enclosure.url,
+                    mime_type: enclosure
+                        .mime_type
+                        .as_deref()
+                        .unwrap_or(DEFAULT_MIME_TYPE),
+                    size_in_bytes: enclosure.length,
+                    duration_in_seconds: enclosure.duration,
+                })
+                .collect(),
+        })
+        .collect();
+
+    serde_json::to_string(&JsonFeed {
+        version: JSON_FEED_VERSION,
+        title: &info.title,
+        home_page_url: info.link.as_deref(),
+        items,
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::articles::{ArticleContent, ContentType},
+        sources::jsonfeed::JSONFeed,
+        testing,
+    };
+    use std::{fs, io::BufReader};
+
+    fn example() -> (OutputInfo, Vec<Article>) {
+        let feed = JSONFeed::new(
+            "https://example.org/feed.json".into(),
+            Uuid::new_v4(),
+        );
+        let articles = feed
+            .parse(&fs::read("test_data/test_jsonfeed.json").unwrap())
+            .unwrap()
+            .articles;
+        let info = OutputInfo {
+            id: Uuid::new_v4(),
+            title: "Curated & filtered".into(),
+            link: Some("https://example.org/".into()),
+            updated: Timestamp(time::Timespec::new(1_597_930_200, 0)),
+        };
+        (info, articles)
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(OutputFormat::from_name("rss"), Some(OutputFormat::Rss));
+        assert_eq!(
+            OutputFormat::from_name("json"),
+            Some(OutputFormat::JsonFeed)
+        );
+        assert_eq!(OutputFormat::from_name("opml"), None);
+    }
+
+    #[test]
+    fn render_atom() {
+        let (info, articles) = example();
+        let xml = OutputFormat::Atom.render(&info, &articles);
+        let feed =
+            atom_syndication::Feed::read_from(BufReader::new(xml.as_bytes()))
+                .unwrap();
+        assert_eq!(feed.title(), "Curated & filtered");
+        assert_eq!(feed.entries().len(), 2);
+        let entry = &feed.entries()[0];
+        assert_eq!(entry.id(), article_id(&articles[0]));
+        assert_eq!(entry.title(), "Second post");
+        assert_eq!(entry.published().unwrap().timestamp(), 1_597_930_200);
+        assert_eq!(entry.authors()[0].name(), "Item Author");
+        assert_eq!(entry.links()[0].href(), "https://example.org/second-post");
+        assert_eq!(entry.links()[1].rel(), "enclosure");
+        assert_eq!(entry.links()[1].mime_type(), Some("audio/mpeg"));
+        assert_eq!(entry.content().unwrap().content_type(), Some("html"));
+        assert_eq!(
+            feed.entries()[1].content().unwrap().value(),
+            Some("Hello, world")
+        );
+    }
+
+    #[test]
+    fn render_rss() {
+        let (info, articles) = example();
+        let xml = OutputFormat::Rss.render(&info, &articles);
+        let channel =
+            rss::Channel::read_from(BufReader::new(xml.as_bytes())).unwrap();
+        assert_eq!(channel.title(), "Curated & filtered");
+        assert_eq!(channel.link(), "https://example.org/");
+        let item = &channel.items()[0];
+        assert_eq!(item.title(), Some("Second post"));
+        assert_eq!(item.link(), Some("https://example.org/second-post"));
+        assert_eq!(item.guid().unwrap().value(), article_id(&articles[0]));
+        assert!(!item.guid().unwrap().is_permalink());
+        assert_eq!(item.pub_date(), Some("Thu, 20 Aug 2020 13:30:00 +0000"));
+        assert_eq!(item.author(), Some("Item Author"));
+        let enclosure = item.enclosure().unwrap();
+        assert_eq!(enclosure.url(), "https://example.org/episode-1.mp3");
+        assert_eq!(enclosure.length(), "1048576");
+        assert_eq!(channel.items()[1].pub_date(), None);
+    }
+
+    #[test]
+    fn render_json_feed() {
+        let (info, articles) = example();
+        let json = OutputFormat::JsonFeed.render(&info, &articles);
+        let feed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(feed["version"], JSON_FEED_VERSION);
+        assert_eq!(feed["home_page_url"], "https://example.org/");
+        let item = &feed["items"][0];
+        assert_eq!(item["id"], article_id(&articles[0]));
+        assert_eq!(item["content_html"], "<p>Now with a <em>podcast</em>.</p>");
+        assert_eq!(item["date_published"], "2020-08-20T13:30:00+00:00");
+        assert_eq!(item["attachments"][0]["duration_in_seconds"], 1800);
+        assert_eq!(feed["items"][1]["content_html"], "Hello, world");
+    }
+
+    #[test]
+    fn plain_text_escaped() {
+        let (info, _) = example();
+        let articles = vec![Article {
+            content: ArticleContent::new(
+                ContentType::Text,
+                Some(testing::MARKUP_TEXT.into()),
+            ),
+            ..testing::article()
+        }];
+
+        let xml = OutputFormat::Atom.render(&info, &articles);
+        let feed =
+            atom_syndication::Feed::read_from(BufReader::new(xml.as_bytes()))
+                .unwrap();
+        let content = feed.entries()[0].content().unwrap();
+        assert_eq!(content.value(), Some(testing::MARKUP_TEXT_HTML));
+
+        let xml = OutputFormat::Rss.render(&info, &articles);
+        let channel =
+            rss::Channel::read_from(BufReader::new(xml.as_bytes())).unwrap();
+        assert_eq!(
+            channel.items()[0].description(),
+            Some(testing::MARKUP_TEXT_HTML)
+        );
+
+        let json = OutputFormat::JsonFeed.render(&info, &articles);
+        let feed: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(feed["items"][0]["content_html"], testing::MARKUP_TEXT_HTML);
+    }
+}
@@ -0,0 +1,495 @@
+//! Filter expressions for `Source.post_filter`.
+//!
+//! Fetched articles are only stored if they match their source's filter.
+//! An empty (or all-whitespace) filter matches every article.
+//!
+//! ```text
+//! expr   := and ("or" and)*
+//! and    := unary ("and" unary)*
+//! unary  := "not" unary | "(" expr ")" | field op string
+//! field  := "title" | "summary" | "content" | "authors" | "categories"
+//!         | "any"
+//! op     := "contains" | "matches"
+//! ```
+//!
+//! `contains` is a case-insensitive substring match, and `matches` is a
+//! regular expression (`regex` crate syntax, `(?i)` for case-insensitivity).
+//! Strings are double quoted, with `\"` and `\\` escapes. Keywords are
+//! case-insensitive. A field with several values (ex: `authors`) matches if
+//! any of its values match, and `any` checks every field. `not`s and
+//! parentheses can be nested up to 64 deep.
+//!
+//! Example:
+//!
+//! ```text
+//! title contains "rust" and not (categories matches "^sponsored")
+//! ```
+
+use crate::db::articles::Article;
+use regex::Regex;
+use std::{error::Error, fmt};
+
+/// How deeply `not`s and parentheses can nest, so a hostile filter can't
+/// overflow the stack while it's parsed or matched.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Title,
+    Summary,
+    Content,
+    Authors,
+    Categories,
+    Any,
+}
+
+#[derive(Debug)]
+pub enum Matcher {
+    /// Lowercased substring
+    Contains(String),
+    Matches(Regex),
+}
+
+#[derive(Debug)]
+pub enum PostFilter {
+    All,
+    Match(Field, Matcher),
+    Not(Box<PostFilter>),
+    /// Flat, so long chains of `and`s & `or`s don't nest
+    And(Vec<PostFilter>),
+    Or(Vec<PostFilter>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    /// Byte offset into the filter where the error was found
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "at position {}: {}", self.position, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    Word(String),
+    Str(String),
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '(' {
+            chars.next();
+            tokens.push((pos, Token::LParen));
+        } else if c == ')' {
+            chars.next();
+            tokens.push((pos, Token::RParen));
+        } else if c == '"' {
+            chars.next();
+            let mut s = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped @ '"'))
+                        | Some((_, escaped @ '\\')) => s.push(escaped),
+                        Some((escape_pos, other)) => {
+                            return Err(ParseError {
+                                position: escape_pos,
+                                message: format!("unknown escape \\{}", other),
+                            })
+                        }
+                        None => {
+                            return Err(ParseError {
+                                position: pos,
+                                message: "unterminated string".into(),
+                            })
+                        }
+                    },
+                    Some((_, other)) => s.push(other),
+                    None => {
+                        return Err(ParseError {
+                            position: pos,
+                            message: "unterminated string".into(),
+                        })
+                    }
+                }
+            }
+            tokens.push((pos, Token::Str(s)));
+        } else if c.is_alphabetic() {
+            let mut word = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !c.is_alphanumeric() && c != '_' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push((pos, Token::Word(word.to_lowercase())));
+        } else {
+            return Err(ParseError {
+                position: pos,
+                message: format!("unexpected character '{}'", c),
+            });
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn error<T, S: Into<String>>(&self, message: S) -> Result<T, ParseError> {
+        Err(ParseError {
+            position: self.position(),
+            message: message.into(),
+        })
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map(|(pos, _)| *pos)
+            .unwrap_or(self.end)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w == keyword => {
+                self.next += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<PostFilter, ParseError> {
+        let mut terms = vec![self.parse_and()?];
+        while self.eat_keyword("or") {
+            terms.push(self.parse_and()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            PostFilter::Or(terms)
+        })
+    }
+
+    fn parse_and(&mut self) -> Result<PostFilter, ParseError> {
+        let mut terms = vec![self.parse_unary()?];
+        while self.eat_keyword("and") {
+            terms.push(self.parse_unary()?);
+        }
+        Ok(if terms.len() == 1 {
+            terms.remove(0)
+        } else {
+            PostFilter::And(terms)
+        })
+    }
+
+    fn parse_unary(&mut self) -> Result<PostFilter, ParseError> {
+        let nested = match self.peek() {
+            Some(Token::LParen) => true,
+            Some(Token::Word(w)) => w == "not",
+            _ => false,
+        };
+        if !nested {
+            return self.parse_match();
+        }
+        if self.depth == MAX_DEPTH {
+            return self.error("too deeply nested");
+        }
+        self.depth += 1;
+        let filter = self.parse_nested();
+        self.depth -= 1;
+        filter
+    }
+
+    /// After `parse_unary` has seen a `not` or a `(`.
+    fn parse_nested(&mut self) -> Result<PostFilter, ParseError> {
+        if self.eat_keyword("not") {
+            return Ok(PostFilter::Not(Box::new(self.parse_unary()?)));
+        }
+        self.next += 1;
+        let inner = self.parse_or()?;
+        match self.peek() {
+            Some(Token::RParen) => {
+                self.next += 1;
+                Ok(inner)
+            }
+            _ => self.error("expected ')'"),
+        }
+    }
+
+    fn parse_match(&mut self) -> Result<PostFilter, ParseError> {
+        let field = match self.peek() {
+            Some(Token::Word(w)) => match w.as_str() {
+                "title" => Field::Title,
+                "summary" => Field::Summary,
+                "content" => Field::Content,
+                "authors" => Field::Authors,
+                "categories" => Field::Categories,
+                "any" => Field::Any,
+                _ => return self.error(format!("unknown field '{}'", w)),
+            },
+            _ => return self.error("expected a field, 'not', or '('"),
+        };
+        self.next += 1;
+
+        let contains = match self.peek() {
+            Some(Token::Word(w)) if w == "contains" => true,
+            Some(Token::Word(w)) if w == "matches" => false,
+            _ => return self.error("expected 'contains' or 'matches'"),
+        };
+        self.next += 1;
+
+        let value = match self.peek() {
+            Some(Token::Str(s)) => s.to_owned(),
+            _ => return self.error("expected a quoted string"),
+        };
+        let matcher = if contains {
+            Matcher::Contains(value.to_lowercase())
+        } else {
+            match Regex::new(&value) {
+                Ok(re) => Matcher::Matches(re),
+                Err(e) => return self.error(format!("invalid regex: {}", e)),
+            }
+        };
+        self.next += 1;
+
+        Ok(PostFilter::Match(field, matcher))
+    }
+}
+
+impl PostFilter {
+    pub fn parse(input: &str) -> Result<PostFilter, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(input)?,
+            next: 0,
+            end: input.len(),
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(PostFilter::All);
+        }
+
+        let filter = parser.parse_or()?;
+        if parser.next < parser.tokens.len() {
+            return parser.error("expected 'and', 'or', or end of filter");
+        }
+        Ok(filter)
+    }
+
+    pub fn matches(&self, article: &Article) -> bool {
+        match self {
+            PostFilter::All => true,
+            PostFilter::Match(field, matcher) => field_values(*field, article)
+                .into_iter()
+                .any(|value| matcher.is_match(value)),
+            PostFilter::Not(f) => !f.matches(article),
+            PostFilter::And(filters) => {
+                filters.iter().all(|f| f.matches(article))
+            }
+            PostFilter::Or(filters) => {
+                filters.iter().any(|f| f.matches(article))
+            }
+        }
+    }
+}
+
+impl Matcher {
+    fn is_match(&self, value: &str) -> bool {
+        match self {
+            Matcher::Contains(needle) => value.to_lowercase().contains(needle),
+            Matcher::Matches(re) => re.is_match(value),
+        }
+    }
+}
+
+fn field_values(field: Field, article: &Article) -> Vec<&str> {
+    let mut values = Vec::new();
+    if let Field::Title | Field::Any = field {
+        values.extend(article.title.as_deref());
+    }
+    if let Field::Summary | Field::Any = field {
+        values.extend(article.summary.as_deref());
+    }
+    if let Field::Content | Field::Any = field {
+        values.extend(article.content.value.as_deref());
+    }
+    if let Field::Authors | Field::Any = field {
+        for author in &article.authors.0 {
+            values.extend(author.name.as_deref());
+            values.extend(author.email.as_deref());
+            values.extend(author.url.as_deref());
+        }
+    }
+    if let Field::Categories | Field::Any = field {
+        json_strings(&article.categories, &mut values);
+    }
+    values
+}
+
+/// Collect every string in a JSON value.
+fn json_strings<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::String(s) => out.push(s),
+        serde_json::Value::Array(values) => {
+            values.iter().for_each(|v| json_strings(v, out))
+        }
+        serde_json::Value::Object(map) => {
+            map.values().for_each(|v| json_strings(v, out))
+        }
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::articles::{
+            ArticleAuthor, ArticleAuthors, ArticleContent, ContentType,
+        },
+        testing,
+    };
+
+    fn article() -> Article {
+        Article {
+            title: Some("Rust 1.45 released".into()),
+            summary: Some("A new stable version".into()),
+            content: ArticleContent::new(
+                ContentType::Html,
+                Some("<p>Procedural macros</p>".into()),
+            ),
+            authors: ArticleAuthors(vec![ArticleAuthor {
+                name: Some("The Rust Team".into()),
+                email: None,
+                url: None,
+                avatar: None,
+            }]),
+            categories: serde_json::json!(["release", "sponsored"]),
+            ..testing::article()
+        }
+    }
+
+    fn matches(filter: &str) -> bool {
+        PostFilter::parse(filter).unwrap().matches(&article())
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        assert!(matches(""));
+        assert!(matches("  \n"));
+    }
+
+    #[test]
+    fn field_matchers() {
+        assert!(matches(r#"title contains "RUST""#));
+        assert!(!matches(r#"title contains "go""#));
+        assert!(matches(r#"summary matches "^A new""#));
+        assert!(matches(r#"content contains "macros""#));
+        assert!(matches(r#"authors contains "rust team""#));
+        assert!(matches(r#"categories matches "^sponsored$""#));
+        assert!(matches(r#"any contains "stable""#));
+    }
+
+    #[test]
+    fn boolean_operators() {
+        assert!(matches(
+            r#"title contains "rust" and not categories contains "go""#
+        ));
+        assert!(!matches(
+            r#"title contains "rust" and not (categories contains "go" or categories contains "sponsored")"#
+        ));
+        assert!(matches(r#"title contains "go" or title contains "rust""#));
+        // "and" binds tighter than "or"
+        assert!(matches(
+            r#"title contains "rust" or title contains "go" and title contains "x""#
+        ));
+        assert!(matches(r#"NOT TITLE CONTAINS "go""#));
+    }
+
+    #[test]
+    fn escaped_strings() {
+        assert!(!matches(r#"title contains "\"""#));
+        assert!(matches(r#"not title contains "\\""#));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let e = PostFilter::parse(r#"link contains "x""#).unwrap_err();
+        assert_eq!(e.position, 0);
+
+        let e = PostFilter::parse(r#"title has "x""#).unwrap_err();
+        assert_eq!(e.position, 6);
+
+        let e = PostFilter::parse(r#"title matches "(""#).unwrap_err();
+        assert_eq!(e.position, 14);
+
+        let e = PostFilter::parse(r#"(title contains "x""#).unwrap_err();
+        assert_eq!(e.position, 19);
+
+        let e = PostFilter::parse(r#"title contains "x"#).unwrap_err();
+        assert_eq!(e.message, "unterminated string");
+
+        PostFilter::parse(r#"title contains "x" title"#).unwrap_err();
+        PostFilter::parse("title contains 'x'").unwrap_err();
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let nested = |depth| {
+            format!(
+                r#"{}title contains "rust"{}"#,
+                "not (".repeat(depth),
+                ")".repeat(depth)
+            )
+        };
+        // Each level is a `not` and a parenthesis
+        assert!(matches(&nested(MAX_DEPTH / 2)));
+        let e = PostFilter::parse(&nested(MAX_DEPTH / 2 + 1)).unwrap_err();
+        assert_eq!(e.message, "too deeply nested");
+
+        let e = PostFilter::parse(&"(".repeat(100_000)).unwrap_err();
+        assert_eq!(e.position, MAX_DEPTH);
+        PostFilter::parse(&"not ".repeat(100_000)).unwrap_err();
+    }
+
+    #[test]
+    fn long_chains() {
+        let chain = |term: &str, op: &str| {
+            vec![term; 100_000].join(&format!(" {} ", op))
+        };
+        assert!(matches(&chain(r#"title contains "rust""#, "and")));
+        assert!(!matches(&chain(r#"title contains "go""#, "or")));
+        let mixed = format!(
+            r#"{} or (title contains "rust" and {})"#,
+            chain(r#"title contains "go""#, "or"),
+            chain(r#"not title contains "go""#, "and"),
+        );
+        assert!(matches(&mixed));
+    }
+}
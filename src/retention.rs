@@ -0,0 +1,402 @@
+// Deleting old articles, so the articles table doesn't grow forever.
+// Each source can override the worker's default policy.
+
+use crate::{
+    db::{
+        self, articles, articles::Article, expired_articles, sources,
+        sources::Source,
+    },
+    timestamp::Timestamp,
+    Result,
+};
+use diesel::prelude::*;
+use std::{collections::HashSet, env};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetentionPolicy {
+    /// Days to keep articles for, by publish time, or else when they were
+    /// stored
+    pub max_age: Option<i32>,
+    /// Articles to keep per source, newest first
+    pub max_count: Option<i32>,
+    /// Never delete articles a user starred
+    pub keep_starred: bool,
+}
+
+/// Keep everything, until configured otherwise.
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        RetentionPolicy {
+            max_age: None,
+            max_count: None,
+            keep_starred: true,
+        }
+    }
+}
+
+/// 0 means no limit
+fn limit(value: i32) -> Option<i32> {
+    Some(value).filter(|v| *v > 0)
+}
+
+impl RetentionPolicy {
+    /// Read the policy from `RETENTION_MAX_AGE` (days), `RETENTION_MAX_COUNT`
+    /// & `RETENTION_KEEP_STARRED`, using the defaults for any that are unset.
+    pub fn from_env() -> RetentionPolicy {
+        let default = RetentionPolicy::default();
+        let var = |name| env::var(name).ok().and_then(|v| v.parse().ok());
+        RetentionPolicy {
+            max_age: var("RETENTION_MAX_AGE").map_or(default.max_age, limit),
+            max_count: var("RETENTION_MAX_COUNT")
+                .map_or(default.max_count, limit),
+            keep_starred: env::var("RETENTION_KEEP_STARRED")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default.keep_starred),
+        }
+    }
+
+    /// This policy, with the source's overrides.
+    pub fn for_source(&self, source: &Source) -> RetentionPolicy {
+        RetentionPolicy {
+            max_age: source.retention_max_age.map_or(self.max_age, limit),
+            max_count: source.retention_max_count.map_or(self.max_count, limit),
+            keep_starred: source
+                .retention_keep_starred
+                .unwrap_or(self.keep_starred),
+        }
+    }
+
+    /// Articles published (or, if undated, stored) before this are expired.
+    fn published_cutoff(&self, now: Timestamp) -> Option<Timestamp> {
+        self.max_age
+            .map(|days| now - time::Duration::days(i64::from(days)))
+    }
+
+    /// Drop newly fetched articles that a cleanup already deleted, or that
+    /// the next one would, so articles still in the feed aren't stored again
+    /// after each cleanup.
+    pub fn skip_expired(
+        &self,
+        source: &Source,
+        new_articles: &mut Vec<Article>,
+        connection: &PgConnection,
+    ) -> QueryResult<()> {
+        let ids: Vec<&str> = new_articles
+            .iter()
+            .filter_map(|a| a.id_from_source.as_deref())
+            .collect();
+        let expired: HashSet<String> =
+            expired_articles::all_with_ids(source.id, &ids, connection)?
+                .into_iter()
+                .collect();
+        new_articles.retain(|a| {
+            a.id_from_source
+                .as_ref()
+                .map_or(true, |id| !expired.contains(id))
+        });
+
+        if let Some(cutoff) = self.published_cutoff(Timestamp::now()) {
+            new_articles
+                .retain(|a| a.published.map(|p| p >= cutoff).unwrap_or(true));
+        }
+        if let Some(max_count) = self.max_count {
+            let kept = articles::recent_dated(
+                source.id,
+                i64::from(max_count),
+                connection,
+            )?;
+            if kept.len() as i64 == i64::from(max_count) {
+                // Past the limit, only articles newer than the oldest kept
+                // one would survive. Undated articles are dated when
+                // they're stored, so they're the newest.
+                let oldest = kept[kept.len() - 1];
+                new_articles
+                    .retain(|a| a.published.map_or(true, |p| p > oldest));
+            }
+        }
+        Ok(())
+    }
+
+    /// Delete a source's expired articles. Returns the number deleted.
+    pub fn clean(
+        &self,
+        source: &Source,
+        connection: &PgConnection,
+    ) -> QueryResult<usize> {
+        if self.max_age.is_none() && self.max_count.is_none() {
+            return Ok(0);
+        }
+        articles::delete_expired(
+            source.id,
+            self.published_cutoff(Timestamp::now()),
+            self.max_count.map(i64::from),
+            self.keep_starred,
+            connection,
+        )
+    }
+}
+
+/// Delete expired articles from every source.
+pub fn clean_all_sources(
+    pool: &db::Pool,
+    defaults: RetentionPolicy,
+) -> Result<()> {
+    let conn = db::DbConn(pool.get()?);
+    for source in sources::all(&conn)? {
+        let deleted = defaults.for_source(&source).clean(&source, &conn)?;
+        if deleted > 0 {
+            log::info!(
+                "Deleted {} expired articles from source {}",
+                deleted,
+                source.id
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use uuid::Uuid;
+
+    #[test]
+    fn source_overrides() {
+        let defaults = RetentionPolicy {
+            max_age: Some(30),
+            max_count: None,
+            keep_starred: true,
+        };
+        let mut source = Source::new(
+            None,
+            "".into(),
+            serde_json::json!({}),
+            "".into(),
+            "".into(),
+        );
+        assert_eq!(defaults.for_source(&source), defaults);
+
+        source.retention_max_age = Some(0);
+        source.retention_max_count = Some(100);
+        source.retention_keep_starred = Some(false);
+        assert_eq!(
+            defaults.for_source(&source),
+            RetentionPolicy {
+                max_age: None,
+                max_count: Some(100),
+                keep_starred: false,
+            }
+        );
+    }
+
+    #[test]
+    fn skip_past_max_count() {
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("retention", "hunter22", &conn);
+        let source = sources::insert(
+            Source::new(
+                None,
+                "".into(),
+                serde_json::json!({}),
+                "".into(),
+                username.clone(),
+            ),
+            &conn,
+        )
+        .unwrap();
+        let at = |days| Some(Timestamp::now() - time::Duration::days(days));
+        articles::insert(
+            Article {
+                source: source.id,
+                published: at(2),
+                ..testing::article()
+            },
+            &conn,
+        )
+        .unwrap();
+
+        let policy = RetentionPolicy {
+            max_age: None,
+            max_count: Some(1),
+            keep_starred: true,
+        };
+        let undated = testing::article();
+        let newer = Article {
+            published: at(1),
+            ..testing::article()
+        };
+        let ids = [undated.id, newer.id];
+        let mut new_articles = vec![
+            undated,
+            newer,
+            Article {
+                published: at(3),
+                ..testing::article()
+            },
+        ];
+        policy
+            .skip_expired(&source, &mut new_articles, &conn)
+            .unwrap();
+        let kept: Vec<Uuid> = new_articles.iter().map(|a| a.id).collect();
+        assert_eq!(kept, ids);
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn fetch_after_cleanup() {
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("retention", "hunter22", &conn);
+        let source = sources::insert(
+            Source::new(
+                None,
+                "".into(),
+                serde_json::json!({}),
+                "".into(),
+                username.clone(),
+            ),
+            &conn,
+        )
+        .unwrap();
+        let undated = |id: &str| Article {
+            source: source.id,
+            id_from_source: Some(id.into()),
+            ..testing::article()
+        };
+        articles::insert(undated("1"), &conn).unwrap();
+        articles::insert(
+            Article {
+                source: source.id,
+                published: Some(Timestamp::now() + time::Duration::minutes(1)),
+                ..testing::article()
+            },
+            &conn,
+        )
+        .unwrap();
+
+        let policy = RetentionPolicy {
+            max_age: None,
+            max_count: Some(1),
+            keep_starred: true,
+        };
+        assert_eq!(policy.clean(&source, &conn).unwrap(), 1);
+        // The feed still has the undated article the cleanup deleted
+        let new = undated("2");
+        let ids = [new.id];
+        let mut new_articles = vec![undated("1"), new];
+        policy
+            .skip_expired(&source, &mut new_articles, &conn)
+            .unwrap();
+        let kept: Vec<Uuid> = new_articles.iter().map(|a| a.id).collect();
+        assert_eq!(kept, ids);
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn undated_at_max_count() {
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("retention", "hunter22", &conn);
+        let source = sources::insert(
+            Source::new(
+                None,
+                "".into(),
+                serde_json::json!({}),
+                "".into(),
+                username.clone(),
+            ),
+            &conn,
+        )
+        .unwrap();
+        let undated = |id: &str| Article {
+            source: source.id,
+            id_from_source: Some(id.into()),
+            ..testing::article()
+        };
+        for id in &["1", "2"] {
+            articles::insert(undated(id), &conn).unwrap();
+        }
+
+        let policy = RetentionPolicy {
+            max_age: None,
+            max_count: Some(2),
+            keep_starred: true,
+        };
+        let mut new_articles = vec![undated("3")];
+        policy
+            .skip_expired(&source, &mut new_articles, &conn)
+            .unwrap();
+        assert_eq!(new_articles.len(), 1);
+        for article in new_articles {
+            articles::insert(article, &conn).unwrap();
+        }
+        assert_eq!(policy.clean(&source, &conn).unwrap(), 1);
+        assert_eq!(
+            articles::all_from_source(source.id, &conn).unwrap().len(),
+            2
+        );
+
+        testing::remove_user(username, &conn);
+    }
+
+    #[test]
+    fn undated_past_max_age() {
+        let conn = testing::conn();
+        let (username, _) = testing::seed_user("retention", "hunter22", &conn);
+        let source = sources::insert(
+            Source::new(
+                None,
+                "".into(),
+                serde_json::json!({}),
+                "".into(),
+                username.clone(),
+            ),
+            &conn,
+        )
+        .unwrap();
+        let old = articles::insert(
+            Article {
+                source: source.id,
+                id_from_source: Some("old".into()),
+                ..testing::article()
+            },
+            &conn,
+        )
+        .unwrap();
+        articles::insert(
+            Article {
+                source: source.id,
+                ..testing::article()
+            },
+            &conn,
+        )
+        .unwrap();
+        diesel::sql_query(
+            "UPDATE articles SET added = NOW() - INTERVAL '10 days'
+             WHERE id = $1",
+        )
+        .bind::<diesel::sql_types::Uuid, _>(old.id)
+        .execute(&*conn)
+        .unwrap();
+
+        let policy = RetentionPolicy {
+            max_age: Some(5),
+            max_count: None,
+            keep_starred: true,
+        };
+        assert_eq!(policy.clean(&source, &conn).unwrap(), 1);
+        assert!(articles::get(old.id, &conn).optional().unwrap().is_none());
+        let mut new_articles = vec![Article {
+            id_from_source: Some("old".into()),
+            ..testing::article()
+        }];
+        policy
+            .skip_expired(&source, &mut new_articles, &conn)
+            .unwrap();
+        assert!(new_articles.is_empty());
+
+        testing::remove_user(username, &conn);
+    }
+}
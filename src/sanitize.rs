@@ -0,0 +1,324 @@
+// Cleaning feed-provided HTML before it's stored, since clients render it.
+// The original is kept in `Article.raw_summary` & `Article.raw_content`.
+
+use crate::{
+    db::{
+        self, articles,
+        articles::{Article, ContentType},
+        sources,
+    },
+    Result,
+};
+use ammonia::{Builder, Url, UrlRelative};
+use regex::Regex;
+use std::{borrow::Cow, collections::HashMap};
+
+/// Hosts `<iframe>`s are kept for. Other iframes lose their `src`.
+const EMBED_HOSTS: [&str; 6] = [
+    "www.youtube.com",
+    "www.youtube-nocookie.com",
+    "player.vimeo.com",
+    "w.soundcloud.com",
+    "bandcamp.com",
+    "open.spotify.com",
+];
+
+/// Articles sanitized at a time by `sanitize_stored`
+const BATCH_SIZE: i64 = 500;
+
+/// Put around search matches by Postgres, then replaced with `<mark>` tags
+/// once the rest of the snippet's escaped. They're private use characters,
+/// so feeds shouldn't have them.
+pub const MATCH_START: char = '\u{E000}';
+pub const MATCH_END: char = '\u{E001}';
+
+lazy_static! {
+    static ref IMG_RE: Regex = Regex::new(r"(?is)<img\s[^>]*>").unwrap();
+    /// An attribute, with its value double, single or un-quoted
+    static ref ATTR_RE: Regex = Regex::new(
+        r#"(?s)([a-zA-Z_:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+    )
+    .unwrap();
+}
+
+fn is_embed(src: &str) -> bool {
+    // Embeds are often protocol-relative
+    Url::parse(src)
+        .or_else(|_| Url::parse(&format!("https:{}", src)))
+        .ok()
+        .filter(|url| url.scheme() == "https" || url.scheme() == "http")
+        .and_then(|url| url.host_str().map(|host| EMBED_HOSTS.contains(&host)))
+        .unwrap_or(false)
+}
+
+/// Whether an `<img>` tag is a 1x1 (or smaller) tracking pixel.
+fn is_tracking_pixel(img: &str) -> bool {
+    let mut sizes = ATTR_RE
+        .captures_iter(img)
+        .filter(|attr| {
+            let name = attr[1].to_lowercase();
+            name == "width" || name == "height"
+        })
+        .map(|attr| {
+            let value = attr
+                .get(2)
+                .or_else(|| attr.get(3))
+                .or_else(|| attr.get(4))
+                .map_or("", |v| v.as_str());
+            value.trim().trim_end_matches("px").parse::<f64>().ok()
+        })
+        .peekable();
+    sizes.peek().is_some()
+        && sizes.all(|size| size.map(|s| s <= 1.0).unwrap_or(false))
+}
+
+fn strip_tracking_pixels(html: &str) -> Cow<str> {
+    IMG_RE.replace_all(html, |img: &regex::Captures| {
+        if is_tracking_pixel(&img[0]) {
+            String::new()
+        } else {
+            img[0].to_string()
+        }
+    })
+}
+
+/// Clean HTML down to an allowlist of tags & attributes, rewriting relative
+/// URLs against `base`. Relative URLs are dropped without a `base`.
+pub fn sanitize_html(html: &str, base: Option<&Url>) -> String {
+    let mut builder = Builder::default();
+    builder
+        .add_tags(&["iframe"])
+        .add_tag_attributes(
+            "iframe",
+            &["src", "width", "height", "allowfullscreen"],
+        )
+        .attribute_filter(|element, attribute, value| {
+            if element == "iframe" && attribute == "src" && !is_embed(value) {
+                None
+            } else {
+                Some(value.into())
+            }
+        })
+        .url_relative(match base {
+            Some(base) => UrlRelative::RewriteWithBase(base.clone()),
+            None => UrlRelative::Deny,
+        });
+    builder.clean(&strip_tracking_pixels(html)).to_string()
+}
+
+/// Escape text for HTML.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A search snippet as HTML: escaped text, with matches between
+/// `MATCH_START` & `MATCH_END` wrapped in `<mark>` tags.
+pub fn highlight_snippet(snippet: &str) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, "<mark>")
+        .replace(MATCH_END, "</mark>")
+}
+
+/// An article's body as HTML, for serving: its full content if it was
+/// fetched, or else its content from the feed, or else its summary.
+///
+/// Plain text content is stored as it is, so it's escaped here. Anything
+/// serving article content to clients as HTML should read it through this.
+pub fn content_html(article: &Article) -> Option<Cow<str>> {
+    if let Some(full_content) = &article.full_content {
+        return Some(Cow::Borrowed(full_content));
+    }
+    match (&article.content.value, article.content.content_type) {
+        (Some(value), ContentType::Text) => {
+            Some(Cow::Owned(escape_html(value)))
+        }
+        (Some(value), _) => Some(Cow::Borrowed(value)),
+        (None, _) => article.summary.as_deref().map(Cow::Borrowed),
+    }
+}
+
+/// Where an article's relative URLs point: its own link, or else the feed.
+fn base_url(article: &Article, feed_url: &str) -> Option<Url> {
+    // Atom's `xml:base` takes precedence
+    article
+        .content
+        .base
+        .as_deref()
+        .into_iter()
+        .chain(article.links.article_url())
+        .chain(Some(feed_url))
+        .find_map(|url| Url::parse(url).ok())
+}
+
+/// How a kind of source's summaries are written. JSON Feed's are plain
+/// text, while Atom's type isn't kept by the feed crate, so it's taken to
+/// be HTML.
+fn summary_type(source_data: &sources::SourceData) -> ContentType {
+    match source_data {
+        sources::SourceData::RSSAtom(_) => ContentType::Html,
+        sources::SourceData::JSONFeed(_) => ContentType::Text,
+    }
+}
+
+/// Sanitize an article's summary & content, keeping the originals.
+///
+/// Plain text content (ex: Atom's `type="text"`) is left alone, since its
+/// type is kept with it, & escaped when it's served (see `content_html`).
+/// Summaries have no type, so plain text ones (a `summary_type` of `Text`)
+/// are escaped into HTML.
+pub fn sanitize_article(
+    article: &mut Article,
+    feed_url: &str,
+    summary_type: ContentType,
+) {
+    let base = base_url(article, feed_url);
+    article.raw_summary = article.summary.clone();
+    article.raw_content = serde_json::to_value(&article.content).ok();
+
+    if let Some(summary) = &article.summary {
+        article.summary = Some(match summary_type {
+            ContentType::Text => escape_html(summary),
+            _ => sanitize_html(summary, base.as_ref()),
+        });
+    }
+    if article.content.content_type != ContentType::Text {
+        if let Some(value) = &article.content.value {
+            article.content.value = Some(sanitize_html(value, base.as_ref()));
+        }
+    }
+}
+
+/// Sanitize articles stored before sanitization was added. Returns the
+/// number sanitized.
+pub fn sanitize_stored(pool: &db::Pool) -> Result<usize> {
+    let conn = db::DbConn(pool.get()?);
+    let feeds: HashMap<_, _> = sources::all(&conn)?
+        .into_iter()
+        .filter_map(|source| {
+            let id = source.id;
+            serde_json::from_value::<sources::SourceData>(source.source_data)
+                .ok()
+                .map(|data| (id, (data.url().to_string(), summary_type(&data))))
+        })
+        .collect();
+
+    let mut sanitized = 0;
+    loop {
+        let batch = articles::all_unsanitized(BATCH_SIZE, &conn)?;
+        if batch.is_empty() {
+            return Ok(sanitized);
+        }
+        for mut article in batch {
+            let (feed_url, summary_type) = feeds
+                .get(&article.source)
+                .map(|(url, summary_type)| (url.as_str(), *summary_type))
+                .unwrap_or(("", ContentType::Html));
+            sanitize_article(&mut article, feed_url, summary_type);
+            articles::update(article, &conn)?;
+            sanitized += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db::articles::ArticleContent, testing};
+
+    #[test]
+    fn sanitize_allowlist() {
+        let base = Url::parse("https://example.org/posts/1").unwrap();
+        let html = r#"<p onclick="steal()">Hi <b>there</b></p>
+<script>alert(1)</script><style>p {}</style>
+<a href="javascript:alert(1)">bad</a> <a href="../about">about</a>
+<img src="/a.png" alt="a"><img src="https://t.example/p.gif" width="1" height="1">
+<iframe src="https://evil.example/x"></iframe>
+<iframe src="//www.youtube.com/embed/abc" onload="x()"></iframe>"#;
+
+        let clean = sanitize_html(html, Some(&base));
+        assert!(clean.contains("<p>Hi <b>there</b></p>"));
+        assert!(!clean.contains("script"));
+        assert!(!clean.contains("alert"));
+        assert!(!clean.contains("onclick"));
+        assert!(!clean.contains("onload"));
+        assert!(clean.contains(r#"href="https://example.org/about""#));
+        assert!(clean.contains(r#"src="https://example.org/a.png""#));
+        assert!(!clean.contains("p.gif"));
+        assert!(!clean.contains("evil.example"));
+        assert!(clean.contains("www.youtube.com/embed/abc"));
+    }
+
+    #[test]
+    fn plain_text_summaries() {
+        let summary = Some("1 < 2 & <b>bold</b>".to_string());
+        let mut article = Article {
+            summary: summary.clone(),
+            ..testing::article()
+        };
+        sanitize_article(&mut article, "", ContentType::Text);
+        assert_eq!(
+            article.summary.as_deref(),
+            Some("1 &lt; 2 &amp; &lt;b&gt;bold&lt;/b&gt;")
+        );
+        assert_eq!(article.raw_summary, summary);
+
+        article.summary = summary.clone();
+        sanitize_article(&mut article, "", ContentType::Html);
+        assert_eq!(article.summary.unwrap(), "1 &lt; 2 &amp; <b>bold</b>");
+        assert_eq!(article.raw_summary, summary);
+    }
+
+    #[test]
+    fn content_as_html() {
+        let mut article = Article {
+            content: ArticleContent::new(
+                ContentType::Text,
+                Some("<script>alert(1)</script>".into()),
+            ),
+            summary: Some("<p>Summary</p>".into()),
+            ..testing::article()
+        };
+        assert_eq!(
+            content_html(&article).unwrap(),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+
+        article.content.content_type = ContentType::Html;
+        article.content.value = Some("<p>Content</p>".into());
+        assert_eq!(content_html(&article).unwrap(), "<p>Content</p>");
+
+        article.content.value = None;
+        assert_eq!(content_html(&article).unwrap(), "<p>Summary</p>");
+
+        article.full_content = Some("<p>Full</p>".into());
+        assert_eq!(content_html(&article).unwrap(), "<p>Full</p>");
+    }
+
+    #[test]
+    fn snippet_highlighting() {
+        let snippet = format!(
+            "<img src=x onerror=alert(1) {}fetch{}er & <b",
+            MATCH_START, MATCH_END
+        );
+        assert_eq!(
+            highlight_snippet(&snippet),
+            "&lt;img src=x onerror=alert(1) <mark>fetch</mark>er &amp; &lt;b"
+        );
+    }
+
+    #[test]
+    fn tracking_pixels() {
+        let html = r#"<img src="a" width="1" height="1"><img src="b" width=0>
+<img src="c" width="1px" height='1px'><img src="d"><img src="e" width="1" height="300">"#;
+        let stripped = strip_tracking_pixels(html);
+        assert!(!stripped.contains(r#""a""#));
+        assert!(!stripped.contains(r#""b""#));
+        assert!(!stripped.contains(r#""c""#));
+        assert!(stripped.contains(r#""d""#));
+        assert!(stripped.contains(r#""e""#));
+    }
+}
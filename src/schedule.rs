@@ -0,0 +1,158 @@
+// When to next fetch a source
+
+use crate::{db::sources::Source, timestamp::Timestamp};
+
+/// How many of a source's newest articles are used to estimate how often
+/// it posts.
+pub const ADAPTIVE_SAMPLE_SIZE: i64 = 20;
+
+/// Minutes to wait before fetching `source` again.
+///
+/// Without `adaptive_fetch`, this is always `min_fetch_interval`.
+/// Otherwise, it's half the average time between the source's recent posts
+/// (counting the time since its newest post, so quiet sources back off), but
+/// no shorter than the feed's own `ttl`. Either way, it's kept within the
+/// source's min/max intervals.
+///
+/// `published` are publish times of the source's newest articles, newest
+/// first.
+pub fn fetch_interval(
+    source: &Source,
+    published: &[Timestamp],
+    now: Timestamp,
+) -> i64 {
+    let min = i64::from(source.min_fetch_interval.max(1));
+    let max = i64::from(source.max_fetch_interval).max(min);
+    if !source.adaptive_fetch {
+        return min;
+    }
+
+    let mut interval = match published.last() {
+        Some(oldest) => {
+            let minutes = (now.0.sec - oldest.0.sec).max(0) / 60;
+            minutes / published.len() as i64 / 2
+        }
+        None => min,
+    };
+    if let Some(ttl) = source.feed_ttl {
+        interval = interval.max(i64::from(ttl));
+    }
+    interval.max(min).min(max)
+}
+
+pub fn next_fetch(
+    source: &Source,
+    published: &[Timestamp],
+    now: Timestamp,
+) -> Timestamp {
+    now + time::Duration::minutes(fetch_interval(source, published, now))
+}
+
+/// Minutes to wait before retrying a source whose last fetch failed:
+/// `min_fetch_interval`, doubled for each failure in a row after the first,
+/// up to `max_fetch_interval`. Sources that keep failing are only retried
+/// every `max_fetch_interval`.
+pub fn retry_interval(source: &Source) -> i64 {
+    let min = i64::from(source.min_fetch_interval.max(1));
+    let max = i64::from(source.max_fetch_interval).max(min);
+    let doublings = (source.consecutive_failures - 1).clamp(0, 32) as u32;
+    min.saturating_mul(2_i64.pow(doublings)).min(max)
+}
+
+pub fn next_retry(source: &Source, now: Timestamp) -> Timestamp {
+    now + time::Duration::minutes(retry_interval(source))
+}
+
+/// When to next fetch a source whose hub pushes its updates. It's still
+/// polled, in case pushes are missed, but only every `max_fetch_interval`.
+pub fn next_pushed_fetch(source: &Source, now: Timestamp) -> Timestamp {
+    let min = i64::from(source.min_fetch_interval.max(1));
+    let max = i64::from(source.max_fetch_interval).max(min);
+    now + time::Duration::minutes(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minutes: i64) -> Timestamp {
+        Timestamp(time::Timespec {
+            sec: minutes * 60,
+            nsec: 0,
+        })
+    }
+
+    fn source(adaptive_fetch: bool, feed_ttl: Option<i32>) -> Source {
+        let mut source = Source::new(
+            None,
+            "".into(),
+            serde_json::json!({}),
+            "".into(),
+            "".into(),
+        );
+        source.min_fetch_interval = 15;
+        source.max_fetch_interval = 24 * 60;
+        source.adaptive_fetch = adaptive_fetch;
+        source.feed_ttl = feed_ttl;
+        source
+    }
+
+    #[test]
+    fn fixed_interval() {
+        let now = at(10_000);
+        assert_eq!(fetch_interval(&source(false, Some(600)), &[], now), 15);
+    }
+
+    #[test]
+    fn adaptive_interval() {
+        let now = at(10_000);
+        // No history
+        assert_eq!(fetch_interval(&source(true, None), &[], now), 15);
+        // Posts every ~2 hours
+        let published = [at(9_880), at(9_760), at(9_640), at(9_520)];
+        assert_eq!(fetch_interval(&source(true, None), &published, now), 60);
+        // The feed asks for a longer interval
+        assert_eq!(
+            fetch_interval(&source(true, Some(180)), &published, now),
+            180
+        );
+        // Posts very rarely
+        let published = [at(1_000)];
+        assert_eq!(
+            fetch_interval(&source(true, None), &published, now),
+            24 * 60
+        );
+        // Posts constantly
+        let published = [at(9_999), at(9_998), at(9_997)];
+        assert_eq!(fetch_interval(&source(true, None), &published, now), 15);
+    }
+
+    #[test]
+    fn retry_backoff() {
+        let mut source = source(true, None);
+        let mut intervals = Vec::new();
+        for failures in 1..=9 {
+            source.consecutive_failures = failures;
+            intervals.push(retry_interval(&source));
+        }
+        assert_eq!(
+            intervals,
+            vec![15, 30, 60, 120, 240, 480, 960, 24 * 60, 24 * 60]
+        );
+        source.consecutive_failures = i32::MAX;
+        assert_eq!(retry_interval(&source), 24 * 60);
+    }
+
+    #[test]
+    fn pushed_interval() {
+        let now = at(10_000);
+        assert_eq!(
+            next_pushed_fetch(&source(true, None), now),
+            at(10_000 + 24 * 60)
+        );
+        // Never sooner than the minimum
+        let mut source = source(false, None);
+        source.max_fetch_interval = 5;
+        assert_eq!(next_pushed_fetch(&source, now), at(10_015));
+    }
+}
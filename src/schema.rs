@@ -1,3 +1,14 @@
+table! {
+    article_states (article, username) {
+        article -> Uuid,
+        username -> Text,
+        read -> Bool,
+        starred -> Bool,
+        read_at -> Nullable<Timestamp>,
+        updated -> Timestamp,
+    }
+}
+
 table! {
     articles (id) {
         id -> Uuid,
@@ -14,6 +25,72 @@ table! {
         extensions -> Json,
         source -> Uuid,
         id_from_source -> Nullable<Text>,
+        raw_summary -> Nullable<Text>,
+        raw_content -> Nullable<Json>,
+        enclosures -> Json,
+        thumbnail -> Nullable<Text>,
+        duration -> Nullable<Int4>,
+        full_content -> Nullable<Text>,
+    }
+}
+
+table! {
+    expired_articles (source, id_from_source) {
+        source -> Uuid,
+        id_from_source -> Text,
+    }
+}
+
+table! {
+    fetch_attempts (id) {
+        id -> Uuid,
+        source -> Uuid,
+        started -> Timestamp,
+        duration_ms -> Int4,
+        http_status -> Nullable<Int4>,
+        error_category -> Nullable<Text>,
+        error -> Nullable<Text>,
+        bytes -> Nullable<Int8>,
+        new_articles -> Int4,
+    }
+}
+
+table! {
+    full_content_queue (article) {
+        article -> Uuid,
+        queued_at -> Timestamp,
+        attempts -> Int4,
+        next_attempt -> Timestamp,
+    }
+}
+
+table! {
+    output_feeds (id) {
+        id -> Uuid,
+        owner -> Text,
+        title -> Text,
+        source -> Nullable<Uuid>,
+        tag -> Nullable<Uuid>,
+        starred -> Bool,
+        public -> Bool,
+        token -> Uuid,
+    }
+}
+
+table! {
+    source_events (id) {
+        id -> Uuid,
+        source -> Uuid,
+        at -> Timestamp,
+        kind -> Text,
+        detail -> Text,
+    }
+}
+
+table! {
+    source_refreshes (source) {
+        source -> Uuid,
+        requested_at -> Timestamp,
     }
 }
 
@@ -25,10 +102,24 @@ table! {
         post_filter -> Text,
         last_post -> Timestamp,
         last_successful_fetch -> Timestamp,
-        fetch_errors -> Array<Text>,
         creator -> Text,
         fetching -> Bool,
         last_fetch_started -> Timestamp,
+        etag -> Nullable<Text>,
+        last_modified -> Nullable<Text>,
+        min_fetch_interval -> Int4,
+        max_fetch_interval -> Int4,
+        adaptive_fetch -> Bool,
+        feed_ttl -> Nullable<Int4>,
+        next_fetch -> Timestamp,
+        disabled -> Bool,
+        consecutive_failures -> Int4,
+        retention_max_age -> Nullable<Int4>,
+        retention_max_count -> Nullable<Int4>,
+        retention_keep_starred -> Nullable<Bool>,
+        fetch_full_content -> Bool,
+        content_selector -> Nullable<Text>,
+        site_link -> Nullable<Text>,
     }
 }
 
@@ -60,21 +151,53 @@ table! {
     users (username) {
         username -> Text,
         password -> Text,
+        search_language -> Text,
+        fever_api_key -> Nullable<Text>,
+    }
+}
+
+table! {
+    websub_subscriptions (source) {
+        source -> Uuid,
+        hub -> Text,
+        topic -> Text,
+        secret -> Text,
+        requested_at -> Nullable<Timestamp>,
+        lease_expires -> Nullable<Timestamp>,
     }
 }
 
+joinable!(article_states -> articles (article));
+joinable!(article_states -> users (username));
 joinable!(articles -> sources (source));
+joinable!(expired_articles -> sources (source));
+joinable!(fetch_attempts -> sources (source));
+joinable!(full_content_queue -> articles (article));
+joinable!(output_feeds -> sources (source));
+joinable!(output_feeds -> tags (tag));
+joinable!(output_feeds -> users (owner));
+joinable!(source_events -> sources (source));
+joinable!(source_refreshes -> sources (source));
 joinable!(sources -> users (creator));
 joinable!(tagged_sources -> sources (source));
 joinable!(tagged_sources -> tags (tag));
 joinable!(tags -> users (owner));
 joinable!(tokens -> users (username));
+joinable!(websub_subscriptions -> sources (source));
 
 allow_tables_to_appear_in_same_query!(
+    article_states,
     articles,
+    expired_articles,
+    fetch_attempts,
+    full_content_queue,
+    output_feeds,
+    source_events,
+    source_refreshes,
     sources,
     tagged_sources,
     tags,
     tokens,
     users,
+    websub_subscriptions,
 );
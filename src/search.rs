@@ -0,0 +1,154 @@
+//! Search queries for `GET /items/search`.
+//!
+//! Queries are turned into Postgres `to_tsquery` input, so words are
+//! stemmed with the user's text search language.
+//!
+//! ```text
+//! rust async       both words
+//! rust OR go       either word
+//! "async rust"     the words next to each other, in order
+//! rust*            words starting with "rust"
+//! rust -java       "rust", but not "java" (also works on phrases)
+//! ```
+
+/// A term, before being joined with `&` & `|`.
+enum Term {
+    Or,
+    /// `to_tsquery` input, and whether it's negated
+    Match(String, bool),
+}
+
+/// Quote a word as a `to_tsquery` lexeme, with a trailing `*` meaning a
+/// prefix match.
+fn lexeme(word: &str) -> Option<String> {
+    let stem = word.trim_end_matches('*');
+    if stem.is_empty() {
+        return None;
+    }
+    let prefix = if stem.len() < word.len() { ":*" } else { "" };
+    Some(format!(
+        "'{}'{}",
+        stem.replace('\\', "\\\\").replace('\'', "''"),
+        prefix
+    ))
+}
+
+fn phrase(words: &str) -> Option<String> {
+    let lexemes: Vec<String> =
+        words.split_whitespace().filter_map(lexeme).collect();
+    match lexemes.len() {
+        0 => None,
+        1 => lexemes.into_iter().next(),
+        _ => Some(format!("({})", lexemes.join(" <-> "))),
+    }
+}
+
+fn terms(query: &str) -> Vec<Term> {
+    let mut terms = Vec::new();
+    let mut chars = query.chars().peekable();
+    loop {
+        while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        let negated = chars.peek() == Some(&'-');
+        if negated {
+            chars.next();
+        }
+
+        let term = match chars.peek() {
+            None => break,
+            Some('"') => {
+                chars.next();
+                let words: String =
+                    chars.by_ref().take_while(|&c| c != '"').collect();
+                phrase(&words)
+            }
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if word == "OR" && !negated {
+                    terms.push(Term::Or);
+                    continue;
+                }
+                lexeme(&word)
+            }
+        };
+        if let Some(term) = term {
+            terms.push(Term::Match(term, negated));
+        }
+    }
+    terms
+}
+
+/// Convert a search query to `to_tsquery` input. `None` if the query has
+/// nothing to search for.
+pub fn to_tsquery(query: &str) -> Option<String> {
+    let mut groups: Vec<Vec<String>> = vec![Vec::new()];
+    for term in terms(query) {
+        match term {
+            Term::Or => groups.push(Vec::new()),
+            Term::Match(term, negated) => {
+                let term = if negated { format!("!{}", term) } else { term };
+                groups.last_mut().unwrap().push(term);
+            }
+        }
+    }
+    let groups: Vec<String> = groups
+        .into_iter()
+        .filter(|g| !g.is_empty())
+        .map(|g| g.join(" & "))
+        .collect();
+    if groups.is_empty() {
+        None
+    } else {
+        Some(groups.join(" | "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_syntax() {
+        assert_eq!(to_tsquery("rust"), Some("'rust'".into()));
+        assert_eq!(
+            to_tsquery("  rust  async* "),
+            Some("'rust' & 'async':*".into())
+        );
+        assert_eq!(
+            to_tsquery("rust OR go -java"),
+            Some("'rust' | 'go' & !'java'".into())
+        );
+        assert_eq!(
+            to_tsquery("\"async rust\" -\"hello world\" \"one\""),
+            Some(
+                "('async' <-> 'rust') & !('hello' <-> 'world') & 'one'".into()
+            )
+        );
+        assert_eq!(
+            to_tsquery("\"unclosed phrase"),
+            to_tsquery("\"unclosed phrase\"")
+        );
+    }
+
+    #[test]
+    fn query_escaping() {
+        assert_eq!(
+            to_tsquery("don't back\\slash & (x)"),
+            Some("'don''t' & 'back\\\\slash' & '&' & '(x)'".into())
+        );
+    }
+
+    #[test]
+    fn empty_queries() {
+        assert_eq!(to_tsquery(""), None);
+        assert_eq!(to_tsquery("  * - \"\" OR "), None);
+    }
+}
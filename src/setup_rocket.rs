@@ -1,6 +1,12 @@
 use crate::{
-    api::v1::{items, sources, users},
-    db, state,
+    api::{
+        fever, greader, nextcloud,
+        v1::{items, opml, output_feeds, sources, tags, users},
+        websub,
+    },
+    db,
+    retention::RetentionPolicy,
+    state,
 };
 
 use rocket::fairing::AdHoc;
@@ -9,20 +15,98 @@ pub fn setup_rocket() -> rocket::Rocket {
     dotenv::dotenv().expect("Failed to read .env file");
     rocket::ignite()
         .manage(db::init_pool())
+        .manage(state::RefreshLimiter::default())
+        .manage(state::AuthCache::default())
+        // For articles hubs push, which are stored by the web server
+        .manage(RetentionPolicy::from_env())
         .mount(
             "/api/v1/",
             routes![
-                items::index,
+                items::items_list,
+                items::items_search,
+                items::items_raw,
+                items::items_state_update,
+                items::items_mark_read,
                 users::user_create,
                 users::user_login,
                 users::user_change_pass,
+                users::user_search_language_update,
+                users::user_fever_enable,
+                users::user_fever_disable,
                 users::user_logout,
                 users::user_delete,
                 users::user_index,
                 sources::source_create,
+                sources::source_preview,
                 sources::sources_list,
                 sources::source_update,
                 sources::source_delete,
+                sources::source_events_list,
+                sources::source_fetches_list,
+                sources::source_refresh,
+                tags::tags_list,
+                tags::tag_create,
+                tags::tag_rename,
+                tags::tag_delete,
+                tags::tag_sources_list,
+                tags::tag_refresh,
+                tags::tag_attach,
+                tags::tag_detach,
+                opml::opml_import,
+                opml::opml_export,
+                output_feeds::output_feeds_list,
+                output_feeds::output_feed_create,
+                output_feeds::output_feed_update,
+                output_feeds::output_feed_delete,
+                output_feeds::output_feed_revoke,
+                output_feeds::output_feed_private,
+                output_feeds::output_feed_public,
+            ],
+        )
+        .mount("/api/fever", routes![fever::fever])
+        .mount("/api/websub", routes![websub::verify, websub::receive])
+        .mount(
+            "/api/greader/",
+            routes![
+                greader::accounts::client_login,
+                greader::accounts::token,
+                greader::accounts::user_info,
+                greader::subscriptions::subscription_list,
+                greader::subscriptions::subscription_edit,
+                greader::subscriptions::subscription_quickadd,
+                greader::tags::tag_list,
+                greader::streams::stream_contents,
+                greader::streams::stream_contents_param,
+                greader::streams::stream_item_ids,
+                greader::streams::stream_items_contents,
+                greader::streams::stream_items_contents_post,
+                greader::streams::edit_tag,
+                greader::streams::mark_all_as_read,
+                greader::streams::unread_count,
+            ],
+        )
+        .mount(
+            "/index.php/apps/news/api/v1-3",
+            routes![
+                nextcloud::version,
+                nextcloud::status,
+                nextcloud::user,
+                nextcloud::folders::folders,
+                nextcloud::folders::create_folder,
+                nextcloud::folders::delete_folder,
+                nextcloud::folders::rename_folder,
+                nextcloud::folders::mark_folder_read,
+                nextcloud::feeds::feeds,
+                nextcloud::feeds::create_feed,
+                nextcloud::feeds::delete_feed,
+                nextcloud::feeds::move_feed,
+                nextcloud::feeds::rename_feed,
+                nextcloud::feeds::mark_feed_read,
+                nextcloud::items::items_list,
+                nextcloud::items::items_updated,
+                nextcloud::items::item_action,
+                nextcloud::items::items_action,
+                nextcloud::items::mark_all_read,
             ],
         )
         .attach(AdHoc::on_attach("Environment tracker", |rocket| {
@@ -1 +1,86 @@
+pub mod discovery;
+pub mod full_content;
+pub mod http;
+pub mod jsonfeed;
+pub mod media;
 pub mod rssatom;
+
+use crate::{
+    db::sources::SourceData,
+    sources::{
+        discovery::FeedCandidate,
+        http::{FetchCache, Fetched},
+        jsonfeed::JSONFeed,
+        rssatom::{ParsedFeed, RSSAtom},
+    },
+    Result,
+};
+use uuid::Uuid;
+
+/// A feed found by `detect`.
+#[derive(Debug)]
+pub struct DetectedFeed {
+    pub source_data: SourceData,
+    pub feed: ParsedFeed,
+}
+
+#[derive(Debug)]
+pub enum Detected {
+    Feed(DetectedFeed),
+    /// An HTML page linking to several feeds
+    Candidates(Vec<FeedCandidate>),
+}
+
+/// Work out what kind of source is at `url`, by fetching it.
+///
+/// HTML pages are searched for links to feeds. If there's only one, it's
+/// used, otherwise the links are returned to pick from. If a URL permanently
+/// redirects, the source uses the new URL.
+pub fn detect(url: &str, source_id: Uuid) -> Result<Detected> {
+    let (url, body) = get(url)?;
+    let parse_err = match parse_feed(&url, &body, source_id) {
+        Ok(feed) => return Ok(Detected::Feed(feed)),
+        Err(e) => e,
+    };
+    if !discovery::looks_like_html(&body) {
+        return Err(parse_err);
+    }
+
+    let mut candidates =
+        discovery::discover(&url, &String::from_utf8_lossy(&body));
+    match candidates.len() {
+        0 => Err(format!("No feeds are linked from {}", url).into()),
+        1 => {
+            let candidate = candidates.remove(0);
+            let (url, body) = get(&candidate.url)?;
+            Ok(Detected::Feed(parse_feed(&url, &body, source_id)?))
+        }
+        _ => Ok(Detected::Candidates(candidates)),
+    }
+}
+
+/// GET `url`, returning the URL to use from now on along with the body.
+fn get(url: &str) -> Result<(String, Vec<u8>)> {
+    let response = http::conditional_get(url, &FetchCache::default())?;
+    let url = response.moved_to.unwrap_or_else(|| url.to_string());
+    match response.fetched {
+        Fetched::Modified(body, _) => Ok((url, body)),
+        Fetched::NotModified => Ok((url, Vec::new())),
+    }
+}
+
+fn parse_feed(url: &str, body: &[u8], source_id: Uuid) -> Result<DetectedFeed> {
+    if jsonfeed::is_json_feed(body) {
+        let json_feed = JSONFeed::new(url.to_string(), source_id);
+        Ok(DetectedFeed {
+            feed: json_feed.parse(body)?,
+            source_data: SourceData::JSONFeed(json_feed),
+        })
+    } else {
+        let rss_atom = RSSAtom::new(url.to_string(), source_id);
+        Ok(DetectedFeed {
+            feed: rss_atom.parse(body)?,
+            source_data: SourceData::RSSAtom(rss_atom),
+        })
+    }
+}
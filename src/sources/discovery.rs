@@ -0,0 +1,152 @@
+// Finding feeds linked from an HTML page, with
+// `<link rel="alternate" type="application/rss+xml" href="...">`
+
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// `type`s of `<link>`s that point to feeds.
+const FEED_TYPES: [&str; 3] = [
+    "application/rss+xml",
+    "application/atom+xml",
+    "application/feed+json",
+];
+
+lazy_static! {
+    static ref LINK_RE: Regex = Regex::new(r"(?is)<link\s[^>]*>").unwrap();
+    /// An attribute, with its value double, single or un-quoted
+    static ref ATTR_RE: Regex = Regex::new(
+        r#"(?s)([a-zA-Z_:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#,
+    )
+    .unwrap();
+}
+
+/// A feed linked from an HTML page.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct FeedCandidate {
+    pub url: String,
+    pub title: Option<String>,
+    pub content_type: String,
+}
+
+/// Whether a response body looks like an HTML page, rather than a feed.
+pub fn looks_like_html(body: &[u8]) -> bool {
+    let start = &body[..body.len().min(1024)];
+    let start = String::from_utf8_lossy(start).to_lowercase();
+    let start = start.trim_start_matches('\u{feff}').trim_start();
+    start.starts_with("<!doctype html") || start.contains("<html")
+}
+
+/// Get the feeds linked from an HTML page at `page_url`, in page order.
+pub fn discover(page_url: &str, html: &str) -> Vec<FeedCandidate> {
+    let page_url = match Url::parse(page_url) {
+        Ok(url) => url,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates: Vec<FeedCandidate> = Vec::new();
+    for link in LINK_RE.find_iter(html) {
+        let (mut rel, mut content_type, mut href, mut title) =
+            (None, None, None, None);
+        for attr in ATTR_RE.captures_iter(link.as_str()) {
+            let value = attr
+                .get(2)
+                .or_else(|| attr.get(3))
+                .or_else(|| attr.get(4))
+                .map(|v| unescape(v.as_str()));
+            match attr[1].to_lowercase().as_str() {
+                "rel" => rel = value,
+                "type" => content_type = value,
+                "href" => href = value,
+                "title" => title = value,
+                _ => (),
+            }
+        }
+
+        let is_alternate = rel
+            .as_deref()
+            .unwrap_or("")
+            .split_whitespace()
+            .any(|r| r.eq_ignore_ascii_case("alternate"));
+        let content_type = match content_type {
+            Some(t)
+                if FEED_TYPES.contains(&t.trim().to_lowercase().as_str()) =>
+            {
+                t.trim().to_lowercase()
+            }
+            _ => continue,
+        };
+        let url = match href.and_then(|href| page_url.join(href.trim()).ok()) {
+            Some(url) => url.to_string(),
+            None => continue,
+        };
+        if !is_alternate || candidates.iter().any(|c| c.url == url) {
+            continue;
+        }
+        candidates.push(FeedCandidate {
+            url,
+            title: title.filter(|t| !t.trim().is_empty()),
+            content_type,
+        });
+    }
+    candidates
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn discover_feed_links() {
+        let html = r#"<!DOCTYPE html>
+<html>
+<head>
+  <title>Example</title>
+  <link rel="stylesheet" href="/style.css">
+  <link rel="alternate" type="application/rss+xml" title="Posts &amp; news"
+        href="/feed.xml">
+  <LINK REL='alternate' TYPE='application/atom+xml' HREF='https://example.org/atom'>
+  <link rel="alternate" type="application/json+oembed" href="/oembed">
+  <link type="application/feed+json" rel="alternate home" href=feed.json />
+  <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+</head>
+<body></body>
+</html>"#;
+        assert!(looks_like_html(html.as_bytes()));
+        assert_eq!(
+            discover("https://example.org/blog/", html),
+            vec![
+                FeedCandidate {
+                    url: "https://example.org/feed.xml".into(),
+                    title: Some("Posts & news".into()),
+                    content_type: "application/rss+xml".into(),
+                },
+                FeedCandidate {
+                    url: "https://example.org/atom".into(),
+                    title: None,
+                    content_type: "application/atom+xml".into(),
+                },
+                FeedCandidate {
+                    url: "https://example.org/blog/feed.json".into(),
+                    title: None,
+                    content_type: "application/feed+json".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn feeds_are_not_html() {
+        assert!(!looks_like_html(b"<?xml version=\"1.0\"?><rss></rss>"));
+        assert!(!looks_like_html(b"{\"version\": \"x\"}"));
+        assert!(looks_like_html(b"\n  <html lang=\"en\"><head>"));
+    }
+}
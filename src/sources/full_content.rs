@@ -0,0 +1,430 @@
+// Full article content, for sources that only publish a summary.
+//
+// The article's page is downloaded, and its main content found with a
+// readability-style heuristic: paragraphs score points for their parents,
+// which are weighed by their class, id & how much of them is links. Sources
+// can give a CSS selector instead, for pages the heuristic gets wrong.
+//
+// Pages are fetched by the worker after articles are stored, a few at a
+// time, so slow pages don't hold up fetching sources.
+
+use crate::{
+    db::{
+        self, articles, articles::Article, full_content_queue,
+        full_content_queue::Queued, sources,
+    },
+    sanitize::sanitize_html,
+    sources::http,
+    timestamp::Timestamp,
+    Result,
+};
+use ammonia::Url;
+use diesel::prelude::*;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::{collections::HashMap, error::Error};
+use uuid::Uuid;
+
+/// Most pages fetched by each `backfill`.
+const PAGES_PER_RUN: i64 = 20;
+/// How long a `backfill` has to fetch its pages, before they're taken again.
+/// Longer than every page timing out.
+const LEASE_MINUTES: i64 = 15;
+/// Minutes before an article's first retry, doubling after each failure.
+const RETRY_MINUTES: i64 = 15;
+/// Articles are given up on after failing this many times.
+const MAX_ATTEMPTS: i32 = 5;
+/// Articles of sources with an invalid `content_selector` wait this long for
+/// it to be fixed.
+const INVALID_SELECTOR_MINUTES: i64 = 60;
+
+/// Less text than this isn't the main content, it's a page without any.
+const MIN_TEXT_LENGTH: usize = 250;
+/// Shorter paragraphs don't count towards their parents' scores.
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+lazy_static! {
+    /// Removed before scoring, unless they also look like content.
+    static ref UNLIKELY: Regex = Regex::new(concat!(
+        "(?i)",
+        "-ad-|banner|breadcrumbs|combx|comment|community|disqus|",
+        "extra|footer|gdpr|header|legends|menu|related|remark|",
+        "replies|rss|shoutbox|sidebar|skyscraper|social|sponsor|",
+        "supplemental|pagination|pager|popup",
+    ))
+    .unwrap();
+    static ref MAYBE: Regex =
+        Regex::new(r"(?i)and|article|body|column|content|main|shadow")
+            .unwrap();
+    static ref POSITIVE: Regex = Regex::new(concat!(
+        "(?i)",
+        "article|body|content|entry|hentry|h-entry|main|page|post|",
+        "text|blog|story",
+    ))
+    .unwrap();
+    static ref NEGATIVE: Regex = Regex::new(concat!(
+        "(?i)",
+        "-ad-|hidden|banner|combx|comment|com-|contact|foot|footnote|",
+        "gdpr|masthead|media|meta|outbrain|promo|related|scroll|",
+        "share|shoutbox|sidebar|skyscraper|sponsor|shopping|tags|",
+        "tool|widget",
+    ))
+    .unwrap();
+}
+
+/// Never part of the content.
+const REMOVED_TAGS: [&str; 7] = [
+    "script", "style", "noscript", "nav", "aside", "form", "footer",
+];
+
+/// Parse a source's `content_selector`.
+pub fn parse_selector(selector: &str) -> std::result::Result<Selector, String> {
+    Selector::parse(selector)
+        .map_err(|e| format!("Invalid content_selector {:?}", e))
+}
+
+/// Points for an element's class & id.
+fn class_weight(element: ElementRef) -> f64 {
+    let element = element.value();
+    element
+        .attr("class")
+        .into_iter()
+        .chain(element.id())
+        .map(|name| {
+            let mut weight = 0.0;
+            if POSITIVE.is_match(name) {
+                weight += 25.0;
+            }
+            if NEGATIVE.is_match(name) {
+                weight -= 25.0;
+            }
+            weight
+        })
+        .sum()
+}
+
+fn is_unlikely(element: ElementRef) -> bool {
+    let value = element.value();
+    if ["html", "body", "article", "main"].contains(&value.name()) {
+        return false;
+    }
+    let names = format!(
+        "{} {}",
+        value.attr("class").unwrap_or(""),
+        value.id().unwrap_or("")
+    );
+    UNLIKELY.is_match(&names) && !MAYBE.is_match(&names)
+}
+
+/// Whitespace-collapsed text, to measure it.
+fn text(element: ElementRef) -> String {
+    element
+        .text()
+        .flat_map(|t| t.split_whitespace())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// How much of an element's text is in links, from 0 to 1.
+fn link_density(element: ElementRef, links: &Selector) -> f64 {
+    let length = text(element).len();
+    if length == 0 {
+        return 0.0;
+    }
+    let link_length: usize = element.select(links).map(|a| text(a).len()).sum();
+    link_length as f64 / length as f64
+}
+
+/// Points an element starts with, by tag.
+fn tag_score(element: ElementRef) -> f64 {
+    match element.value().name() {
+        "div" | "article" | "main" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    }
+}
+
+/// Remove elements that are never, or unlikely to be, content.
+fn strip_unlikely(document: &mut Html) {
+    let all = Selector::parse("*").unwrap();
+    let removed: Vec<_> = document
+        .select(&all)
+        .filter(|element| {
+            REMOVED_TAGS.contains(&element.value().name())
+                || is_unlikely(*element)
+        })
+        .map(|element| element.id())
+        .collect();
+    for id in removed {
+        if let Some(mut node) = document.tree.get_mut(id) {
+            node.detach();
+        }
+    }
+}
+
+/// Find the main content of a page, as HTML. With a `selector`, that's
+/// every element it matches, otherwise it's found heuristically. `None` if
+/// there doesn't seem to be any.
+pub fn extract(html: &str, selector: Option<&Selector>) -> Option<String> {
+    let mut document = Html::parse_document(html);
+    if let Some(selector) = selector {
+        let matches: Vec<String> =
+            document.select(selector).map(|e| e.html()).collect();
+        return Some(matches.concat()).filter(|html| !html.is_empty());
+    }
+
+    strip_unlikely(&mut document);
+    let paragraphs = Selector::parse("p, pre, td, blockquote").unwrap();
+    let links = Selector::parse("a").unwrap();
+
+    // Paragraphs give their parent full points, & grandparent half
+    let mut scores = HashMap::new();
+    for paragraph in document.select(&paragraphs) {
+        let length = text(paragraph).len();
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+        let points = 1.0
+            + text(paragraph).matches(',').count() as f64
+            + (length as f64 / 100.0).min(3.0);
+        let ancestors = paragraph
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .take(2)
+            .zip(&[1.0, 0.5]);
+        for (ancestor, share) in ancestors {
+            *scores.entry(ancestor.id()).or_insert_with(|| {
+                tag_score(ancestor) + class_weight(ancestor)
+            }) += points * share;
+        }
+    }
+
+    let (best, best_score) = scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let element = ElementRef::wrap(document.tree.get(id)?)?;
+            Some((element, score * (1.0 - link_density(element, &links))))
+        })
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())?;
+    if best_score <= 0.0 || text(best).len() < MIN_TEXT_LENGTH {
+        return None;
+    }
+    Some(best.html())
+}
+
+/// Download an article's page, & store its main content in `full_content`.
+pub fn fetch(article: &mut Article, selector: Option<&Selector>) -> Result<()> {
+    let page_url = article
+        .links
+        .article_url()
+        .ok_or("Article has no link to fetch")?;
+    let (url, html) = http::get_page(page_url)?;
+    let base = Url::parse(&url).ok();
+    article.full_content = extract(&html, selector)
+        .map(|content| sanitize_html(&content, base.as_ref()));
+    Ok(())
+}
+
+/// Fetch full content for queued articles, up to `PAGES_PER_RUN` of them.
+/// Returns how many were fetched.
+pub fn backfill(pool: &db::Pool) -> Result<usize> {
+    let conn = db::DbConn(pool.get()?);
+    let queued = full_content_queue::take(
+        PAGES_PER_RUN,
+        time::Duration::minutes(LEASE_MINUTES),
+        &conn,
+    )?;
+    fetch_queued(&queued, &conn)
+}
+
+/// Fetch full content for articles taken from the queue. Articles leave the
+/// queue once it's stored. Failed ones are retried later, until they've
+/// failed `MAX_ATTEMPTS` times, & are then kept without it.
+fn fetch_queued(queued: &[Queued], conn: &PgConnection) -> Result<usize> {
+    let attempts: HashMap<Uuid, i32> =
+        queued.iter().map(|q| (q.article, q.attempts)).collect();
+    let ids: Vec<Uuid> = attempts.keys().copied().collect();
+    let mut known: HashMap<Uuid, sources::Source> = HashMap::new();
+    let mut fetched = 0;
+    for mut article in articles::all_with_ids(&ids, conn)? {
+        if !known.contains_key(&article.source) {
+            let source = sources::get(article.source, conn)?;
+            known.insert(source.id, source);
+        }
+        let source = &known[&article.source];
+        let failures = attempts[&article.id];
+        // The source may have stopped fetching full content since
+        if !source.fetch_full_content {
+            full_content_queue::remove(article.id, conn)?;
+            continue;
+        }
+        let selector =
+            match source.content_selector.as_deref().map(parse_selector) {
+                Some(Ok(selector)) => Some(selector),
+                Some(Err(e)) => {
+                    log::warn!("{}", e);
+                    full_content_queue::retry(
+                        article.id,
+                        failures,
+                        Timestamp::now()
+                            + time::Duration::minutes(INVALID_SELECTOR_MINUTES),
+                        conn,
+                    )?;
+                    continue;
+                }
+                None => None,
+            };
+        match fetch(&mut article, selector.as_ref()) {
+            Ok(()) => {
+                conn.transaction::<_, Box<dyn Error>, _>(|| {
+                    articles::set_full_content(
+                        article.id,
+                        article.full_content,
+                        conn,
+                    )?;
+                    full_content_queue::remove(article.id, conn)?;
+                    Ok(())
+                })?;
+                fetched += 1;
+            }
+            Err(e) => {
+                log::warn!(
+                    "Could not fetch full content for article {}: {}",
+                    article.id,
+                    e
+                );
+                let failures = failures + 1;
+                if failures < MAX_ATTEMPTS {
+                    let delay = RETRY_MINUTES << (failures - 1);
+                    full_content_queue::retry(
+                        article.id,
+                        failures,
+                        Timestamp::now() + time::Duration::minutes(delay),
+                        conn,
+                    )?;
+                } else {
+                    full_content_queue::remove(article.id, conn)?;
+                }
+            }
+        }
+    }
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db::articles::{ArticleLink, ArticleLinks},
+        schema::full_content_queue as queue,
+        testing,
+    };
+    use std::fs;
+
+    #[test]
+    fn extract_main_content() {
+        let html =
+            fs::read_to_string("test_data/full_content_article.html").unwrap();
+        let content = extract(&html, None).unwrap();
+        assert!(content.starts_with("<article"));
+        assert!(content.contains("Why we rewrote the fetcher"));
+        assert!(content.contains("Conditional requests"));
+        assert!(content.contains("fetcher.png"));
+        assert!(!content.contains("Share on Twitter"));
+        assert!(!content.contains("Popular posts"));
+        assert!(!content.contains("Great post"));
+        assert!(!content.contains("analytics"));
+    }
+
+    #[test]
+    fn selector_override() {
+        let html =
+            fs::read_to_string("test_data/full_content_selector.html").unwrap();
+        // Too little text for the heuristic
+        assert_eq!(extract(&html, None), None);
+
+        let selector = parse_selector("td.notes-body").unwrap();
+        let content = extract(&html, Some(&selector)).unwrap();
+        assert!(content.contains("Version 2.0 is out."));
+        assert!(!content.contains("Previous release notes"));
+
+        let missing = parse_selector(".story").unwrap();
+        assert_eq!(extract(&html, Some(&missing)), None);
+        assert!(parse_selector("div >").is_err());
+    }
+
+    #[test]
+    fn failures_stay_queued() {
+        let conn = testing::conn();
+        let (username, _) =
+            testing::seed_user("full-content", "hunter22", &conn);
+        let source = |selector: &str| {
+            sources::insert(
+                sources::Source {
+                    fetch_full_content: true,
+                    content_selector: Some(selector.into()),
+                    ..sources::Source::new(
+                        None,
+                        "".into(),
+                        serde_json::json!({}),
+                        "".into(),
+                        username.clone(),
+                    )
+                },
+                &conn,
+            )
+            .unwrap()
+        };
+        let (valid, invalid) = (source("p"), source("div >"));
+        let article = |source: Uuid, url: String| {
+            articles::insert(
+                Article {
+                    source,
+                    links: ArticleLinks(vec![ArticleLink::new(url)]),
+                    ..testing::article()
+                },
+                &conn,
+            )
+            .unwrap()
+            .id
+        };
+        let fetched = article(
+            valid.id,
+            testing::serve(&["HTTP/1.1 200 OK\r\n\r\n<p>Full content</p>"]),
+        );
+        let failed = article(
+            valid.id,
+            testing::serve(&["HTTP/1.1 500 Internal Server Error\r\n\r\n"]),
+        );
+        let waiting = article(invalid.id, "http://example.org/".into());
+        let ids = [fetched, failed, waiting];
+        full_content_queue::push(&ids, &conn).unwrap();
+
+        let queued: Vec<Queued> = ids
+            .iter()
+            .map(|&article| Queued {
+                article,
+                attempts: 0,
+            })
+            .collect();
+        assert_eq!(fetch_queued(&queued, &conn).unwrap(), 1);
+        assert_eq!(
+            articles::get(fetched, &conn)
+                .unwrap()
+                .full_content
+                .as_deref(),
+            Some("<p>Full content</p>")
+        );
+        let left: Vec<(Uuid, i32)> = queue::table
+            .select((queue::article, queue::attempts))
+            .filter(queue::article.eq_any(&ids[..]))
+            .order(queue::attempts.desc())
+            .load(&*conn)
+            .unwrap();
+        assert_eq!(left, [(failed, 1), (waiting, 0)]);
+
+        testing::remove_user(username, &conn);
+    }
+}
@@ -0,0 +1,491 @@
+// Shared HTTP fetching for sources
+
+use crate::{timestamp::Timestamp, Result};
+use reqwest::{
+    blocking::Client,
+    header::{
+        ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, LOCATION,
+        RETRY_AFTER,
+    },
+    redirect::Policy,
+    StatusCode, Url,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Read},
+    time::Duration,
+};
+
+/// Give up on a source that takes longer than this to respond.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Give up on a source that redirects more than this.
+const MAX_REDIRECTS: usize = 10;
+/// Give up on a web page larger than this.
+const MAX_PAGE_SIZE: u64 = 2 * 1024 * 1024;
+/// Give up on a feed larger than this.
+const MAX_FEED_SIZE: u64 = 10 * 1024 * 1024;
+
+/// HTTP cache validators from a source's last successful fetch.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FetchCache {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Result of a conditional fetch.
+#[derive(Debug)]
+pub enum Fetched<T> {
+    /// The source hasn't changed since the validators in the `FetchCache`
+    /// were stored.
+    NotModified,
+    Modified(T, FetchCache),
+}
+
+impl<T> Fetched<T> {
+    pub fn map<U, F: FnOnce(T) -> Result<U>>(self, f: F) -> Result<Fetched<U>> {
+        Ok(match self {
+            Fetched::NotModified => Fetched::NotModified,
+            Fetched::Modified(t, cache) => Fetched::Modified(f(t)?, cache),
+        })
+    }
+}
+
+/// A conditional fetch, along with where the source has moved to.
+#[derive(Debug)]
+pub struct FetchResponse<T> {
+    pub fetched: Fetched<T>,
+    /// The final URL, if every redirect on the way was permanent (301/308)
+    pub moved_to: Option<String>,
+    /// HTTP status of the final response
+    pub status: u16,
+    /// Size of the response body
+    pub bytes: usize,
+}
+
+impl<T> FetchResponse<T> {
+    /// Errors from `f` keep where the source moved to, see `MovedError`.
+    pub fn map<U, F: FnOnce(T) -> Result<U>>(
+        self,
+        f: F,
+    ) -> Result<FetchResponse<U>> {
+        let moved_to = self.moved_to;
+        match self.fetched.map(f) {
+            Ok(fetched) => Ok(FetchResponse {
+                fetched,
+                moved_to,
+                status: self.status,
+                bytes: self.bytes,
+            }),
+            Err(error) => Err(MovedError::wrap(moved_to, error)),
+        }
+    }
+}
+
+/// A fetch that failed after the source moved permanently. The source's
+/// new URL should still be saved.
+#[derive(Debug)]
+pub struct MovedError {
+    pub moved_to: String,
+    pub error: Box<dyn Error>,
+}
+
+impl MovedError {
+    fn wrap(moved_to: Option<String>, error: Box<dyn Error>) -> Box<dyn Error> {
+        match moved_to {
+            Some(moved_to) => Box::new(MovedError { moved_to, error }),
+            None => error,
+        }
+    }
+}
+
+impl fmt::Display for MovedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (moved permanently to {})", self.error, self.moved_to)
+    }
+}
+
+impl Error for MovedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&*self.error)
+    }
+}
+
+/// Responses that should change how a source is fetched from now on.
+#[derive(Debug)]
+pub enum StatusError {
+    /// 410 Gone: the source won't be back.
+    Gone,
+    /// 429/503 with a `Retry-After`: don't fetch again until `at`.
+    RetryAfter { status: u16, at: Timestamp },
+}
+
+impl StatusError {
+    pub fn status(&self) -> u16 {
+        match self {
+            StatusError::Gone => StatusCode::GONE.as_u16(),
+            StatusError::RetryAfter { status, .. } => *status,
+        }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatusError::Gone => write!(f, "Source is gone (410)"),
+            StatusError::RetryAfter { at, .. } => write!(
+                f,
+                "Source asked to retry after {}",
+                time::at_utc(at.0).rfc822()
+            ),
+        }
+    }
+}
+
+impl Error for StatusError {}
+
+/// Requests that failed before the source responded, in ways reqwest
+/// doesn't tell apart from other connection errors.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The source's host didn't resolve.
+    Dns(reqwest::Error),
+    /// The TLS handshake failed, ex: for an untrusted certificate.
+    Tls(reqwest::Error),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectError::Dns(e) => write!(f, "DNS lookup failed: {}", e),
+            ConnectError::Tls(e) => write!(f, "TLS handshake failed: {}", e),
+        }
+    }
+}
+
+impl Error for ConnectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConnectError::Dns(e) | ConnectError::Tls(e) => Some(e),
+        }
+    }
+}
+
+/// Whether any error in `e`'s chain of causes is a `T`.
+fn caused_by<T: Error + 'static>(e: &(dyn Error + 'static)) -> bool {
+    let mut cause = Some(e);
+    while let Some(c) = cause {
+        // `io::Error`s hide what they wrap from `source`
+        let wrapped = c.downcast_ref::<io::Error>().and_then(|e| e.get_ref());
+        if c.is::<T>() || wrapped.map_or(false, |w| w.is::<T>()) {
+            return true;
+        }
+        cause = c.source();
+    }
+    false
+}
+
+/// Send a request to `url`, telling DNS & TLS failures apart.
+fn send(
+    request: reqwest::blocking::RequestBuilder,
+    url: &Url,
+) -> Result<reqwest::blocking::Response> {
+    let e = match request.send() {
+        Ok(resp) => return Ok(resp),
+        Err(e) => e,
+    };
+    if e.is_timeout() {
+        return Err(e.into());
+    }
+    if caused_by::<native_tls::Error>(&e) {
+        return Err(ConnectError::Tls(e).into());
+    }
+    // The resolver's errors are plain `io::Error`s, so look the host up
+    // again to see whether that's what failed
+    if url.socket_addrs(|| None).is_err() {
+        return Err(ConnectError::Dns(e).into());
+    }
+    Err(e.into())
+}
+
+/// Read a response's body, failing if it's larger than `max_size`.
+fn read_body(
+    resp: reqwest::blocking::Response,
+    max_size: u64,
+    what: &str,
+) -> Result<Vec<u8>> {
+    let too_large = || format!("{} is larger than {} bytes", what, max_size);
+    if resp.content_length().map_or(false, |len| len > max_size) {
+        return Err(too_large().into());
+    }
+    let mut body = Vec::new();
+    resp.take(max_size + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > max_size {
+        return Err(too_large().into());
+    }
+    Ok(body)
+}
+
+/// Parse a `Retry-After` header, either a number of seconds or a date.
+fn retry_after(value: &str, now: Timestamp) -> Option<Timestamp> {
+    match value.trim().parse::<u32>() {
+        Ok(secs) => Some(now + time::Duration::seconds(i64::from(secs))),
+        Err(_) => value.parse().ok(),
+    }
+}
+
+/// GET `url`, sending `If-None-Match`/`If-Modified-Since` from `cache`.
+///
+/// Redirects are followed, and reported in `moved_to` if they're all
+/// permanent. `cache` is only sent to `url` itself, since validators from
+/// one URL mean nothing to another. Gone & rate limited sources are
+/// reported as a `StatusError`. Failures after a permanent redirect are a
+/// `MovedError`. Feeds larger than `MAX_FEED_SIZE` are an error.
+pub fn conditional_get(
+    url: &str,
+    cache: &FetchCache,
+) -> Result<FetchResponse<Vec<u8>>> {
+    let mut moved_to = None;
+    get_following(url, cache, &mut moved_to)
+        .map_err(|error| MovedError::wrap(moved_to, error))
+}
+
+/// `conditional_get`, keeping `moved_to` up to date as redirects arrive.
+fn get_following(
+    url: &str,
+    cache: &FetchCache,
+    moved_to: &mut Option<String>,
+) -> Result<FetchResponse<Vec<u8>>> {
+    let client = Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(Policy::none())
+        .build()?;
+    let mut url = Url::parse(url)?;
+    let mut redirects = 0;
+    let mut permanent = true;
+
+    let resp = loop {
+        let mut request = client.get(url.clone());
+        if redirects == 0 {
+            if let Some(etag) = &cache.etag {
+                request = request.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &cache.last_modified {
+                request =
+                    request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+
+        let resp = send(request, &url)?;
+        let status = resp.status();
+        if !status.is_redirection() || status == StatusCode::NOT_MODIFIED {
+            break resp;
+        }
+        redirects += 1;
+        if redirects > MAX_REDIRECTS {
+            return Err(format!("More than {} redirects", MAX_REDIRECTS).into());
+        }
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| format!("{} without a Location", status))?;
+        url = url.join(location)?;
+        permanent &= status == StatusCode::MOVED_PERMANENTLY
+            || status == StatusCode::PERMANENT_REDIRECT;
+        *moved_to = if permanent {
+            Some(url.to_string())
+        } else {
+            None
+        };
+    };
+
+    let header = |name| {
+        resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string())
+    };
+    match resp.status() {
+        StatusCode::NOT_MODIFIED => {
+            return Ok(FetchResponse {
+                fetched: Fetched::NotModified,
+                moved_to: moved_to.take(),
+                status: StatusCode::NOT_MODIFIED.as_u16(),
+                bytes: 0,
+            })
+        }
+        StatusCode::GONE => return Err(StatusError::Gone.into()),
+        StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => {
+            if let Some(at) = header(RETRY_AFTER)
+                .and_then(|v| retry_after(&v, Timestamp::now()))
+            {
+                return Err(StatusError::RetryAfter {
+                    status: resp.status().as_u16(),
+                    at,
+                }
+                .into());
+            }
+        }
+        _ => (),
+    }
+    // After a temporary redirect, validators would be for the wrong URL
+    let new_cache = if redirects > 0 && moved_to.is_none() {
+        FetchCache::default()
+    } else {
+        FetchCache {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    };
+    let resp = resp.error_for_status()?;
+    let status = resp.status().as_u16();
+    let body = read_body(resp, MAX_FEED_SIZE, "Feed")?;
+    Ok(FetchResponse {
+        bytes: body.len(),
+        fetched: Fetched::Modified(body, new_cache),
+        moved_to: moved_to.take(),
+        status,
+    })
+}
+
+/// GET a web page as text, following redirects. Returns the page's final
+/// URL along with it. Pages larger than `MAX_PAGE_SIZE` are an error.
+pub fn get_page(url: &str) -> Result<(String, String)> {
+    let client = Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(Policy::limited(MAX_REDIRECTS))
+        .build()?;
+    let resp = client.get(url).send()?.error_for_status()?;
+    let url = resp.url().to_string();
+    let body = read_body(resp, MAX_PAGE_SIZE, "Page")?;
+    Ok((url, String::from_utf8_lossy(&body).into_owned()))
+}
+
+/// POST a form, failing unless it's accepted.
+pub fn post_form(url: &str, form: &[(&str, &str)]) -> Result<()> {
+    let client = Client::builder()
+        .timeout(FETCH_TIMEOUT)
+        .redirect(Policy::limited(MAX_REDIRECTS))
+        .build()?;
+    client.post(url).form(form).send()?.error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::serve;
+
+    fn body(response: FetchResponse<Vec<u8>>) -> String {
+        match response.fetched {
+            Fetched::Modified(body, _) => String::from_utf8(body).unwrap(),
+            Fetched::NotModified => panic!("Expected a body"),
+        }
+    }
+
+    #[test]
+    fn permanent_redirect() {
+        let url = serve(&[
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n\r\nfeed",
+        ]);
+        let response = conditional_get(&url, &FetchCache::default()).unwrap();
+        assert_eq!(response.moved_to, Some(format!("{}new", url)));
+        assert_eq!(body(response), "feed");
+
+        // Temporary redirects along the way aren't a move, and the
+        // validators aren't kept for a URL they weren't sent to
+        let url = serve(&[
+            "HTTP/1.1 308 Permanent Redirect\r\nLocation: /new\r\n\r\n",
+            "HTTP/1.1 302 Found\r\nLocation: /newer\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nETag: \"1\"\r\n\r\nfeed",
+        ]);
+        let response = conditional_get(&url, &FetchCache::default()).unwrap();
+        assert_eq!(response.moved_to, None);
+        match response.fetched {
+            Fetched::Modified(_, cache) => {
+                assert_eq!(cache, FetchCache::default())
+            }
+            Fetched::NotModified => panic!("Expected a body"),
+        }
+
+        // The move's kept when fetching the new URL fails
+        let url = serve(&[
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\n\r\n",
+            "HTTP/1.1 500 Internal Server Error\r\n\r\n",
+        ]);
+        let e = conditional_get(&url, &FetchCache::default()).unwrap_err();
+        let moved = e.downcast_ref::<MovedError>().unwrap();
+        assert_eq!(moved.moved_to, format!("{}new", url));
+        assert!(moved.error.is::<reqwest::Error>());
+
+        // ...or parsing what it sent does
+        let url = serve(&[
+            "HTTP/1.1 301 Moved Permanently\r\nLocation: /new\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n\r\nnot a feed",
+        ]);
+        let e = conditional_get(&url, &FetchCache::default())
+            .unwrap()
+            .map::<(), _>(|_| Err("Not a feed".into()))
+            .unwrap_err();
+        assert!(e.is::<MovedError>());
+    }
+
+    #[test]
+    fn feed_size_limit() {
+        let large = format!(
+            "HTTP/1.1 200 OK\r\n\r\n{}",
+            "x".repeat(MAX_FEED_SIZE as usize + 1)
+        );
+        let url = serve(&[&large]);
+        let e = conditional_get(&url, &FetchCache::default()).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            format!("Feed is larger than {} bytes", MAX_FEED_SIZE)
+        );
+    }
+
+    #[test]
+    fn gone() {
+        let url = serve(&["HTTP/1.1 410 Gone\r\n\r\n"]);
+        let e = conditional_get(&url, &FetchCache::default()).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<StatusError>(),
+            Some(StatusError::Gone)
+        ));
+    }
+
+    #[test]
+    fn retry_after_status() {
+        let url = serve(&[
+            "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 120\r\n\r\n",
+            "HTTP/1.1 503 Service Unavailable\r\n\r\n",
+        ]);
+        let before = Timestamp::now();
+        let e = conditional_get(&url, &FetchCache::default()).unwrap_err();
+        match e.downcast_ref::<StatusError>() {
+            Some(StatusError::RetryAfter { status, at }) => {
+                assert_eq!(*status, 503);
+                assert!(*at >= before + time::Duration::seconds(120));
+                assert!(*at <= Timestamp::now() + time::Duration::seconds(120));
+            }
+            other => panic!("Expected a Retry-After, got {:?}", other),
+        }
+
+        // Without a Retry-After, it's an ordinary failure
+        let e = conditional_get(&url, &FetchCache::default()).unwrap_err();
+        assert!(e.is::<reqwest::Error>());
+    }
+
+    #[test]
+    fn parse_retry_after() {
+        let now = Timestamp(time::Timespec::new(1_000_000, 0));
+        assert_eq!(retry_after("120", now).map(|t| t.0.sec), Some(1_000_120));
+        assert_eq!(
+            retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now).map(|t| t.0.sec),
+            Some(1_445_412_480)
+        );
+        assert!(retry_after("soon", now).is_none());
+    }
+}
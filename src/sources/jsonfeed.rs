@@ -0,0 +1,404 @@
+// JSON Feed (https://jsonfeed.org/version/1.1) sources
+
+use crate::{
+    db::articles::{
+        Article, ArticleAuthor, ArticleAuthors, ArticleContent,
+        ArticleEnclosure, ArticleEnclosures, ArticleLink, ArticleLinks,
+        ContentType,
+    },
+    sanitize::sanitize_article,
+    sources::{
+        http::{self, FetchCache, FetchResponse},
+        media,
+        rssatom::{
+            fallback_id, unique_in_source, FeedFormat, ParsedFeed, SourceData,
+        },
+    },
+    timestamp::Timestamp,
+    Result,
+};
+
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt};
+use uuid::Uuid;
+
+/// Every JSON Feed's `version` starts with this.
+const VERSION_PREFIX: &str = "https://jsonfeed.org/version/";
+
+#[derive(Deserialize, Debug)]
+struct Feed {
+    version: String,
+    title: Option<String>,
+    home_page_url: Option<String>,
+    #[serde(default)]
+    authors: Vec<Author>,
+    /// JSON Feed 1.0's single author
+    author: Option<Author>,
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Item {
+    /// Should be a string, but some feeds use numbers
+    id: serde_json::Value,
+    url: Option<String>,
+    external_url: Option<String>,
+    title: Option<String>,
+    content_html: Option<String>,
+    content_text: Option<String>,
+    summary: Option<String>,
+    image: Option<String>,
+    banner_image: Option<String>,
+    date_published: Option<String>,
+    #[serde(default)]
+    authors: Vec<Author>,
+    author: Option<Author>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Author {
+    name: Option<String>,
+    url: Option<String>,
+    avatar: Option<String>,
+}
+
+/// Both fields are required by the spec, but feeds leave them out. Those
+/// without a `url` are skipped.
+#[derive(Deserialize, Debug)]
+struct Attachment {
+    url: Option<String>,
+    mime_type: Option<String>,
+    size_in_bytes: Option<u64>,
+    duration_in_seconds: Option<f64>,
+}
+
+#[derive(Debug)]
+pub struct JSONFeedError(String);
+
+impl fmt::Display for JSONFeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JSON Feed: ({})", self.0)
+    }
+}
+
+impl Error for JSONFeedError {}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JSONFeed {
+    url: String,
+    source_id: Uuid,
+}
+
+impl SourceData for JSONFeed {
+    fn fetch(&self, cache: &FetchCache) -> Result<FetchResponse<ParsedFeed>> {
+        http::conditional_get(&self.url, cache)?.map(|resp| self.parse(&resp))
+    }
+
+    fn unique(
+        &self,
+        articles: &mut Vec<Article>,
+        conn: &PgConnection,
+    ) -> Result<()> {
+        unique_in_source(self.source_id, articles, conn)
+    }
+}
+
+impl JSONFeed {
+    pub fn new(url: String, source_id: Uuid) -> JSONFeed {
+        JSONFeed { url, source_id }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn set_url(&mut self, url: String) {
+        self.url = url;
+    }
+
+    pub fn parse(&self, resp: &[u8]) -> Result<ParsedFeed> {
+        let feed: Feed = serde_json::from_slice(resp)
+            .map_err(|e| JSONFeedError(e.to_string()))?;
+        if !feed.version.starts_with(VERSION_PREFIX) {
+            return Err(Box::new(JSONFeedError(format!(
+                "Unknown version {}",
+                feed.version
+            ))));
+        }
+
+        // Items without authors inherit the feed's
+        let feed_authors = authors(feed.authors, feed.author);
+        Ok(ParsedFeed {
+            format: FeedFormat::JsonFeed,
+            title: feed.title,
+            link: feed.home_page_url,
+            articles: feed
+                .items
+                .into_iter()
+                .map(|item| {
+                    let mut article = JSONFeed::item_to_article(
+                        item,
+                        &feed_authors,
+                        self.source_id,
+                    );
+                    sanitize_article(
+                        &mut article,
+                        &self.url,
+                        ContentType::Text,
+                    );
+                    article
+                })
+                .collect(),
+            ttl: None,
+            // Pushed updates are only taken for RSS & Atom feeds
+            hub: None,
+        })
+    }
+
+    fn item_to_article(
+        item: Item,
+        feed_authors: &[Author],
+        source_id: Uuid,
+    ) -> Article {
+        let published = item.date_published.as_ref().and_then(|date| {
+            match chrono::DateTime::parse_from_rfc3339(date) {
+                Ok(datetime) => Some(Timestamp(time::Timespec {
+                    sec: datetime.timestamp(),
+                    nsec: 0,
+                })),
+                Err(_) => {
+                    log::debug!(
+                        "Could not parse from source {} as date: {}",
+                        source_id,
+                        date
+                    );
+                    None
+                }
+            }
+        });
+
+        let content = match (item.content_html, item.content_text) {
+            (Some(html), _) => {
+                ArticleContent::new(ContentType::Html, Some(html))
+            }
+            (None, Some(text)) => {
+                ArticleContent::new(ContentType::Text, Some(text))
+            }
+            (None, None) => ArticleContent::new(ContentType::Html, None),
+        };
+
+        let mut item_authors = authors(item.authors, item.author);
+        if item_authors.is_empty() {
+            item_authors = feed_authors.to_vec();
+        }
+
+        let mut article = Article {
+            id: Uuid::new_v4(),
+            title: item.title,
+            published,
+            source_info: serde_json::json!([]),
+            summary: item.summary,
+            content,
+            rights: None,
+            links: ArticleLinks(
+                item.url
+                    .map(ArticleLink::new)
+                    .into_iter()
+                    .chain(item.external_url.map(|url| ArticleLink {
+                        relationship: Some("related".to_string()),
+                        ..ArticleLink::new(url)
+                    }))
+                    .collect(),
+            ),
+            authors: ArticleAuthors(
+                item_authors
+                    .into_iter()
+                    .map(|author| ArticleAuthor {
+                        name: author.name,
+                        email: None,
+                        url: author.url,
+                        avatar: author.avatar,
+                    })
+                    .collect(),
+            ),
+            categories: serde_json::to_value(item.tags)
+                .unwrap_or_else(|_| serde_json::json!([])),
+            comments_url: None,
+            extensions: serde_json::json!({}),
+            source: source_id,
+            id_from_source: match item.id {
+                serde_json::Value::String(id) => Some(id),
+                serde_json::Value::Null => None,
+                id => Some(id.to_string()),
+            }
+            .filter(|id| !id.trim().is_empty()),
+            raw_summary: None,
+            raw_content: None,
+            enclosures: ArticleEnclosures(
+                item.attachments
+                    .into_iter()
+                    .filter_map(|attachment| {
+                        Some(ArticleEnclosure {
+                            url: attachment
+                                .url
+                                .filter(|url| !url.trim().is_empty())?,
+                            mime_type: attachment
+                                .mime_type
+                                .filter(|t| !t.trim().is_empty()),
+                            length: attachment.size_in_bytes.map(|l| l as i64),
+                            duration: attachment
+                                .duration_in_seconds
+                                .map(|d| d.round() as i32),
+                        })
+                    })
+                    .collect(),
+            ),
+            thumbnail: item.image.or(item.banner_image),
+            duration: None,
+            full_content: None,
+        };
+        if article.id_from_source.is_none() {
+            article.id_from_source = Some(fallback_id(&article));
+        }
+        media::extract(&mut article);
+        article
+    }
+}
+
+/// JSON Feed 1.1's `authors`, falling back to 1.0's `author`.
+fn authors(authors: Vec<Author>, author: Option<Author>) -> Vec<Author> {
+    if authors.is_empty() {
+        author.into_iter().collect()
+    } else {
+        authors
+    }
+}
+
+/// Whether a response body looks like a JSON Feed.
+pub fn is_json_feed(resp: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(resp)
+        .ok()
+        .and_then(|feed| {
+            feed["version"]
+                .as_str()
+                .map(|version| version.starts_with(VERSION_PREFIX))
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs::File, io::Read};
+
+    #[test]
+    fn parse_example_json_feed() {
+        let feed = JSONFeed {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+
+        let mut file = File::open("test_data/test_jsonfeed.json").unwrap();
+        let mut file_contents = Vec::new();
+        file.read_to_end(&mut file_contents).unwrap();
+        assert!(is_json_feed(&file_contents));
+
+        let articles = feed.parse(&file_contents).unwrap().articles;
+        assert_eq!(articles.len(), 2);
+
+        let first = &articles[0];
+        assert_eq!(first.source, feed.source_id);
+        assert_eq!(first.id_from_source.as_deref(), Some("2"));
+        assert_eq!(first.title.as_deref(), Some("Second post"));
+        assert_eq!(first.published.map(|p| p.0.sec), Some(1_597_930_200));
+        assert_eq!(first.content.content_type, ContentType::Html);
+        assert_eq!(first.authors.0[0].name.as_deref(), Some("Item Author"));
+        assert_eq!(first.categories, serde_json::json!(["rust", "feeds"]));
+        assert_eq!(
+            first.enclosures.0[0].mime_type.as_deref(),
+            Some("audio/mpeg")
+        );
+        assert_eq!(first.enclosures.0[0].length, Some(1_048_576));
+        assert_eq!(first.duration, Some(1800));
+        assert_eq!(
+            first.thumbnail.as_deref(),
+            Some("https://example.org/episode-1.png")
+        );
+
+        let second = &articles[1];
+        assert_eq!(second.id_from_source.as_deref(), Some("1"));
+        assert_eq!(second.content.content_type, ContentType::Text);
+        // Inherited from the feed
+        assert_eq!(second.authors.0[0].name.as_deref(), Some("Feed Author"));
+        assert!(second.published.is_none());
+    }
+
+    #[test]
+    fn parse_attachments() {
+        let feed = JSONFeed {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+        let articles = feed
+            .parse(
+                br#"{
+                    "version": "https://jsonfeed.org/version/1.1",
+                    "items": [{
+                        "id": "1",
+                        "content_text": "Hi",
+                        "attachments": [
+                            {"url": "https://example.org/a.mp3"},
+                            {"mime_type": "audio/mpeg"},
+                            {"url": " ", "mime_type": "audio/mpeg"}
+                        ]
+                    }]
+                }"#,
+            )
+            .unwrap()
+            .articles;
+        let enclosures = &articles[0].enclosures.0;
+        assert_eq!(enclosures.len(), 1);
+        assert_eq!(enclosures[0].url, "https://example.org/a.mp3");
+        assert!(enclosures[0].mime_type.is_none());
+    }
+
+    #[test]
+    fn escape_summaries() {
+        let feed = JSONFeed {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+        let articles = feed
+            .parse(
+                br#"{
+                    "version": "https://jsonfeed.org/version/1.1",
+                    "items": [{
+                        "id": "1",
+                        "content_text": "Hi",
+                        "summary": "<script>alert(1)</script>"
+                    }]
+                }"#,
+            )
+            .unwrap()
+            .articles;
+        assert_eq!(
+            articles[0].summary.as_deref(),
+            Some("&lt;script&gt;alert(1)&lt;/script&gt;")
+        );
+    }
+
+    #[test]
+    fn detect_json_feed() {
+        assert!(!is_json_feed(b"<rss version=\"2.0\"></rss>"));
+        assert!(!is_json_feed(b"{\"version\": \"1.1\"}"));
+        assert!(is_json_feed(
+            b"{\"version\": \"https://jsonfeed.org/version/1\", \"items\": []}"
+        ));
+    }
+}
@@ -0,0 +1,231 @@
+// Media attached to articles: enclosures, thumbnails & podcast durations.
+//
+// RSS `<enclosure>`s, Atom `rel="enclosure"` links & JSON Feed attachments
+// are read by each source. This fills in the rest from feed extensions,
+// Media RSS (`media:content`, `media:thumbnail`) & iTunes, as serialized by
+// the feed crates: `{prefix: {name: [{name, value, attrs, children}]}}`.
+
+use crate::db::articles::{Article, ArticleEnclosure};
+use serde_json::Value;
+
+/// Seconds, from an iTunes-style `HH:MM:SS`, `MM:SS` or plain seconds.
+pub fn parse_duration(duration: &str) -> Option<i32> {
+    let parts: Vec<&str> = duration.trim().split(':').collect();
+    if parts.len() > 3 {
+        return None;
+    }
+    let mut seconds = 0.0;
+    for part in parts {
+        let part: f64 = part.trim().parse().ok()?;
+        if part < 0.0 {
+            return None;
+        }
+        seconds = seconds * 60.0 + part;
+    }
+    Some(seconds.round() as i32).filter(|s| *s > 0)
+}
+
+/// Only web URLs are kept, since clients put these in `src`s.
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim().to_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Extension elements named `name`, ex: `media:content`.
+fn elements<'a>(
+    extensions: &'a Value,
+    name: &str,
+) -> impl Iterator<Item = &'a Value> {
+    extensions[name].as_array().into_iter().flatten()
+}
+
+fn attr<'a>(element: &'a Value, name: &str) -> Option<&'a str> {
+    element["attrs"][name]
+        .as_str()
+        .filter(|v| !v.trim().is_empty())
+}
+
+/// `media:*` elements named `name`, including those in a `media:group` or
+/// nested in `media:content`.
+fn media_elements<'a>(media: &'a Value, name: &'a str) -> Vec<&'a Value> {
+    let groups: Vec<&Value> = elements(media, "group")
+        .map(|group| &group["children"])
+        .collect();
+    let parents = Some(media).into_iter().chain(groups);
+    let mut found = Vec::new();
+    for parent in parents {
+        found.extend(elements(parent, name));
+        for content in elements(parent, "content") {
+            found.extend(elements(&content["children"], name));
+        }
+    }
+    found
+}
+
+fn is_image(content: &Value) -> bool {
+    attr(content, "medium") == Some("image")
+        || attr(content, "type")
+            .map(|t| t.starts_with("image/"))
+            .unwrap_or(false)
+}
+
+/// Add media from an article's extensions, without replacing any the feed
+/// gave directly, then drop media that isn't at a web URL.
+pub fn extract(article: &mut Article) {
+    let media = &article.extensions["media"];
+    let itunes = &article.extensions["itunes"];
+
+    let mut thumbnails: Vec<String> = media_elements(media, "thumbnail")
+        .into_iter()
+        .filter_map(|thumbnail| attr(thumbnail, "url"))
+        .chain(elements(itunes, "image").filter_map(|i| attr(i, "href")))
+        .map(|url| url.to_string())
+        .collect();
+    let mut enclosures = Vec::new();
+    for content in media_elements(media, "content") {
+        let url = match attr(content, "url") {
+            Some(url) => url.to_string(),
+            None => continue,
+        };
+        // Images are often the article's picture, not an attachment
+        if is_image(content) {
+            thumbnails.push(url);
+            continue;
+        }
+        enclosures.push(ArticleEnclosure {
+            url,
+            mime_type: attr(content, "type").map(|t| t.to_string()),
+            length: attr(content, "fileSize").and_then(|l| l.parse().ok()),
+            duration: attr(content, "duration").and_then(parse_duration),
+        });
+    }
+    let itunes_duration = elements(itunes, "duration")
+        .find_map(|d| d["value"].as_str())
+        .and_then(parse_duration);
+
+    for enclosure in enclosures {
+        if !article.enclosures.0.iter().any(|e| e.url == enclosure.url) {
+            article.enclosures.0.push(enclosure);
+        }
+    }
+    article.enclosures.0.retain(|e| is_web_url(&e.url));
+    // Falling back to an attached image
+    let image_enclosure = article
+        .enclosures
+        .0
+        .iter()
+        .find(|e| {
+            e.mime_type
+                .as_deref()
+                .map(|t| t.starts_with("image/"))
+                .unwrap_or(false)
+        })
+        .map(|e| e.url.clone());
+    article.thumbnail = article
+        .thumbnail
+        .take()
+        .into_iter()
+        .chain(thumbnails)
+        .find(|url| is_web_url(url))
+        .or(image_enclosure);
+    article.duration = article
+        .duration
+        .or(itunes_duration)
+        .or_else(|| article.enclosures.0.iter().find_map(|e| e.duration));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn element(attrs: Value, children: Value) -> Value {
+        serde_json::json!({
+            "name": "", "value": null, "attrs": attrs, "children": children
+        })
+    }
+
+    fn article(extensions: Value) -> Article {
+        Article {
+            extensions,
+            ..testing::article()
+        }
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(90));
+        assert_eq!(parse_duration("01:30"), Some(90));
+        assert_eq!(parse_duration(" 1:02:03 "), Some(3723));
+        assert_eq!(parse_duration("12.6"), Some(13));
+        assert_eq!(parse_duration("0"), None);
+        assert_eq!(parse_duration("1:2:3:4"), None);
+        assert_eq!(parse_duration("-5"), None);
+        assert_eq!(parse_duration("an hour"), None);
+    }
+
+    #[test]
+    fn media_rss_group() {
+        // As in a YouTube feed
+        let mut article = article(serde_json::json!({
+            "media": {"group": [element(serde_json::json!({}), serde_json::json!({
+                "content": [element(serde_json::json!({
+                    "url": "https://www.youtube.com/v/abc",
+                    "type": "application/x-shockwave-flash"
+                }), serde_json::json!({}))],
+                "thumbnail": [element(serde_json::json!({
+                    "url": "https://i.ytimg.com/vi/abc/hqdefault.jpg"
+                }), serde_json::json!({}))]
+            }))]}
+        }));
+        extract(&mut article);
+        assert_eq!(
+            article.thumbnail.as_deref(),
+            Some("https://i.ytimg.com/vi/abc/hqdefault.jpg")
+        );
+        assert_eq!(article.enclosures.0.len(), 1);
+        assert_eq!(
+            article.enclosures.0[0].url,
+            "https://www.youtube.com/v/abc"
+        );
+    }
+
+    #[test]
+    fn feed_media_wins() {
+        let mut article = article(serde_json::json!({
+            "media": {"content": [
+                element(serde_json::json!({
+                    "url": "https://example.org/ep.mp3",
+                    "type": "audio/mpeg",
+                    "fileSize": "1000",
+                    "duration": "60"
+                }), serde_json::json!({})),
+                element(serde_json::json!({
+                    "url": "https://example.org/cover.jpg",
+                    "medium": "image"
+                }), serde_json::json!({})),
+                element(serde_json::json!({
+                    "url": "javascript:alert(1)"
+                }), serde_json::json!({}))
+            ]},
+            "itunes": {"duration": [{
+                "name": "itunes:duration", "value": "2:00",
+                "attrs": {}, "children": {}
+            }]}
+        }));
+        article.enclosures.0.push(ArticleEnclosure {
+            mime_type: Some("audio/mpeg".into()),
+            ..ArticleEnclosure::new("https://example.org/ep.mp3".into())
+        });
+        article.thumbnail = Some("data:image/png;base64,AAAA".into());
+        extract(&mut article);
+
+        assert_eq!(article.enclosures.0.len(), 1);
+        assert_eq!(article.enclosures.0[0].length, None);
+        assert_eq!(
+            article.thumbnail.as_deref(),
+            Some("https://example.org/cover.jpg")
+        );
+        assert_eq!(article.duration, Some(120));
+    }
+}
@@ -1,21 +1,66 @@
-#![allow(clippy::eval_order_dependence)]
+#![allow(clippy::mixed_read_write_in_expression)]
 use crate::{
-    db::articles::{Article, ArticleSource},
+    db::articles::{
+        Article, ArticleAuthor, ArticleAuthors, ArticleContent,
+        ArticleEnclosure, ArticleEnclosures, ArticleLink, ArticleLinks,
+        ArticleSource, ContentType,
+    },
+    sanitize::sanitize_article,
     schema::articles,
+    sources::{
+        http::{self, FetchCache, FetchResponse},
+        media,
+    },
+    timestamp::Timestamp,
     Result,
 };
 
-use diesel::prelude::*;
+use diesel::{dsl::sql, prelude::*, sql_types};
+use quick_xml::{events::Event, Reader};
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 use std::{error::Error, fmt, io::BufReader};
 use uuid::Uuid;
 
+/// Kinds of feed documents.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedFormat {
+    Rss,
+    Atom,
+    JsonFeed,
+}
+
+/// Everything parsed from a fetched feed.
+#[derive(Debug)]
+pub struct ParsedFeed {
+    pub format: FeedFormat,
+    pub title: Option<String>,
+    /// The site the feed is for
+    pub link: Option<String>,
+    pub articles: Vec<Article>,
+    /// Minutes between updates the feed advertises (`<ttl>`,
+    /// `sy:updatePeriod`)
+    pub ttl: Option<i32>,
+    /// Where updates are pushed from, if the feed uses WebSub
+    pub hub: Option<HubLinks>,
+}
+
+/// A feed's WebSub hub (`<link rel="hub">`), along with the URL the hub
+/// knows it by (`<link rel="self">`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct HubLinks {
+    pub hub: String,
+    pub topic: Option<String>,
+}
+
 /// Methods specific to a kind of source (ex: RSS)
 pub trait SourceData {
-    // Pull available articles from a source.
-    fn fetch(&self) -> Result<Vec<Article>>;
+    // Pull available articles from a source, unless it hasn't changed
+    // since `cache` was stored.
+    fn fetch(&self, cache: &FetchCache) -> Result<FetchResponse<ParsedFeed>>;
     // Remove articles from a list that already exist in the db.
     fn unique(
         &self,
@@ -28,6 +73,8 @@ pub trait SourceData {
 pub struct RSSFetchError {
     rss_error: rss::Error,
     atom_error: atom_syndication::Error,
+    /// Whether the document's root element is Atom's `<feed>`
+    atom: bool,
 }
 
 impl fmt::Display for RSSFetchError {
@@ -36,6 +83,13 @@ impl fmt::Display for RSSFetchError {
     }
 }
 
+impl RSSFetchError {
+    /// Whether the response looked like an Atom feed, rather than RSS.
+    pub fn is_atom(&self) -> bool {
+        self.atom
+    }
+}
+
 impl Error for RSSFetchError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         self.rss_error.source()
@@ -49,10 +103,8 @@ pub struct RSSAtom {
 }
 
 impl SourceData for RSSAtom {
-    fn fetch(&self) -> Result<Vec<Article>> {
-        let resp = reqwest::blocking::get(&self.url).and_then(|r| r.text())?;
-
-        self.parse(resp.as_bytes())
+    fn fetch(&self, cache: &FetchCache) -> Result<FetchResponse<ParsedFeed>> {
+        http::conditional_get(&self.url, cache)?.map(|resp| self.parse(&resp))
     }
 
     fn unique(
@@ -60,77 +112,51 @@ impl SourceData for RSSAtom {
         articles: &mut Vec<Article>,
         conn: &PgConnection,
     ) -> Result<()> {
-        // http://www.詹姆斯.com/blog/2006/08/rss-dup-detection
-        // Using this heirarchy: GUID -> link -> Title -> Desc -> Content
-        //  Pick at least 2 that aren't empty, and filter for those.
-
-        let mut indexes_to_keep: Vec<bool> = Vec::with_capacity(articles.len());
-
-        for article in articles.iter() {
-            let mut query = articles::table
-                .select(articles::id)
-                .filter(articles::source.eq(&self.source_id))
-                .into_boxed();
-            let mut filters = 0;
-            if let Some(id) = &article.id_from_source {
-                query = query.filter(articles::id_from_source.eq(id));
-                filters += 1;
-            };
-
-            let deserialized_links: std::result::Result<
-                Vec<String>,
-                serde_json::Error,
-            > = serde_json::from_value(article.links.to_owned());
-            if let Ok(links) = deserialized_links {
-                if !links.is_empty() {
-                    query = query
-                        .filter(articles::links.eq(article.links.to_owned()));
-                    filters += 1;
-                }
-            };
+        unique_in_source(self.source_id, articles, conn)
+    }
+}
 
-            if filters < 2 {
-                if let Some(title) = &article.title {
-                    query = query.filter(articles::title.eq(title));
-                    filters += 1;
-                };
-            }
+impl RSSAtom {
+    pub fn new(url: String, source_id: Uuid) -> RSSAtom {
+        RSSAtom { url, source_id }
+    }
 
-            if filters < 2 {
-                if let Some(summary) = &article.summary {
-                    query = query.filter(articles::summary.eq(summary));
-                    filters += 1;
-                };
-            }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
 
-            if filters < 2 {
-                query = query
-                    .filter(articles::content.eq(article.content.to_owned()));
-            }
+    pub fn set_url(&mut self, url: String) {
+        self.url = url;
+    }
 
-            let similar_articles: Vec<Uuid> = query.load(conn)?;
-            indexes_to_keep.push(similar_articles.is_empty());
+    /// Parse an RSS or Atom feed, sanitizing its articles' HTML.
+    pub fn parse(&self, resp: &[u8]) -> Result<ParsedFeed> {
+        let mut feed = self.parse_unsanitized(resp)?;
+        for article in &mut feed.articles {
+            sanitize_article(article, &self.url, ContentType::Html);
         }
-
-        let mut i = 0;
-        articles.retain(|_| (indexes_to_keep[i], i += 1).0);
-
-        Ok(())
+        Ok(feed)
     }
-}
 
-impl RSSAtom {
-    fn parse(&self, resp: &[u8]) -> Result<Vec<Article>> {
+    fn parse_unsanitized(&self, resp: &[u8]) -> Result<ParsedFeed> {
         let rss_err = match rss::Channel::read_from(BufReader::new(resp)) {
             Err(e) => e,
             Ok(channel) => {
-                return Ok(channel
-                    .into_items()
-                    .into_iter()
-                    .map(|item| {
-                        RSSAtom::rss_item_to_article(&item, self.source_id)
-                    })
-                    .collect())
+                return Ok(ParsedFeed {
+                    format: FeedFormat::Rss,
+                    title: Some(channel.title().to_string()),
+                    link: Some(channel.link().to_string())
+                        .filter(|link| !link.is_empty()),
+                    articles: channel
+                        .items()
+                        .iter()
+                        .map(|item| {
+                            RSSAtom::rss_item_to_article(item, self.source_id)
+                        })
+                        .collect(),
+                    ttl: update_hint(resp),
+                    hub: hub_links(resp),
+                });
             }
         };
 
@@ -138,33 +164,48 @@ impl RSSAtom {
             match atom_syndication::Feed::read_from(BufReader::new(resp)) {
                 Err(e) => e,
                 Ok(feed) => {
-                    return Ok(feed
-                        .entries
-                        .into_iter()
-                        .map(|entry| {
-                            RSSAtom::atom_entry_to_article(
-                                &entry,
-                                self.source_id,
-                            )
-                        })
-                        .collect())
+                    return Ok(ParsedFeed {
+                        format: FeedFormat::Atom,
+                        title: Some(feed.title().to_string()),
+                        link: feed
+                            .links()
+                            .iter()
+                            .find(|link| link.rel() == "alternate")
+                            .map(|link| link.href().to_string()),
+                        articles: feed
+                            .entries()
+                            .iter()
+                            .map(|entry| {
+                                RSSAtom::atom_entry_to_article(
+                                    entry,
+                                    self.source_id,
+                                )
+                            })
+                            .collect(),
+                        ttl: update_hint(resp),
+                        hub: hub_links(resp),
+                    });
                 }
             };
 
         Err(Box::new(RSSFetchError {
             rss_error: rss_err,
             atom_error: atom_err,
+            atom: root_element(resp).map_or(false, |root| root == b"feed"),
         }))
     }
 
     fn rss_item_to_article(item: &rss::Item, source_id: Uuid) -> Article {
         let ts = item
             .pub_date()
-            .map(|s| rfc822_sanitizer::parse_from_rfc2822_with_fallback(s).ok())
-            .flatten()
-            .map(|datetime| time::Timespec {
-                sec: datetime.timestamp(),
-                nsec: 0,
+            .and_then(|s| {
+                rfc822_sanitizer::parse_from_rfc2822_with_fallback(s).ok()
+            })
+            .map(|datetime| {
+                Timestamp(time::Timespec {
+                    sec: datetime.timestamp(),
+                    nsec: 0,
+                })
             });
         // Did we get a date, but not a result?
         if let (Some(date), None) = (item.pub_date(), ts) {
@@ -177,7 +218,7 @@ impl RSSAtom {
 
         let source_info = match item
             .source()
-            .map(|source| RSSAtom::rss_source_to_article_source(source))
+            .map(RSSAtom::rss_source_to_article_source)
             .map(serde_json::to_value)
         {
             Some(Ok(v)) => v,
@@ -188,22 +229,24 @@ impl RSSAtom {
             None => serde_json::json!([]),
         };
 
-        Article {
+        let itunes = item.itunes_ext();
+        let mut article = Article {
             id: Uuid::new_v4(),
             title: item.title().map(|s| s.to_string()),
             published: ts,
             source_info,
             summary: None,
-            content: serde_json::json!({"value": item.content().map(|s| s.to_string())}),
+            content: ArticleContent::new(
+                ContentType::Html,
+                item.content().map(|s| s.to_string()),
+            ),
             rights: None,
-            links: serde_json::to_value(opt_to_vector(
-                item.link().map(|s| s.to_string()),
-            ))
-            .unwrap(),
-            authors: serde_json::to_value(opt_to_vector(
-                item.author().map(|s| s.to_string()),
-            ))
-            .unwrap(),
+            links: ArticleLinks(opt_to_vector(
+                item.link().map(|url| ArticleLink::new(url.to_string())),
+            )),
+            authors: ArticleAuthors(opt_to_vector(
+                item.author().map(ArticleAuthor::from_rss),
+            )),
             // name -> term
             // domain -> scheme
             categories: item
@@ -217,39 +260,123 @@ impl RSSAtom {
                 .unwrap_or_else(|_| serde_json::json!({})),
             source: source_id,
             id_from_source: item.guid().map(|guid| guid.value().to_string()),
-        }
+            raw_summary: None,
+            raw_content: None,
+            enclosures: ArticleEnclosures(opt_to_vector(item.enclosure().map(
+                |enclosure| {
+                    ArticleEnclosure {
+                        mime_type: Some(enclosure.mime_type().to_string())
+                            .filter(|t| !t.is_empty()),
+                        // Often 0 when unknown
+                        length: enclosure
+                            .length()
+                            .trim()
+                            .parse()
+                            .ok()
+                            .filter(|l| *l > 0),
+                        ..ArticleEnclosure::new(enclosure.url().to_string())
+                    }
+                },
+            ))),
+            thumbnail: itunes
+                .and_then(|itunes| itunes.image())
+                .map(|s| s.to_string()),
+            duration: itunes
+                .and_then(|itunes| itunes.duration())
+                .and_then(media::parse_duration),
+            full_content: None,
+        };
+        media::extract(&mut article);
+        article
     }
 
     fn atom_entry_to_article(
         entry: &atom_syndication::Entry,
         source_id: Uuid,
     ) -> Article {
-        Article {
+        let mut article = Article {
             id: Uuid::new_v4(),
             title: Some(entry.title().to_string()),
-            published: entry.published().map(|datetime| time::Timespec {
-                sec: datetime.timestamp(),
-                nsec: 0,
+            published: entry.published().map(|datetime| {
+                Timestamp(time::Timespec {
+                    sec: datetime.timestamp(),
+                    nsec: 0,
+                })
             }),
             // TODO serialize Source
             source_info: serde_json::to_value(opt_to_vector(entry.source()))
                 .unwrap(),
             summary: entry.summary().map(|s| s.to_string()),
-            content: serde_json::to_value(entry.content())
-                .unwrap_or_else(|_| serde_json::json!({})),
+            content: match entry.content() {
+                Some(content) => ArticleContent {
+                    content_type: ContentType::from_atom(
+                        content.content_type(),
+                    ),
+                    value: content.value().map(|s| s.to_string()),
+                    src: content.src().map(|s| s.to_string()),
+                    // atom_syndication doesn't expose the content's
+                    // `xml:lang` or `xml:base`
+                    lang: None,
+                    base: None,
+                },
+                None => ArticleContent::new(ContentType::Text, None),
+            },
             rights: entry.rights().map(|s| s.to_string()),
-            links: serde_json::to_value(&entry.links)
-                .unwrap_or_else(|_| serde_json::json!([])),
-            authors: serde_json::to_value(&entry.authors)
-                .unwrap_or_else(|_| serde_json::json!([])),
+            links: ArticleLinks(
+                entry
+                    .links()
+                    .iter()
+                    .map(|link| ArticleLink {
+                        url: link.href().to_string(),
+                        relationship: Some(link.rel().to_string()),
+                        title: link.title().map(|s| s.to_string()),
+                        mime_type: link.mime_type().map(|s| s.to_string()),
+                    })
+                    .collect(),
+            ),
+            authors: ArticleAuthors(
+                entry
+                    .authors()
+                    .iter()
+                    .map(|person| ArticleAuthor {
+                        name: Some(person.name().to_string()),
+                        email: person.email().map(|s| s.to_string()),
+                        url: person.uri().map(|s| s.to_string()),
+                        avatar: None,
+                    })
+                    .collect(),
+            ),
             categories: serde_json::to_value(&entry.categories)
                 .unwrap_or_else(|_| serde_json::json!([])),
             comments_url: None,
             extensions: serde_json::to_value(entry.extensions())
                 .unwrap_or_else(|_| serde_json::json!({})),
             source: source_id,
-            id_from_source: Some(entry.id.to_owned()),
+            id_from_source: Some(entry.id.trim().to_owned())
+                .filter(|id| !id.is_empty()),
+            raw_summary: None,
+            raw_content: None,
+            enclosures: ArticleEnclosures(
+                entry
+                    .links()
+                    .iter()
+                    .filter(|link| link.rel() == "enclosure")
+                    .map(|link| ArticleEnclosure {
+                        mime_type: link.mime_type().map(|s| s.to_string()),
+                        length: link.length().and_then(|l| l.parse().ok()),
+                        ..ArticleEnclosure::new(link.href().to_string())
+                    })
+                    .collect(),
+            ),
+            thumbnail: None,
+            duration: None,
+            full_content: None,
+        };
+        if article.id_from_source.is_none() {
+            article.id_from_source = Some(fallback_id(&article));
         }
+        media::extract(&mut article);
+        article
     }
 
     fn rss_source_to_article_source(source: &rss::Source) -> ArticleSource {
@@ -260,6 +387,208 @@ impl RSSAtom {
     }
 }
 
+/// An id for an article its feed didn't give one: its link, or else a hash
+/// of its title, summary & content.
+pub fn fallback_id(article: &Article) -> String {
+    if let Some(url) = article.links.article_url() {
+        return url.to_string();
+    }
+    let mut hasher = Sha256::new();
+    for part in &[
+        &article.title,
+        &article.summary,
+        &article.content.value,
+        &article.content.src,
+    ] {
+        hasher.input(part.as_deref().unwrap_or_default());
+        hasher.input(b"\0");
+    }
+    format!("{:x}", hasher.result())
+}
+
+/// Local name of the document's root element, ex: `rss` or `feed`.
+fn root_element(resp: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = Reader::from_reader(resp);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                return Some(e.local_name().to_vec())
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => (),
+        }
+        buf.clear();
+    }
+}
+
+/// Minutes between feed updates, from an RSS `<ttl>` and/or the
+/// syndication module's `sy:updatePeriod` & `sy:updateFrequency`.
+///
+/// Read straight from the XML, since neither feed crate exposes all of
+/// these. When both are present, the longer interval is used.
+fn update_hint(resp: &[u8]) -> Option<i32> {
+    let mut reader = Reader::from_reader(resp);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let mut element = Vec::new();
+    let (mut ttl, mut period, mut frequency) = (None, None, None);
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) => {
+                element = e.local_name().to_vec();
+                // Only look at the channel/feed, not items/entries
+                if element == b"item" || element == b"entry" {
+                    break;
+                }
+            }
+            Ok(Event::Text(text)) => {
+                let text = text.unescape_and_decode(&reader).ok();
+                match element.as_slice() {
+                    b"ttl" => ttl = text,
+                    b"updatePeriod" => period = text,
+                    b"updateFrequency" => frequency = text,
+                    _ => (),
+                }
+            }
+            Ok(Event::End(_)) => element.clear(),
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    let ttl = ttl.and_then(|ttl| ttl.trim().parse::<i32>().ok());
+    let period = period.and_then(|period| match period.trim() {
+        "hourly" => Some(60),
+        "daily" => Some(24 * 60),
+        "weekly" => Some(7 * 24 * 60),
+        "monthly" => Some(30 * 24 * 60),
+        "yearly" => Some(365 * 24 * 60),
+        _ => None,
+    });
+    let frequency = frequency
+        .and_then(|f| f.trim().parse::<i32>().ok())
+        .filter(|f| *f > 0)
+        .unwrap_or(1);
+
+    ttl.into_iter()
+        .chain(period.map(|p| p / frequency))
+        .filter(|minutes| *minutes > 0)
+        .max()
+}
+
+/// The feed's WebSub hub, from `<link>`s (or `<atom:link>`s in RSS) on the
+/// channel/feed.
+fn hub_links(resp: &[u8]) -> Option<HubLinks> {
+    let mut reader = Reader::from_reader(resp);
+    reader.trim_text(true);
+    let mut buf = Vec::new();
+    let (mut hub, mut topic) = (None, None);
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => {
+                match e.local_name() {
+                    // Only look at the channel/feed, not items/entries
+                    b"item" | b"entry" => break,
+                    b"link" => {
+                        let (mut rel, mut href) = (None, None);
+                        for attr in e.attributes().filter_map(|a| a.ok()) {
+                            let value =
+                                attr.unescape_and_decode_value(&reader).ok();
+                            match attr.key {
+                                b"rel" => rel = value,
+                                b"href" => href = value,
+                                _ => (),
+                            }
+                        }
+                        match rel.as_deref() {
+                            Some("hub") => hub = hub.or(href),
+                            Some("self") => topic = topic.or(href),
+                            _ => (),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+
+    hub.map(|hub| HubLinks { hub, topic })
+}
+
+/// Remove articles from a list that already exist in the db for a source.
+pub fn unique_in_source(
+    source_id: Uuid,
+    articles: &mut Vec<Article>,
+    conn: &PgConnection,
+) -> Result<()> {
+    // http://www.詹姆斯.com/blog/2006/08/rss-dup-detection
+    // Using this heirarchy: GUID -> link -> Title -> Desc -> Content
+    //  Pick at least 2 that aren't empty, and filter for those.
+
+    let mut indexes_to_keep: Vec<bool> = Vec::with_capacity(articles.len());
+
+    for article in articles.iter() {
+        let mut query = articles::table
+            .select(articles::id)
+            .filter(articles::source.eq(source_id))
+            .into_boxed();
+        let mut filters = 0;
+        if let Some(id) = &article.id_from_source {
+            query = query.filter(articles::id_from_source.eq(id));
+            filters += 1;
+        };
+
+        // `json` has no equality operator, so compare as `jsonb`
+        if !article.links.0.is_empty() {
+            query = query.filter(
+                sql::<sql_types::Bool>("links::jsonb = ")
+                    .bind::<sql_types::Jsonb, _>(serde_json::to_value(
+                        &article.links,
+                    )?),
+            );
+            filters += 1;
+        };
+
+        if filters < 2 {
+            if let Some(title) = &article.title {
+                query = query.filter(articles::title.eq(title));
+                filters += 1;
+            };
+        }
+
+        if filters < 2 {
+            if let Some(summary) = &article.summary {
+                query = query.filter(articles::summary.eq(summary));
+                filters += 1;
+            };
+        }
+
+        if filters < 2 {
+            query = query.filter(
+                sql::<sql_types::Bool>("content::jsonb = ")
+                    .bind::<sql_types::Jsonb, _>(serde_json::to_value(
+                        &article.content,
+                    )?),
+            );
+        }
+
+        let similar_articles: Vec<Uuid> = query.load(conn)?;
+        indexes_to_keep.push(similar_articles.is_empty());
+    }
+
+    let mut i = 0;
+    articles.retain(|_| (indexes_to_keep[i], i += 1).0);
+
+    Ok(())
+}
+
 fn opt_to_vector<T>(o: Option<T>) -> Vec<T> {
     o.into_iter().collect::<Vec<T>>()
 }
@@ -279,13 +608,138 @@ mod tests {
         let mut file_contents = Vec::new();
         file.read_to_end(&mut file_contents).unwrap();
 
-        let articles = rss.parse(file_contents.as_slice()).unwrap();
+        let articles = rss.parse(file_contents.as_slice()).unwrap().articles;
 
         // let articles = good_rss.fetch().unwrap();
         for a in &articles {
             assert!(a.source == rss.source_id);
             println!("{:#?}", a);
         }
+
+        let first = &articles[0];
+        assert_eq!(first.content.content_type, ContentType::Html);
+        // The tracking pixel is gone, but kept in the original
+        let value = first.content.value.as_deref().unwrap();
+        assert!(value.starts_with("<p>Designation"));
+        assert!(value.contains("JG_111220_Coping_1.jpg"));
+        assert!(!value.contains("/~/i/638716532"));
+        assert!(first.raw_content.as_ref().unwrap()["value"]
+            .as_str()
+            .unwrap()
+            .contains("/~/i/638716532"));
+        let enclosure = &first.enclosures.0[0];
+        assert!(enclosure.url.ends_with("JG_111220_Coping_1.jpg"));
+        assert_eq!(enclosure.mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(enclosure.length, None);
+        assert_eq!(first.thumbnail.as_deref(), Some(enclosure.url.as_str()));
+        assert_eq!(
+            first.authors.0[0].name.as_deref(),
+            Some("Rochester Democrat and Chronicle")
+        );
+    }
+
+    #[test]
+    fn rss_author_formats() {
+        let author = ArticleAuthor::from_rss("lawyer@boyer.net (Lawyer Boyer)");
+        assert_eq!(author.email.as_deref(), Some("lawyer@boyer.net"));
+        assert_eq!(author.name.as_deref(), Some("Lawyer Boyer"));
+
+        let author = ArticleAuthor::from_rss(" Jane Doe ");
+        assert_eq!(author.email, None);
+        assert_eq!(author.name.as_deref(), Some("Jane Doe"));
+    }
+
+    #[test]
+    fn parse_update_hints() {
+        let rss = RSSAtom {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+        let feed = rss
+            .parse(
+                r#"<rss version="2.0"
+                    xmlns:sy="http://purl.org/rss/1.0/modules/syndication/">
+                  <channel>
+                    <title>t</title><link>l</link><description>d</description>
+                    <ttl>60</ttl>
+                    <sy:updatePeriod>daily</sy:updatePeriod>
+                    <sy:updateFrequency>4</sy:updateFrequency>
+                  </channel>
+                </rss>"#
+                    .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(feed.ttl, Some(6 * 60));
+
+        let hint = update_hint(
+            b"<rss><channel><ttl> 30 </ttl><item><ttl>5</ttl></item>\
+              </channel></rss>",
+        );
+        assert_eq!(hint, Some(30));
+    }
+
+    #[test]
+    fn parse_hub_links() {
+        let hub = hub_links(
+            br#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom">
+              <channel>
+                <title>t</title><link>https://example.org/</link>
+                <atom:link rel="hub" href="https://hub.example/"/>
+                <atom:link rel="self" href="https://example.org/rss"/>
+                <item><atom:link rel="hub" href="https://other.example/"/>
+                </item>
+              </channel>
+            </rss>"#,
+        );
+        assert_eq!(
+            hub,
+            Some(HubLinks {
+                hub: "https://hub.example/".into(),
+                topic: Some("https://example.org/rss".into()),
+            })
+        );
+
+        let hub = hub_links(
+            br#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <link rel="hub" href="https://hub.example/"></link>
+              <entry><link rel="self" href="https://example.org/1"/></entry>
+            </feed>"#,
+        );
+        assert_eq!(hub.unwrap().topic, None);
+        assert_eq!(hub_links(b"<feed><title>t</title></feed>"), None);
+    }
+
+    #[test]
+    fn atom_entries_without_ids() {
+        let atom = RSSAtom {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+        let feed = atom
+            .parse(
+                br#"<feed xmlns="http://www.w3.org/2005/Atom">
+                  <title>t</title>
+                  <entry><id>tag:example.org,2020:1</id><title>1</title></entry>
+                  <entry>
+                    <title>2</title>
+                    <link rel="enclosure" href="https://example.org/2.mp3"/>
+                    <link href="https://example.org/2"/>
+                  </entry>
+                  <entry><title>3</title></entry>
+                  <entry><title>4</title></entry>
+                </feed>"#,
+            )
+            .unwrap();
+        let ids: Vec<_> = feed
+            .articles
+            .iter()
+            .map(|a| a.id_from_source.clone().unwrap())
+            .collect();
+        assert_eq!(ids[0], "tag:example.org,2020:1");
+        assert_eq!(ids[1], "https://example.org/2");
+        // Hashes of their content
+        assert_eq!(ids[2].len(), 64);
+        assert_ne!(ids[2], ids[3]);
     }
 
     #[test]
@@ -294,7 +748,7 @@ mod tests {
             url: "".to_string(),
             source_id: Uuid::new_v4(),
         };
-        rss.fetch().unwrap_err();
+        rss.fetch(&FetchCache::default()).unwrap_err();
     }
 
     #[test]
@@ -306,4 +760,25 @@ mod tests {
         rss.parse("<html><body><p>Hello world!</p></body></html>".as_bytes())
             .unwrap_err();
     }
+
+    #[test]
+    fn parse_errors_by_format() {
+        let rss = RSSAtom {
+            url: "".to_string(),
+            source_id: Uuid::new_v4(),
+        };
+        let is_atom = |resp: &str| {
+            rss.parse(resp.as_bytes())
+                .unwrap_err()
+                .downcast_ref::<RSSFetchError>()
+                .unwrap()
+                .is_atom()
+        };
+        assert!(is_atom(
+            r#"<?xml version="1.0"?>
+            <feed xmlns="http://www.w3.org/2005/Atom"><entry>"#
+        ));
+        assert!(!is_atom(r#"<rss version="2.0"><channel>"#));
+        assert!(!is_atom("<html><body><p>Hello world!</p></body></html>"));
+    }
 }
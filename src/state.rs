@@ -1,3 +1,190 @@
 use rocket::config;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
 
 pub struct Environment(pub config::Environment);
+
+/// Refresh requests allowed per user, within `REFRESH_WINDOW`
+const REFRESH_LIMIT: usize = 10;
+/// Refresh requests allowed for the same source or tag, within
+/// `REFRESH_WINDOW`
+const TARGET_REFRESH_LIMIT: usize = 3;
+const REFRESH_WINDOW: Duration = Duration::from_secs(60);
+
+/// Recent refresh requests for each user, & each source or tag, to rate
+/// limit them.
+#[derive(Default)]
+pub struct RefreshLimiter(Mutex<RefreshRequests>);
+
+#[derive(Default)]
+struct RefreshRequests {
+    users: HashMap<String, VecDeque<Instant>>,
+    targets: HashMap<Uuid, VecDeque<Instant>>,
+}
+
+/// Forget requests from before the window, & keys with none left.
+fn forget_old<K: Eq + Hash>(
+    requests: &mut HashMap<K, VecDeque<Instant>>,
+    now: Instant,
+) {
+    requests.retain(|_, times| {
+        while let Some(time) = times.front() {
+            if now.duration_since(*time) < REFRESH_WINDOW {
+                break;
+            }
+            times.pop_front();
+        }
+        !times.is_empty()
+    });
+}
+
+impl RefreshLimiter {
+    /// Record a request from `username` to refresh `target` (a source or
+    /// tag), unless either has had too many recently. Returns whether the
+    /// request is allowed.
+    pub fn allow(&self, username: &str, target: Uuid) -> bool {
+        self.allow_at(username, target, Instant::now())
+    }
+
+    fn allow_at(&self, username: &str, target: Uuid, now: Instant) -> bool {
+        let mut requests = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        forget_old(&mut requests.users, now);
+        forget_old(&mut requests.targets, now);
+
+        let count =
+            |times: Option<&VecDeque<Instant>>| times.map_or(0, VecDeque::len);
+        if count(requests.users.get(username)) >= REFRESH_LIMIT
+            || count(requests.targets.get(&target)) >= TARGET_REFRESH_LIMIT
+        {
+            return false;
+        }
+        requests
+            .users
+            .entry(username.to_string())
+            .or_default()
+            .push_back(now);
+        requests.targets.entry(target).or_default().push_back(now);
+        true
+    }
+}
+
+/// How long verified HTTP Basic credentials are trusted for.
+const AUTH_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Recently verified HTTP Basic credentials, so clients that send them with
+/// every request don't wait on a bcrypt check each time. Entries are keyed
+/// by a hash of the password along with the user's stored password hash, so
+/// the password itself isn't kept, and changing it stops the old one
+/// working.
+#[derive(Default)]
+pub struct AuthCache(Mutex<HashMap<Vec<u8>, Instant>>);
+
+fn auth_key(password: &str, password_hash: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.input(password_hash.as_bytes());
+    hasher.input(b":");
+    hasher.input(password.as_bytes());
+    hasher.result().to_vec()
+}
+
+impl AuthCache {
+    /// Whether `password` was verified against `password_hash` recently.
+    pub fn verified(&self, password: &str, password_hash: &str) -> bool {
+        self.verified_at(password, password_hash, Instant::now())
+    }
+
+    fn verified_at(
+        &self,
+        password: &str,
+        password_hash: &str,
+        now: Instant,
+    ) -> bool {
+        let verified = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        verified
+            .get(&auth_key(password, password_hash))
+            .map_or(false, |at| now.duration_since(*at) < AUTH_CACHE_TTL)
+    }
+
+    /// Remember that `password` matches `password_hash`.
+    pub fn insert(&self, password: &str, password_hash: &str) {
+        self.insert_at(password, password_hash, Instant::now())
+    }
+
+    fn insert_at(&self, password: &str, password_hash: &str, now: Instant) {
+        let mut verified = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        verified.retain(|_, at| now.duration_since(*at) < AUTH_CACHE_TTL);
+        verified.insert(auth_key(password, password_hash), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_user_limit() {
+        let limiter = RefreshLimiter::default();
+        let now = Instant::now();
+        for _ in 0..REFRESH_LIMIT {
+            assert!(limiter.allow_at("a", Uuid::new_v4(), now));
+        }
+        assert!(!limiter.allow_at("a", Uuid::new_v4(), now));
+        // Other users have their own limit
+        assert!(limiter.allow_at("b", Uuid::new_v4(), now));
+    }
+
+    #[test]
+    fn per_target_limit() {
+        let limiter = RefreshLimiter::default();
+        let now = Instant::now();
+        let source = Uuid::new_v4();
+        for _ in 0..TARGET_REFRESH_LIMIT {
+            assert!(limiter.allow_at("a", source, now));
+        }
+        assert!(!limiter.allow_at("a", source, now));
+        assert!(!limiter.allow_at("b", source, now));
+        assert!(limiter.allow_at("a", Uuid::new_v4(), now));
+    }
+
+    #[test]
+    fn window_reset() {
+        let limiter = RefreshLimiter::default();
+        let start = Instant::now();
+        let source = Uuid::new_v4();
+        for _ in 0..TARGET_REFRESH_LIMIT {
+            assert!(limiter.allow_at("a", source, start));
+        }
+        let later = start + REFRESH_WINDOW / 2;
+        assert!(!limiter.allow_at("a", source, later));
+
+        // Requests are forgotten a window after they're made
+        let after = start + REFRESH_WINDOW;
+        assert!(limiter.allow_at("a", source, after));
+        assert_eq!(limiter.0.lock().unwrap().targets[&source].len(), 1);
+        // Along with users that haven't made any since
+        assert!(limiter.allow_at("b", Uuid::new_v4(), after + REFRESH_WINDOW));
+        assert!(!limiter.0.lock().unwrap().users.contains_key("a"));
+    }
+
+    #[test]
+    fn auth_cache_expiry() {
+        let cache = AuthCache::default();
+        let start = Instant::now();
+        cache.insert_at("hunter22", "$2b$hash", start);
+        assert!(cache.verified_at("hunter22", "$2b$hash", start));
+        assert!(!cache.verified_at("hunter2", "$2b$hash", start));
+        // The password was changed
+        assert!(!cache.verified_at("hunter22", "$2b$other", start));
+
+        let later = start + AUTH_CACHE_TTL;
+        assert!(!cache.verified_at("hunter22", "$2b$hash", later));
+        cache.insert_at("other", "$2b$hash", later);
+        assert_eq!(cache.0.lock().unwrap().len(), 1);
+    }
+}
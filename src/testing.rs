@@ -0,0 +1,262 @@
+// Fixtures shared by tests.
+
+use crate::{
+    db::{
+        self,
+        articles::{
+            self, Article, ArticleAuthors, ArticleContent, ArticleEnclosures,
+            ArticleLinks, ContentType,
+        },
+        sources::{self, Source, SourceData},
+        tags, tokens,
+        users::{self, User},
+        DbConn,
+    },
+    sanitize,
+    setup_rocket::setup_rocket,
+    sources::jsonfeed::JSONFeed,
+};
+use bcrypt::{hash, DEFAULT_COST};
+use diesel::pg::PgConnection;
+use rocket::{
+    http::{ContentType as HttpContentType, Header},
+    local::{Client, LocalResponse},
+};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+};
+use uuid::Uuid;
+
+/// Plain text that looks like markup, & how it's served as HTML.
+pub const MARKUP_TEXT: &str = "<script>alert(1)</script>";
+pub const MARKUP_TEXT_HTML: &str = "&lt;script&gt;alert(1)&lt;/script&gt;";
+
+/// A connection to the database named by `DATABASE_URL`, which may be set
+/// in `.env`.
+pub fn conn() -> DbConn {
+    dotenv::dotenv().ok();
+    DbConn(db::init_pool().get().unwrap())
+}
+
+/// A client for the web server, as `setup_rocket` builds it.
+pub fn client() -> Client {
+    Client::new(setup_rocket()).expect("valid rocket instance")
+}
+
+/// A response's JSON body.
+pub fn json(response: &mut LocalResponse) -> Value {
+    serde_json::from_str(&response.body_string().unwrap()).unwrap()
+}
+
+/// An empty article from a new source, to fill in with `..article()`.
+pub fn article() -> Article {
+    Article {
+        id: Uuid::new_v4(),
+        title: None,
+        published: None,
+        source_info: serde_json::json!([]),
+        summary: None,
+        content: ArticleContent::new(ContentType::Html, None),
+        rights: None,
+        links: ArticleLinks::default(),
+        authors: ArticleAuthors::default(),
+        categories: serde_json::json!([]),
+        comments_url: None,
+        extensions: serde_json::json!({}),
+        source: Uuid::new_v4(),
+        id_from_source: None,
+        raw_summary: None,
+        raw_content: None,
+        enclosures: ArticleEnclosures::default(),
+        thumbnail: None,
+        duration: None,
+        full_content: None,
+    }
+}
+
+/// A stand-in source, sending `responses` (without their
+/// `Content-Length`) one per request. Returns its URL.
+pub fn serve(responses: &[&str]) -> String {
+    serve_recording(responses).0
+}
+
+/// `serve`, also returning each request's body once it's been read.
+pub fn serve_recording(
+    responses: &[&str],
+) -> (String, mpsc::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let responses: Vec<String> =
+        responses.iter().map(|r| r.to_string()).collect();
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for response in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let line = line.trim().to_lowercase();
+                if line.is_empty() {
+                    break;
+                }
+                if let Some(value) = line.strip_prefix("content-length:") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut request_body = vec![0; length];
+            reader.read_exact(&mut request_body).unwrap();
+            let (head, body) =
+                response.split_at(response.find("\r\n\r\n").unwrap());
+            let body = &body[4..];
+            write!(
+                reader.get_mut(),
+                "{}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                head,
+                body.len(),
+                body
+            )
+            .unwrap();
+            // Callers that only want `serve` have dropped the receiver
+            tx.send(request_body).ok();
+        }
+    });
+    (url, rx)
+}
+
+/// A new user, with one source holding the articles from
+/// `test_data/test_jsonfeed.json`. Returns the username & source id.
+pub fn seed_user(
+    prefix: &str,
+    password: &str,
+    conn: &PgConnection,
+) -> (String, Uuid) {
+    let username = format!("{}-{}", prefix, Uuid::new_v4().to_simple());
+    users::insert(
+        User {
+            username: username.clone(),
+            password: hash(password, DEFAULT_COST).unwrap(),
+            search_language: users::DEFAULT_SEARCH_LANGUAGE.into(),
+            fever_api_key: None,
+        },
+        conn,
+    )
+    .unwrap();
+
+    let source = Uuid::new_v4();
+    let feed = JSONFeed::new("https://example.org/feed.json".into(), source);
+    let parsed = feed
+        .parse(&fs::read("test_data/test_jsonfeed.json").unwrap())
+        .unwrap();
+    let new_source = insert_source(
+        source,
+        "Example Blog",
+        SourceData::JSONFeed(feed),
+        &username,
+        conn,
+    );
+    sources::update(
+        &Source {
+            site_link: parsed.link,
+            ..new_source
+        },
+        conn,
+    )
+    .unwrap();
+    for article in parsed.articles {
+        articles::insert(article, conn).unwrap();
+    }
+    (username, source)
+}
+
+/// Store an article in `source` from a JSON Feed item with `MARKUP_TEXT`
+/// as its `content_text`, the way fetching would.
+pub fn insert_markup_text(source: Uuid, conn: &PgConnection) -> Article {
+    let url = "https://example.org/feed.json";
+    let feed = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": "Example Blog",
+        "items": [{
+            "id": "markup",
+            "content_text": MARKUP_TEXT,
+            "date_published": "2020-08-21T00:00:00Z",
+        }],
+    });
+    let mut article = JSONFeed::new(url.into(), source)
+        .parse(feed.to_string().as_bytes())
+        .unwrap()
+        .articles
+        .remove(0);
+    sanitize::sanitize_article(&mut article, url, ContentType::Text);
+    articles::insert(article, conn).unwrap()
+}
+
+/// Save a source of `username`'s, reading `data` made for the source id
+/// `id`.
+pub fn insert_source(
+    id: Uuid,
+    title: &str,
+    data: SourceData,
+    username: &str,
+    conn: &PgConnection,
+) -> Source {
+    sources::insert(
+        Source::new(
+            Some(id),
+            title.into(),
+            serde_json::to_value(data).unwrap(),
+            "".into(),
+            username.to_string(),
+        ),
+        conn,
+    )
+    .unwrap()
+}
+
+/// Log in to the v1 API, returning the `Authorization` header to send.
+pub fn login(
+    client: &Client,
+    username: &str,
+    password: &str,
+) -> Header<'static> {
+    let mut response = client
+        .post("/api/v1/user/login")
+        .header(HttpContentType::JSON)
+        .body(
+            json!({
+                "username": username,
+                "password": password,
+                "persistent": false,
+            })
+            .to_string(),
+        )
+        .dispatch();
+    let login = json(&mut response);
+    Header::new(
+        "Authorization",
+        format!(
+            "Bearer {}",
+            login["contents"]["api_token"].as_str().unwrap()
+        ),
+    )
+}
+
+/// Delete a user from `seed_user`, along with everything of theirs.
+pub fn remove_user(username: String, conn: &PgConnection) {
+    for source in sources::all_from_user(username.clone(), conn).unwrap() {
+        sources::delete(source.id, conn).unwrap();
+    }
+    for tag in tags::all_from_user(username.clone(), conn).unwrap() {
+        tags::delete(tag.id, conn).unwrap();
+    }
+    for token in tokens::all_for_user(username.clone(), conn).unwrap() {
+        tokens::delete(token.id, conn).unwrap();
+    }
+    users::delete(username, conn).unwrap();
+}
@@ -1,5 +1,5 @@
 // Custom date + time type
-// Fulfills serde + diesel traits
+// Fulfills serde + diesel + rocket form traits
 
 use diesel::{
     deserialize::{self, FromSql},
@@ -7,6 +7,7 @@ use diesel::{
     serialize::{self, Output, ToSql},
     sql_types, *,
 };
+use rocket::{http::RawStr, request::FromFormValue};
 use serde::{
     de, de::Visitor, Deserialize, Deserializer, Serialize, Serializer,
 };
@@ -14,9 +15,12 @@ use std::{
     fmt,
     io::Write,
     ops::{Add, Sub},
+    str::FromStr,
 };
 
-#[derive(Debug, AsExpression, FromSqlRow, PartialEq, Clone, Copy)]
+#[derive(
+    Debug, AsExpression, FromSqlRow, PartialEq, PartialOrd, Clone, Copy,
+)]
 #[sql_type = "sql_types::Timestamp"]
 pub struct Timestamp(pub time::Timespec);
 
@@ -65,10 +69,18 @@ impl<'de> Visitor<'de> for TimestampVisitor {
     where
         E: de::Error,
     {
+        value.parse().map_err(E::custom)
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
         let datetime =
             match rfc822_sanitizer::parse_from_rfc2822_with_fallback(value) {
                 Ok(dt) => dt,
-                Err(e) => return Err(E::custom(format!("{}", e))),
+                Err(e) => return Err(format!("{}", e)),
             };
 
         Ok(Timestamp(time::Timespec {
@@ -78,6 +90,19 @@ impl<'de> Visitor<'de> for TimestampVisitor {
     }
 }
 
+/// Allows rfc822 timestamps as (url-encoded) query parameters.
+impl<'v> FromFormValue<'v> for Timestamp {
+    type Error = &'v RawStr;
+
+    fn from_form_value(form_value: &'v RawStr) -> Result<Self, Self::Error> {
+        form_value
+            .url_decode()
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .ok_or(form_value)
+    }
+}
+
 impl<'de> Deserialize<'de> for Timestamp {
     fn deserialize<D>(deserializer: D) -> Result<Timestamp, D::Error>
     where
@@ -0,0 +1,180 @@
+// Subscribing to WebSub hubs, so feeds that use them push new content
+// instead of waiting to be polled. See https://www.w3.org/TR/websub/
+
+use crate::{
+    db::{
+        self, websub_subscriptions, websub_subscriptions::WebSubSubscription,
+    },
+    sources::http,
+    timestamp::Timestamp,
+    Result,
+};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Sha256, Sha384, Sha512};
+use std::env;
+use uuid::Uuid;
+
+/// How long to ask hubs to push for, before the subscription's renewed.
+pub const LEASE_SECONDS: i64 = 7 * 24 * 60 * 60;
+/// The longest lease a hub can grant.
+pub const MAX_LEASE_SECONDS: i64 = 4 * LEASE_SECONDS;
+/// How long hubs have to verify a subscription request, before it's retried.
+pub const VERIFY_SECONDS: i64 = 60 * 60;
+
+/// Where hubs send verification requests & pushed content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSubConfig {
+    /// This server's public URL, ex: `https://example.com`
+    pub base_url: String,
+}
+
+impl WebSubConfig {
+    /// Read the server's public URL from `WEBSUB_CALLBACK_URL`. Without it,
+    /// hubs can't reach the server, so sources are only polled.
+    pub fn from_env() -> Option<WebSubConfig> {
+        env::var("WEBSUB_CALLBACK_URL")
+            .ok()
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty())
+            .map(|base_url| WebSubConfig { base_url })
+    }
+
+    pub fn callback(&self, source: Uuid) -> String {
+        format!("{}/api/websub/{}", self.base_url, source)
+    }
+}
+
+/// Ask the hub to start (or keep) pushing the subscription's topic. The hub
+/// confirms by calling back, see `api::websub`.
+pub fn subscribe(
+    subscription: &WebSubSubscription,
+    config: &WebSubConfig,
+) -> Result<()> {
+    let callback = config.callback(subscription.source);
+    let lease_seconds = LEASE_SECONDS.to_string();
+    http::post_form(
+        &subscription.hub,
+        &[
+            ("hub.mode", "subscribe"),
+            ("hub.topic", subscription.topic.as_str()),
+            ("hub.callback", callback.as_str()),
+            ("hub.secret", subscription.secret.as_str()),
+            ("hub.lease_seconds", lease_seconds.as_str()),
+        ],
+    )
+}
+
+/// Request new subscriptions, retry those the hub never verified, & renew
+/// those about to expire.
+pub fn subscribe_due(pool: &db::Pool, config: &WebSubConfig) -> Result<()> {
+    let conn = db::DbConn(pool.get()?);
+    let now = Timestamp::now();
+    let due = websub_subscriptions::all_due(
+        now - time::Duration::seconds(VERIFY_SECONDS),
+        now + time::Duration::days(1),
+        &conn,
+    )?;
+    for mut subscription in due {
+        subscription.requested_at = Some(now);
+        websub_subscriptions::update(&subscription, &conn)?;
+        if let Err(e) = subscribe(&subscription, config) {
+            log::error!(
+                "Subscribing to hub {} for source {} failed: {}",
+                subscription.hub,
+                subscription.source,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Whether `signature` (an `X-Hub-Signature` header, ex: `sha256=<hex>`) is
+/// a valid HMAC of `body`.
+pub fn valid_signature(secret: &str, signature: &str, body: &[u8]) -> bool {
+    let (algorithm, expected) = match signature.find('=') {
+        Some(i) => (&signature[..i], &signature[i + 1..]),
+        None => return false,
+    };
+    let expected = match from_hex(expected) {
+        Some(expected) => expected,
+        None => return false,
+    };
+    macro_rules! verify {
+        ($digest:ty) => {
+            match Hmac::<$digest>::new_varkey(secret.as_bytes()) {
+                Ok(mut mac) => {
+                    mac.input(body);
+                    mac.verify(&expected).is_ok()
+                }
+                Err(_) => false,
+            }
+        };
+    }
+    match algorithm {
+        "sha1" => verify!(Sha1),
+        "sha256" => verify!(Sha256),
+        "sha384" => verify!(Sha384),
+        "sha512" => verify!(Sha512),
+        _ => false,
+    }
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    #[test]
+    fn signatures() {
+        assert!(valid_signature(
+            "key",
+            "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+            BODY
+        ));
+        assert!(valid_signature(
+            "key",
+            "sha256=f7bc83f430538424b13298e6aa6fb143\
+             ef4d59a14946175997479dbc2d1a3cd8",
+            BODY
+        ));
+        // Wrong secret
+        assert!(!valid_signature(
+            "other",
+            "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+            BODY
+        ));
+        // Unknown algorithm, or not hex
+        assert!(!valid_signature(
+            "key",
+            "md5=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9",
+            BODY
+        ));
+        assert!(!valid_signature("key", "sha1=nothex", BODY));
+        assert!(!valid_signature("key", "", BODY));
+    }
+
+    #[test]
+    fn callback_url() {
+        let config = WebSubConfig {
+            base_url: "https://example.com".to_string(),
+        };
+        let source = Uuid::nil();
+        assert_eq!(
+            config.callback(source),
+            format!("https://example.com/api/websub/{}", source)
+        );
+    }
+}